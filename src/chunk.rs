@@ -1,6 +1,6 @@
 use std::vec;
 
-use crate::{code::OpCode, error::{TokenCell, TokenTriple}, value::Value};
+use crate::{code::OpCode, error::{TokenCell, VariableName}, value::Value};
 use gc_arena::{Collect, Gc};
 
 // TODO benchmark/compare to using a manually resized array
@@ -10,15 +10,32 @@ pub struct Chunk<'chnk> {
     pub code: Vec<OpCode>,
     constants: Vec<Value<'chnk>>, //TODO VALUE ARRAY typedef faster?
     locations: Vec<(usize, usize)>,
+    /** named locals and the instructions they're in scope for, only read to name values in errors */
+    local_names: Vec<LocalName>,
+    /** name of the variable behind each upvalue this function reads */
+    upvalue_names: Vec<Option<String>>,
     valid: bool,
 }
 
+/** debug info for a named local, the slot holds it from start until end */
+#[derive(Collect)]
+#[collect(require_static)]
+struct LocalName {
+    name: String,
+    slot: u8,
+    start: usize,
+    /** usize::MAX while it's still in scope */
+    end: usize,
+}
+
 impl<'chnk> Chunk<'chnk> {
     pub fn new() -> Self {
         Self {
             code: vec![],
             constants: vec![],
             locations: vec![],
+            local_names: vec![],
+            upvalue_names: vec![],
             valid: true,
         }
     }
@@ -53,6 +70,108 @@ impl<'chnk> Chunk<'chnk> {
         false
     }
 
+    /** location of the instruction at index, the last one if we're past the end */
+    pub fn get_location(&self, index: usize) -> TokenCell {
+        self.locations
+            .get(index)
+            .or(self.locations.last())
+            .copied()
+            .unwrap_or((0, 0))
+    }
+
+    /** a named local starts being held by slot at the next instruction, returns a handle to end it's scope with */
+    pub fn begin_local_name(&mut self, name: String, slot: u8) -> usize {
+        self.local_names.push(LocalName {
+            name,
+            slot,
+            start: self.code.len(),
+            end: usize::MAX,
+        });
+        self.local_names.len() - 1
+    }
+
+    /** the local stops being held by it's slot from the next instruction */
+    pub fn end_local_name(&mut self, handle: usize) {
+        if let Some(l) = self.local_names.get_mut(handle) {
+            l.end = self.code.len();
+        }
+    }
+
+    /** name of the local held by slot at the instruction index */
+    pub fn local_name(&self, slot: u8, index: usize) -> Option<&str> {
+        self.local_names
+            .iter()
+            .rev()
+            .find(|l| l.slot == slot && l.start <= index && index < l.end)
+            .map(|l| l.name.as_str())
+    }
+
+    pub fn set_upvalue_name(&mut self, index: u8, name: &str) {
+        let index = index as usize;
+        if self.upvalue_names.len() <= index {
+            self.upvalue_names.resize(index + 1, None);
+        }
+        self.upvalue_names[index] = Some(name.to_string());
+    }
+
+    pub fn upvalue_name(&self, index: u8) -> Option<&str> {
+        self.upvalue_names.get(index as usize)?.as_deref()
+    }
+
+    /** the instruction that left the value `depth` below the top for the instruction at index, and
+     * which of it's results that was counting down from the top. Gives up at anything we can't follow
+     * or when a jump lands in between */
+    fn operand_source(&self, index: usize, mut depth: usize) -> Option<(usize, usize)> {
+        for j in (0..index).rev() {
+            let (pops, pushes) = stack_effect(&self.code[j])?;
+            if depth < pushes {
+                let jumped = self
+                    .code
+                    .iter()
+                    .enumerate()
+                    .any(|(k, op)| jump_target(k, op).is_some_and(|t| t > j && t <= index));
+                return if jumped { None } else { Some((j, depth)) };
+            }
+            depth = depth + pops - pushes;
+        }
+        None
+    }
+
+    /** name the variable the value `depth` below the top was read from by the time the instruction at index runs */
+    pub fn operand_name(&self, index: usize, depth: usize) -> Option<VariableName> {
+        let (j, result) = self.operand_source(index, depth)?;
+        match &self.code[j] {
+            OpCode::GET_GLOBAL { constant } => match self.get_constant(*constant) {
+                Value::String(s) => Some(VariableName::Global(s.clone())),
+                _ => None,
+            },
+            OpCode::GET_LOCAL { index } => self
+                .local_name(*index, j)
+                .map(|s| VariableName::Local(s.to_string())),
+            OpCode::GET_UPVALUE { index } => self
+                .upvalue_name(*index)
+                .map(|s| VariableName::Upvalue(s.to_string())),
+            OpCode::TABLE_GET { .. } => Some(VariableName::Field(self.key_name(j))),
+            // the method sits below the object it was looked up on
+            OpCode::GET_METHOD if result == 1 => Some(VariableName::Method(self.key_name(j))),
+            _ => None,
+        }
+    }
+
+    /** the key the instruction at index reads with when it's a string constant, otherwise `?` */
+    fn key_name(&self, index: usize) -> String {
+        match self.operand_source(index, 0) {
+            Some((j, _)) => match &self.code[j] {
+                OpCode::CONSTANT { constant } => match self.get_constant(*constant) {
+                    Value::String(s) => s.clone(),
+                    _ => "?".to_string(),
+                },
+                _ => "?".to_string(),
+            },
+            None => "?".to_string(),
+        }
+    }
+
     pub fn read_last_code(&self) -> &OpCode {
         self.code.last().unwrap()
     }
@@ -160,5 +279,57 @@ impl<'chnk> Chunk<'chnk> {
         self.code.clear();
         self.constants.clear();
         self.locations.clear();
+        self.local_names.clear();
+        self.upvalue_names.clear();
+    }
+}
+
+/** values an instruction takes off the stack and how many it leaves, None for anything we don't follow back through */
+fn stack_effect(op: &OpCode) -> Option<(usize, usize)> {
+    match op {
+        OpCode::CONSTANT { .. }
+        | OpCode::GET_GLOBAL { .. }
+        | OpCode::GET_LOCAL { .. }
+        | OpCode::GET_UPVALUE { .. }
+        | OpCode::NIL
+        | OpCode::TRUE
+        | OpCode::FALSE => Some((0, 1)),
+        OpCode::TABLE_GET { depth } => Some((*depth as usize + 1, 1)),
+        OpCode::GET_METHOD => Some((2, 2)),
+        OpCode::NEGATE | OpCode::NOT | OpCode::LENGTH | OpCode::BIT_NOT => Some((1, 1)),
+        OpCode::ADD
+        | OpCode::SUB
+        | OpCode::MULTIPLY
+        | OpCode::DIVIDE
+        | OpCode::MODULUS
+        | OpCode::EXPONENT
+        | OpCode::FLOOR_DIVIDE
+        | OpCode::BIT_AND
+        | OpCode::BIT_OR
+        | OpCode::BIT_XOR
+        | OpCode::SHIFT_LEFT
+        | OpCode::SHIFT_RIGHT
+        | OpCode::CONCAT
+        | OpCode::EQUAL
+        | OpCode::NOT_EQUAL
+        | OpCode::LESS
+        | OpCode::LESS_EQUAL
+        | OpCode::GREATER
+        | OpCode::GREATER_EQUAL => Some((2, 1)),
+        _ => None,
+    }
+}
+
+/** the instruction a jump at index lands on */
+fn jump_target(index: usize, op: &OpCode) -> Option<usize> {
+    match op {
+        OpCode::GOTO_IF_FALSE(n)
+        | OpCode::GOTO_IF_TRUE(n)
+        | OpCode::POP_AND_GOTO_IF_FALSE(n)
+        | OpCode::FORWARD(n)
+        | OpCode::FOR_NUMERIC(n)
+        | OpCode::FOR_GENERIC(_, n) => Some(index + *n as usize + 1),
+        OpCode::REWIND(n) => Some((index + 1).saturating_sub(*n as usize)),
+        _ => None,
     }
 }
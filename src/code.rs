@@ -43,16 +43,39 @@ pub enum OpCode {
     POP_AND_GOTO_IF_FALSE(u16),
     /** Compares 2nd(end) and 3rd(iter) value on stack, if greater then forward by X, otherwise push 3rd(iter) on to new stack  */
     FOR_NUMERIC(u16),
+    /** Checks the first of N values returned by the iterator function, if nil then pop the N values and forward by X, otherwise copy it into the control slot just below them */
+    FOR_GENERIC(u8, u16),
     FORWARD(u16),
     REWIND(u16),
     RETURN(u8),
+    /// Return n values plus however many the preceding open instruction left on the stack
+    RETURN_OPEN(u8),
     POP,
     POPS(u8),
+    /// Push copies of the top n values in the same order, lets a table path be read and then written
+    DUPLICATE(u8),
     CLOSE_UPVALUES(u8),
+    /// Register the local at index as to-be-closed, it's value must have a `__close` metamethod or be nil or false
+    TO_BE_CLOSED {
+        index: u8,
+    },
+    /// Call `__close` on every to-be-closed local of the frame at or above index, last registered first
+    CLOSE {
+        index: u8,
+    },
     ADD,
     SUB,
     MULTIPLY,
     DIVIDE,
+    MODULUS,
+    EXPONENT,
+    FLOOR_DIVIDE,
+    BIT_AND,
+    BIT_OR,
+    BIT_XOR,
+    BIT_NOT,
+    SHIFT_LEFT,
+    SHIFT_RIGHT,
     NEGATE,
     CONCAT,
     NOT,
@@ -72,6 +95,14 @@ pub enum OpCode {
     META(u8),
     /// Call function with n parameters, and r count of assignments desired
      CALL(u8,u8),
+    /// Same as CALL but the last argument is open, n fixed parameters plus however many the preceding instruction left
+    CALL_OPEN(u8, u8),
+    /// Call function with n parameters in place of the current frame, whatever it returns goes straight to our caller
+    TAIL_CALL(u8),
+    /// Same as TAIL_CALL but the last argument is open
+    TAIL_CALL_OPEN(u8),
+    /// Push n varargs of the current frame padded with nils, or all of them when n is 0 which leaves the call open
+    VARARGS(u8),
     /// tell the VM we expect n values for next assignment before resetting, otherwise 1
     NEED(u8),
     REGISTER_UPVALUE {
@@ -89,6 +120,8 @@ pub enum OpCode {
         offset: u8,
     },
     TABLE_BUILD(u8),
+    /// Same as TABLE_BUILD but the last value is open
+    TABLE_BUILD_OPEN(u8),
     TABLE_GET {
         depth: u8,
     },
@@ -98,10 +131,17 @@ pub enum OpCode {
     TABLE_GET_FROM {
         index: u8,
     },
+    /** Method lookup for `obj:name()`, replaces [obj, name] with [method, obj] so obj becomes the first argument */
+    GET_METHOD,
     /** depth indicates how many index contansts are on the stack. Statement does not need pop */
     TABLE_SET {
         depth: u8,
     },
+    /** Store the popped value through the table path sitting offset values below the top. The path stays on the stack, used when a multiple assignment holds several paths at once */
+    TABLE_ASSIGN {
+        offset: u8,
+        depth: u8,
+    },
     // TABLE_SET_BY_CONSTANT {
     //     constant: u8,
     // },
@@ -115,6 +155,10 @@ impl Display for OpCode {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
             Self::CALL(i,m) => write!(f, "OP_CALL({},{})", i,m),
+            Self::CALL_OPEN(i, m) => write!(f, "OP_CALL_OPEN({}+,{})", i, m),
+            Self::TAIL_CALL(i) => write!(f, "OP_TAIL_CALL({})", i),
+            Self::TAIL_CALL_OPEN(i) => write!(f, "OP_TAIL_CALL_OPEN({}+)", i),
+            Self::VARARGS(n) => write!(f, "OP_VARARGS x{}", n),
             Self::REGISTER_UPVALUE {
                 index: i,
                 neighboring: n,
@@ -140,6 +184,9 @@ impl Display for OpCode {
             Self::FOR_NUMERIC(offset) => {
                 write!(f, "OP_FOR_NUMERIC {}", offset)
             }
+            Self::FOR_GENERIC(n, offset) => {
+                write!(f, "OP_FOR_GENERIC x{} {}", n, offset)
+            }
             Self::DEFINE_GLOBAL { constant } => {
                 write!(f, "OP_DEFINE_GLOBAL {}", constant)
             }
@@ -151,13 +198,17 @@ impl Display for OpCode {
             }
             Self::NEED(u) => write!(f, "OP_NEED {}",u),
             Self::RETURN(u) => write!(f, "OP_RETURNx{}",u),
+            Self::RETURN_OPEN(u) => write!(f, "OP_RETURN_OPENx{}+", u),
             Self::POP => write!(f, "OP_POP"),
+            Self::DUPLICATE(n) => write!(f, "OP_DUPLICATEx{}", n),
             Self::POPS(n) => {
                 write!(f, "OP_POPx{}", n)
             }
             Self::CLOSE_UPVALUES(n) => {
                 write!(f, "OP_CLOSE_UPVALUEx{}", n)
             }
+            Self::TO_BE_CLOSED { index } => write!(f, "OP_TO_BE_CLOSED {}", index),
+            Self::CLOSE { index } => write!(f, "OP_CLOSE {}", index),
             Self::CONSTANT { constant } => {
                 write!(f, "OP_CONSTANT {}", constant)
             }
@@ -176,6 +227,15 @@ impl Display for OpCode {
             Self::DIVIDE => {
                 write!(f, "OP_DIVIDE")
             }
+            Self::MODULUS => write!(f, "OP_MODULUS"),
+            Self::EXPONENT => write!(f, "OP_EXPONENT"),
+            Self::FLOOR_DIVIDE => write!(f, "OP_FLOOR_DIVIDE"),
+            Self::BIT_AND => write!(f, "OP_BIT_AND"),
+            Self::BIT_OR => write!(f, "OP_BIT_OR"),
+            Self::BIT_XOR => write!(f, "OP_BIT_XOR"),
+            Self::BIT_NOT => write!(f, "OP_BIT_NOT"),
+            Self::SHIFT_LEFT => write!(f, "OP_SHIFT_LEFT"),
+            Self::SHIFT_RIGHT => write!(f, "OP_SHIFT_RIGHT"),
             Self::NEGATE => write!(f, "OP_NEGATE"),
             Self::CONCAT => write!(f, "OP_CONCAT"),
             Self::LITERAL { dest, literal } => {
@@ -207,6 +267,7 @@ impl Display for OpCode {
             Self::NEW_TABLE => write!(f, "OP_NEW_TABLE"),
             Self::TABLE_INSERT { offset } => write!(f, "OP_TABLE_INSERT @{}", offset),
             Self::TABLE_BUILD(u) => write!(f, "OP_TABLE_BUILD [;{}]", u),
+            Self::TABLE_BUILD_OPEN(u) => write!(f, "OP_TABLE_BUILD_OPEN [;{}+]", u),
             Self::TABLE_GET { depth } => write!(f, "OP_TABLE_GET {}[]", depth),
             Self::TABLE_GET_BY_CONSTANT { constant } => {
                 write!(f, "OP_TABLE_GET_BY_CONSTANT {}", constant)
@@ -214,7 +275,11 @@ impl Display for OpCode {
             Self::TABLE_GET_FROM { index } => {
                 write!(f, "OP_TABLE_GET_FROM {}", index)
             }
+            Self::GET_METHOD => write!(f, "OP_GET_METHOD"),
             Self::TABLE_SET { depth } => write!(f, "OP_TABLE_SET {}[]", depth),
+            Self::TABLE_ASSIGN { offset, depth } => {
+                write!(f, "OP_TABLE_ASSIGN {} {}[]", offset, depth)
+            }
             // Self::TABLE_SET_BY_CONSTANT { constant } => {
             //     write!(f, "OP_TABLE_SET_BY_CONSTANT {}", constant)
             // }
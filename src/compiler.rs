@@ -2,7 +2,7 @@ use std::{
     cmp::Ordering, collections::HashMap, fmt::{Display, Formatter}, iter::Peekable, println, vec
 };
 
-use gc_arena::{Gc, Mutation};
+use gc_arena::{Arena, Gc, Mutation, Rootable};
 
 use crate::{
     code::OpCode,
@@ -10,6 +10,7 @@ use crate::{
     function::FunctionObject,
     lexer::Lexer,
     token::{Operator, Token},
+    types::{FunctionType, Type},
     value::Value,
 };
 
@@ -156,10 +157,15 @@ enum Precedence {
     And,        // and
     Equality,   // == ~= !=
     Comparison, // < > <= >=
+    BitOr,      // |
+    BitXor,     // ~
+    BitAnd,     // &
+    Shift,      // << >>
     Concat,     // ..
     Term,       // + -
-    Factor,     // * /
+    Factor,     // * / // %
     Unary,      // ~ - !
+    Exponent,   // ^
     Call,       // . ()
     Primary,
 }
@@ -174,6 +180,7 @@ pub struct LanguageFlags {
     pub implicit_returns: bool,
     pub arrow_functions: bool,
     pub bang_operator: bool,
+    pub interpolation: bool,
 }
 
 impl Default for LanguageFlags {
@@ -182,6 +189,7 @@ impl Default for LanguageFlags {
             implicit_returns: false,
             arrow_functions: false,
             bang_operator: false,
+            interpolation: false,
         }
     }
 }
@@ -194,11 +202,16 @@ impl Precedence {
             Precedence::Or => Precedence::And,
             Precedence::And => Precedence::Equality,
             Precedence::Equality => Precedence::Comparison,
-            Precedence::Comparison => Precedence::Concat,
+            Precedence::Comparison => Precedence::BitOr,
+            Precedence::BitOr => Precedence::BitXor,
+            Precedence::BitXor => Precedence::BitAnd,
+            Precedence::BitAnd => Precedence::Shift,
+            Precedence::Shift => Precedence::Concat,
             Precedence::Concat => Precedence::Term,
             Precedence::Term => Precedence::Factor,
             Precedence::Factor => Precedence::Unary,
-            Precedence::Unary => Precedence::Call,
+            Precedence::Unary => Precedence::Exponent,
+            Precedence::Exponent => Precedence::Call,
             Precedence::Call => Precedence::Primary,
             Precedence::Primary => Precedence::Primary, // TODO over?
         }
@@ -214,19 +227,32 @@ impl Display for Precedence {
             Precedence::And => write!(f, "And"),
             Precedence::Equality => write!(f, "Equality"),
             Precedence::Comparison => write!(f, "Comparison"),
+            Precedence::BitOr => write!(f, "BitOr"),
+            Precedence::BitXor => write!(f, "BitXor"),
+            Precedence::BitAnd => write!(f, "BitAnd"),
+            Precedence::Shift => write!(f, "Shift"),
             Precedence::Concat => write!(f, "Concat"),
             Precedence::Term => write!(f, "Term"),
             Precedence::Factor => write!(f, "Factor"),
             Precedence::Unary => write!(f, "Unary"),
+            Precedence::Exponent => write!(f, "Exponent"),
             Precedence::Call => write!(f, "Call"),
             Precedence::Primary => write!(f, "Primary"),
         }
     }
 }
 
+type Rule = for<'c> fn(
+    &mut Compiler,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    it: &mut Peekable<Lexer>,
+    can_assign: bool,
+) -> Catch;
+
 struct ParseRule {
-    prefix: fn(&mut Compiler, f: FnRef, it: &mut Peekable<Lexer>, can_assign: bool) -> Catch,
-    infix: fn(&mut Compiler, f: FnRef, it: &mut Peekable<Lexer>, can_assign: bool) -> Catch,
+    prefix: Rule,
+    infix: Rule,
     precedence: Precedence,
 }
 
@@ -237,6 +263,8 @@ const WORD_MAP: [&str; 7] = [
 // type LSPIndent =(usize, usize);
 // start, length, type
 type LSPFormatMark = (usize, usize, u8);
+// line, column, severity (1 error, 2 warning), message
+type LSPDiagnostic = (usize, usize, u8, String);
 
 #[cfg(feature = "wasm")]
 #[derive(Serialize, Deserialize)]
@@ -245,12 +273,14 @@ pub struct LanguageServerOutput<'c> {
     legend: [&'c str; 7],
     map: Vec<LSPFormatMark>,
     indented: String, // indents: Vec<IndentMark>,
+    diagnostics: Vec<LSPDiagnostic>,
 }
 #[cfg(not(feature = "wasm"))]
 pub struct LanguageServerOutput<'c> {
     legend: [&'c str; 7],
     map: Vec<LSPFormatMark>,
     indented: String, // indents: Vec<IndentMark>,
+    diagnostics: Vec<LSPDiagnostic>,
 }
 
 impl Display for LanguageServerOutput<'_> {
@@ -259,7 +289,11 @@ impl Display for LanguageServerOutput<'_> {
         for (s, l, t) in self.map.iter() {
             write!(f, "({} {} {}),", s, l, t)?;
         }
-        writeln!(f, "{}", self.indented)
+        writeln!(f, "{}", self.indented)?;
+        for (line, column, severity, message) in self.diagnostics.iter() {
+            writeln!(f, "{}:{} [{}] {}", line, column, severity, message)?;
+        }
+        Ok(())
     }
 }
 
@@ -271,6 +305,81 @@ struct Local {
     /** how many layers deep the local value is nested in a function, with 0 being global (should only happen once to reserve the root func on the stack) */
     functional_depth: usize,
     is_captured: bool,
+    known: VariableType,
+    /** handle to the chunk's debug name for this local, ended when it leaves scope */
+    debug: Option<usize>,
+}
+
+/** what the compiler knows about a variable, the annotation is enforced while the inferred type is only a best guess */
+#[derive(Clone, Default)]
+struct VariableType {
+    /** annotated type, Type::None when declared without one */
+    typing: Type,
+    /** last type assigned that we could follow */
+    inferred: Type,
+    /** parameters and returns when the variable holds a function we compiled */
+    signature: Option<FunctionType>,
+    attribute: Attribute,
+    /** literal a const was initialised with, reads emit it instead of reading the variable */
+    folded: Option<Token>,
+}
+
+/** `<const>` or `<close>` following a declared name, both forbid assignment */
+#[derive(Clone, Copy, PartialEq, Default)]
+enum Attribute {
+    #[default]
+    None,
+    Const,
+    /** the value's `__close` metamethod is called when the variable goes out of scope */
+    Close,
+}
+
+/** a loop we're compiling within, so break knows which locals to drop and where to jump */
+struct LoopScope {
+    /** index into locals where the loop body's locals begin */
+    base: usize,
+    breaks: Vec<PendingBreak>,
+}
+
+/** break reserves room for its POPS and CLOSE_UPVALUES ops followed by a FORWARD, we can only fill them in once the loop ends and we know which locals were captured */
+struct PendingBreak {
+    index: usize,
+    /** captured flag for each local alive at the break, from the loop's base upward */
+    captured: Vec<bool>,
+}
+
+/** a label gotos can reach while the block it's declared in is open */
+struct GotoLabel {
+    /** chunk index the label points at */
+    index: usize,
+    /** how many locals were in scope at the label */
+    locals: usize,
+    depth: usize,
+}
+
+/** goto reserves room for a CLOSE, it's POPS and CLOSE_UPVALUES ops and the jump, we fill them in once the function ends and we know the label and which locals were captured */
+struct PendingGoto {
+    label: String,
+    index: usize,
+    location: TokenCell,
+    /** index into locals of the function's first local, a goto never leaves anything below it */
+    base: usize,
+    /** captured flag for each local alive at the goto, from base upward */
+    captured: Vec<bool>,
+    /** which of those locals are `<close>` */
+    closing: Vec<bool>,
+    /** how many locals from the goto are still alive, lowered as they leave scope */
+    live: usize,
+    /** the deepest scope the goto can still find it's label in, lowered as it's blocks end */
+    depth: usize,
+    /** chunk index and locals count of the label once found */
+    target: Option<(usize, usize)>,
+}
+
+/** one name in a target list holding table paths, a path's table and keys wait on the stack until the values are ready */
+enum AssignTarget {
+    Variable(OpCode, String),
+    Field(u8),
 }
 
 struct UpLocal {
@@ -288,6 +397,8 @@ pub struct Compiler {
     // pub body: FunctionObject<'chnk>,
     pub current_index: usize,
     pub errors: Vec<ErrorTuple>,
+    /** non-fatal problems found while compiling, the chunk still runs */
+    pub warnings: Vec<ErrorTuple>,
     pub valid: bool,
     current: Result<Token, ErrorTuple>,
     current_location: TokenCell,
@@ -300,22 +411,31 @@ pub struct Compiler {
     local_functional_offset: Vec<usize>,
     locals: Vec<Local>,
     local_count: usize,
-    labels: HashMap<String, usize>,
+    /** labels visible from the current block */
+    labels: HashMap<String, GotoLabel>,
+    /** labels closing out their block along with the depth of that block, they're placed once the block's locals are dropped */
+    end_labels: Vec<(String, usize)>,
     // location: (usize, usize),
     // previous: TokenTuple,
     // pre_previous: TokenTuple,
-    pending_gotos: Vec<(String, usize, TokenCell)>,
+    pending_gotos: Vec<PendingGoto>,
     /** hack to flip off a pop when an expression takes on statement properties, used only for := right now */
     override_pop: bool,
     /// flag that a self calling method was used
     self_arg: bool,
+    /// flag that the next function built is a method and takes an implicit self
+    method_self: bool,
     /** language flags for optional features */
     language_flags: LanguageFlags,
     /** tracks if the last statement was an expression for implicit returns */
     last_was_expression: bool,
+    /** tracks if the last statement of a block was a return, a return nested in a block can still fall through */
+    last_was_return: bool,
     // return_count: u8,
     /** tracks the number of values on the stack from comma-separated expressions */
     expression_count: u8,
+    /** chunk size right after an `and`, `or` or parentheses, a call ending there is cut to one value and can't be opened */
+    closed_at: usize,
     /// used for multi var assignment, start small, expand if really necessary
     var_stack: Vec<(OpCode, OpCode)>,
     /// men will do anything to not have to allocate a new vec
@@ -325,6 +445,22 @@ pub struct Compiler {
     // correctly. If we walk our setters all the way to find an assignment (:=) 
     /// can we gather multivars for setters? multivar return or gets must skip this
     can_multivar_set: bool,
+    /** loops within the current function, innermost last */
+    loops: Vec<LoopScope>,
+    /** current function was declared with `...`, the main chunk always is */
+    variadic: bool,
+    /** type of the last expression compiled, Type::None if it can't be known at compile time */
+    expression_type: Type,
+    /** expression_type is inferred from a variable and not guaranteed, mismatches only warn */
+    expression_inferred: bool,
+    /** signature of the last expression if it's a function we know the annotations of */
+    expression_signature: Option<FunctionType>,
+    /** annotated globals, these outlive a single compile like the globals themselves */
+    global_types: HashMap<String, VariableType>,
+    /** annotated return type of the function being compiled */
+    return_type: Type,
+    /** merged type of the return statements seen so far in the function being compiled */
+    inferred_return: Option<Type>,
 }
 
 impl Compiler {
@@ -337,6 +473,7 @@ impl Compiler {
             current_location: (0, 0),
             current_index: 0,
             errors: vec![],
+            warnings: vec![],
             valid: true,
             scope_depth: 0,
             functional_depth: 0,
@@ -346,23 +483,37 @@ impl Compiler {
                 depth: 0,
                 functional_depth: 0,
                 is_captured: false,
+                known: VariableType::default(),
+                debug: None,
             }],
             local_functional_offset: vec![],
             local_offset: vec![],
             local_count: 1,
             labels: HashMap::new(),
+            end_labels: vec![],
             pending_gotos: vec![],
             // location: (0, 0),
             // previous: (Token::Nil, (0, 0)),
             // pre_previous: (Token::Nil, (0, 0)),
             override_pop: false,
             self_arg: false,
+            method_self: false,
             language_flags: LanguageFlags::default(),
             last_was_expression: false,
+            last_was_return: false,
             expression_count: 0,
+            closed_at: 0,
             var_stack: Vec::with_capacity(4),
             var_set_stack: Vec::with_capacity(4),
             can_multivar_set: true,
+            loops: vec![],
+            variadic: true,
+            expression_type: Type::None,
+            expression_inferred: false,
+            expression_signature: None,
+            global_types: HashMap::new(),
+            return_type: Type::None,
+            inferred_return: None,
         }
     }
 
@@ -372,12 +523,18 @@ impl Compiler {
         arrow_functions: bool,
         bang_operator: bool,
     ) -> Compiler {
-        let mut compiler = Self::new();
-        compiler.language_flags = LanguageFlags {
+        Self::with_language_flags(LanguageFlags {
             implicit_returns,
             arrow_functions,
             bang_operator,
-        };
+            ..LanguageFlags::default()
+        })
+    }
+
+    /** Create a new compiler instance with any set of language flags, those added later default to off */
+    pub fn with_language_flags(language_flags: LanguageFlags) -> Compiler {
+        let mut compiler = Self::new();
+        compiler.language_flags = language_flags;
         compiler
     }
 
@@ -412,6 +569,8 @@ impl Compiler {
 
     /** Push error and location on to error stack */
     fn push_error(&mut self, code: ErrorTuple) {
+        // lexer errors arrive here without passing through error_syntax
+        self.valid = false;
         self.errors.push(code);
     }
 
@@ -424,6 +583,21 @@ impl Compiler {
         std::mem::replace(&mut self.errors, vec![])
     }
 
+    /** Return warnings from the last compile */
+    pub fn get_warnings(&self) -> &Vec<ErrorTuple> {
+        &self.warnings
+    }
+
+    /** Push a warning, unlike errors this doesn't invalidate the chunk */
+    fn warn(&mut self, code: SiltError, location: TokenCell) {
+        self.warnings.push(ErrorTuple { code, location });
+    }
+
+    /** Push a warning at the current token location */
+    fn warn_at(&mut self, code: SiltError) {
+        self.warn(code, self.current_location);
+    }
+
     // fn get_chunk(&self,e: &'a mut Emphereal) -> &'chnk Chunk {
     //     &e.body.chunk
     // }
@@ -474,7 +648,14 @@ impl Compiler {
 
     /** Force stack to pop N values without usual niceties, this both emits opcode and drops off the emulated stack locals */
     fn force_stack_pop(&mut self, f: FnRef, n: usize) {
-        self.locals.truncate(self.locals.len() - n);
+        for _ in 0..n {
+            let l = self.locals.pop().unwrap();
+            if let Some(handle) = l.debug {
+                f.chunk.end_local_name(handle);
+            }
+            leave_goto_local(self, self.locals.len(), l.is_captured);
+        }
+        self.local_count -= n;
         self.emit_at(f, OpCode::POPS(n as u8));
     }
 
@@ -555,9 +736,6 @@ impl Compiler {
             f.chunk.write_code(v.1, self.current_location);
         }
     }
-    fn pull_getter(&mut self, f: FnRef) -> OpCode {
-        self.var_stack.first().unwrap().1.clone()
-    }
 
     /** only use after peek */
     // pub fn eat_out(&mut self) -> TokenResult {
@@ -676,6 +854,10 @@ impl Compiler {
             OpCode::FORWARD(_) => self.change_code(f, offset, OpCode::FORWARD(jump as u16)),
             OpCode::REWIND(_) => self.change_code(f, offset, OpCode::REWIND(jump as u16)),
             OpCode::FOR_NUMERIC(_) => self.change_code(f, offset, OpCode::FOR_NUMERIC(jump as u16)),
+            OpCode::FOR_GENERIC(n, _) => {
+                let n = *n;
+                self.change_code(f, offset, OpCode::FOR_GENERIC(n, jump as u16))
+            }
             _ => {
                 return Err(self.error_at(SiltError::ChunkCorrupt));
             }
@@ -692,10 +874,6 @@ impl Compiler {
         self.write_code(f, OpCode::REWIND(jump as u16), self.current_location);
     }
 
-    fn set_label(&mut self, f: FnRef, label: String) {
-        self.labels.insert(label, self.get_chunk_size(f));
-    }
-
     fn identifer_constant(&mut self, f: FnRef, ident: String) -> u8 {
         self.write_identifier(f, ident) as u8
     }
@@ -747,13 +925,24 @@ impl Compiler {
 
         match token {
             Token::OpenParen => rule!(grouping, call, Call),
-            Token::OpenBrace => rule!(tabulate, call_table, None),
+            Token::Colon => rule!(void, method_call, Call),
+            Token::Dot | Token::OpenBracket => rule!(void, call_index, Call),
+            Token::ArrowFunction => rule!(misplaced_arrow, misplaced_arrow, Call),
+            Token::OpenBrace => rule!(tabulate, call_table, Call),
             Token::Assign => rule!(void, void, None),
             Token::Op(op) => match op {
                 Operator::Sub => rule!(unary, binary, Term),
                 Operator::Add => rule!(void, binary, Term),
                 Operator::Multiply => rule!(void, binary, Factor),
                 Operator::Divide => rule!(void, binary, Factor),
+                Operator::FloorDivide => rule!(void, binary, Factor),
+                Operator::Modulus => rule!(void, binary, Factor),
+                Operator::Exponent => rule!(void, exponent, Exponent),
+                Operator::BitOr => rule!(void, binary, BitOr),
+                Operator::Tilde => rule!(unary, binary, BitXor),
+                Operator::BitAnd => rule!(void, binary, BitAnd),
+                Operator::ShiftLeft => rule!(void, binary, Shift),
+                Operator::ShiftRight => rule!(void, binary, Shift),
                 Operator::Not => rule!(unary, void, None),
                 Operator::NotEqual => rule!(void, binary, Equality),
                 Operator::Equal => rule!(void, binary, Equality),
@@ -771,8 +960,10 @@ impl Compiler {
             // Token::OpenBracket => rule!(void, indexer, Call),
             Token::Integer(_) => rule!(integer, void, None),
             Token::Number(_) => rule!(number, void, None),
-            Token::StringLiteral(_) => rule!(string, call_string, None),
+            Token::StringLiteral(_) => rule!(string, call_string, Call),
+            Token::TemplateString(_) | Token::TemplateHead(_) => rule!(template, void, None),
             Token::Nil => rule!(literal, void, None),
+            Token::VarArgs => rule!(varargs, void, None),
             Token::True => rule!(literal, void, None),
             Token::False => rule!(literal, void, None),
             // Token::Bang => rule!(unary, void, None),
@@ -797,21 +988,27 @@ impl Compiler {
             });
         }
         let lexer = Lexer::new(source);
-        let mut body = FunctionObject::new(name, true);
+        let mut body = FunctionObject::new(name.clone(), true);
+        body.chunk_name = name;
         let mut iter = lexer.peekable();
+        self.warnings.clear();
+        self.labels.clear();
+        self.end_labels.clear();
+        self.pending_gotos.clear();
 
         while iter.peek().is_some() {
             match declaration(self, mc, &mut body, &mut iter) {
                 Ok(()) => {}
                 Err(e) => {
                     self.push_error(e);
-                    self.synchronize();
+                    self.synchronize(&mut iter);
                 }
             }
         }
 
         // Handle implicit returns for multiple expressions
-        if self.language_flags.implicit_returns && self.last_was_expression {
+        let implicit = self.language_flags.implicit_returns && self.last_was_expression;
+        if implicit {
             // If we have multiple expressions, keep them all on the stack
             // Otherwise, drop the last POP to keep the single expression
             if self.expression_count <= 1 {
@@ -820,6 +1017,20 @@ impl Compiler {
         } else {
             self.drop_last_if(&mut body, &OpCode::POP);
         }
+        // top level `<close>` locals are the only ones on the stack, RETURN closes them beneath an implicit return but otherwise we clear them so nothing is returned
+        let top_locals = self.locals.len() - 1;
+        if top_locals > 0 && !implicit {
+            if let Some(index) = close_slot(self, 1) {
+                self.emit(&mut body, OpCode::CLOSE { index }, (0, 0));
+            }
+            self.emit(&mut body, OpCode::POPS(top_locals as u8), (0, 0));
+        }
+        let end = self.get_chunk_size(&mut body);
+        resolve_gotos(self, &mut body, end);
+        if top_locals > 0 {
+            self.locals.truncate(1);
+            self.local_count = 1;
+        }
         // self.expression_count we should convert to tuple right? piping to CLI ???
         self.emit(&mut body, OpCode::RETURN(0), (0, 0));
         if !self.valid {
@@ -988,14 +1199,34 @@ impl Compiler {
             indented = source.to_owned();
         }
 
+        let diagnostics = self.diagnose(source);
         LanguageServerOutput {
             legend: WORD_MAP,
             map,
             indented,
+            diagnostics,
         }
     }
 
-    fn synchronize(&mut self) {
+    /** compile in a throwaway arena only to gather the errors and warnings */
+    fn diagnose(&mut self, source: &str) -> Vec<LSPDiagnostic> {
+        let arena = Arena::<Rootable![()]>::new(|_| ());
+        arena.mutate(|mc, _| {
+            self.compile(mc, None, source);
+        });
+        let errors = self.pop_errors().into_iter().map(|e| (e, 1));
+        let warnings = self.warnings.iter().cloned().map(|w| (w, 2));
+        errors
+            .chain(warnings)
+            .map(|(e, severity)| (e.location.0, e.location.1, severity, e.code.to_string()))
+            .collect()
+    }
+
+    fn synchronize(&mut self, it: &mut Peekable<Lexer>) {
+        // a lexer error stays peeked until consumed, drop it or we never make progress
+        if let Some(Err(_)) = it.peek() {
+            it.next();
+        }
         // TODO should we unwind or just dump it all?
         // self.eat();
         // while !self.is_end() {
@@ -1007,9 +1238,10 @@ impl Compiler {
         // }
     }
 
-    fn parse_precedence(
+    fn parse_precedence<'c>(
         &mut self,
-        f: FnRef,
+        mc: &Mutation<'c>,
+        f: FnRef<'_, 'c>,
         it: &mut Peekable<Lexer>,
         precedence: Precedence,
         skip_step: bool,
@@ -1031,7 +1263,11 @@ impl Compiler {
         );
         // if (rule.prefix) != Self::void { // TODO bubble error up if no prefix, call invalid func to bubble?
         let can_assign = precedence <= Precedence::Assignment;
-        (rule.prefix)(self, f, it, can_assign)?;
+        // prefix rules that know what they produce will say so
+        self.expression_type = Type::None;
+        self.expression_inferred = false;
+        self.expression_signature = None;
+        (rule.prefix)(self, mc, f, it, can_assign)?;
 
         loop {
             let c = self.peek_result(it);
@@ -1051,7 +1287,7 @@ impl Compiler {
                 break;
             }
             self.store(it);
-            (rule.infix)(self, f, it, false)?;
+            (rule.infix)(self, mc, f, it, can_assign)?;
         }
 
         // TODO test this with `local b="b" sprint b`
@@ -1089,6 +1325,7 @@ fn declaration<'a, 'c: 'a>(
 
     // Reset expression tracking for each declaration
     this.last_was_expression = false;
+    let is_return = matches!(t, Token::Return);
 
     match t {
         Token::Local => declaration_keyword(this, mc, f, it, true, false)?,
@@ -1099,6 +1336,7 @@ fn declaration<'a, 'c: 'a>(
         }
         _ => statement(this, mc, f, it)?,
     }
+    this.last_was_return = is_return;
     Ok(())
 }
 
@@ -1115,14 +1353,23 @@ fn declaration_keyword<'a, 'c: 'a>(
     let (res, location) = this.pop(it);
     match res? {
         Token::Identifier(ident) => {
-            if this.scope_depth > 0 && local {
+            let attribute = optional_attribute(this, it)?;
+            let declared = VariableType {
+                typing: optional_annotation(this, it)?,
+                attribute,
+                ..Default::default()
+            };
+            if let Token::Comma = this.peek(it)? {
+                multi_declaration(this, mc, f, it, (ident, location, declared), local)?;
+            } else if (this.scope_depth > 0 || attribute == Attribute::Close) && local {
                 //local
                 //TODO should we warn? redefine_behavior(this,ident)?
-                add_local(this, it, ident)?;
-                typing(this, f, it, None)?;
+                // a to-be-closed variable needs a stack slot to be closed from, even at the top level
+                add_local(this, f, it, ident.clone())?;
+                typing(this, mc, f, it, ident, None, declared)?;
             } else {
-                let ident = this.identifer_constant(f, ident);
-                typing(this, f, it, Some((ident, location)))?;
+                let constant = this.identifer_constant(f, ident.clone());
+                typing(this, mc, f, it, ident, Some((constant, location)), declared)?;
             }
         }
         Token::Function => {
@@ -1142,9 +1389,89 @@ fn declaration_keyword<'a, 'c: 'a>(
     Ok(())
 }
 
+/** `local a, b: string, c = explist` the values are balanced to the names, which only come into scope after the expressions. Each value is checked against the annotation of the name it lands on */
+fn multi_declaration<'c>(
+    this: &mut Compiler,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    it: &mut Peekable<Lexer>,
+    first: (String, TokenCell, VariableType),
+    local: bool,
+) -> Catch {
+    devnote!(this it "multi_declaration");
+    let mut names = vec![first];
+    while let Token::Comma = this.peek(it)? {
+        this.eat(it);
+        let (res, location) = this.pop(it);
+        match res? {
+            Token::Identifier(ident) => {
+                let attribute = optional_attribute(this, it)?;
+                let declared = VariableType {
+                    typing: optional_annotation(this, it)?,
+                    attribute,
+                    ..Default::default()
+                };
+                names.push((ident, location, declared))
+            }
+            _ => return Err(this.error_at(SiltError::ExpectedLocalIdentifier)),
+        }
+    }
+    if names.len() > u8::MAX as usize {
+        return Err(this.error_at(SiltError::TooManyLocals));
+    }
+    let closing = names
+        .iter()
+        .filter(|(_, _, declared)| declared.attribute == Attribute::Close)
+        .count();
+    if closing > 1 {
+        return Err(this.error_at(SiltError::MultipleToBeClosed));
+    }
+    if let Token::Assign = this.peek(it)? {
+        this.eat(it);
+        this.expression_count = 1;
+        this.can_multivar_set = false;
+        // the values are walked one at a time so each can be held to it's name
+        loop {
+            this.parse_precedence(mc, f, it, Precedence::Assignment, false)?;
+            if let Some((_, _, declared)) = names.get(this.expression_count as usize - 1) {
+                check_initializer(this, declared.typing)?;
+            }
+            if let Token::Comma = this.peek(it)? {
+                add!(this);
+                this.eat(it);
+            } else {
+                break;
+            }
+        }
+        this.can_multivar_set = true;
+        balance_expressions(this, f, names.len() as isize);
+    } else {
+        this.emit_at(f, OpCode::NILS(names.len() as u8));
+    }
+    if (this.scope_depth > 0 || closing > 0) && local {
+        for (ident, _, declared) in names {
+            let attribute = declared.attribute;
+            let index = add_local(this, f, it, ident)?;
+            set_local_type(this, declared);
+            if attribute == Attribute::Close {
+                this.emit_at(f, OpCode::TO_BE_CLOSED { index });
+            }
+        }
+    } else {
+        // last value is on top of the stack
+        for (ident, location, known) in names.into_iter().rev() {
+            set_global_type(this, &ident, known);
+            let constant = this.identifer_constant(f, ident);
+            define_variable(this, it, f, Some((constant, location)))?;
+        }
+    }
+    Ok(())
+}
+
 fn declaration_scope<'a, 'c: 'a>(
     this: &mut Compiler,
-    f: FnRef,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
     it: &mut Peekable<Lexer>,
     ident: String,
     local: bool,
@@ -1153,11 +1480,19 @@ fn declaration_scope<'a, 'c: 'a>(
     if this.scope_depth > 0 && local {
         //local
         //TODO should we warn? redefine_behavior(this,ident)?
-        add_local(this, it, ident)?;
-        typing(this, f, it, None)?;
+        add_local(this, f, it, ident.clone())?;
+        let declared = VariableType {
+            typing: optional_annotation(this, it)?,
+            ..Default::default()
+        };
+        typing(this, mc, f, it, ident, None, declared)?;
     } else {
-        let ident = this.identifer_constant(f, ident);
-        typing(this, f, it, Some((ident, location)))?;
+        let constant = this.identifer_constant(f, ident.clone());
+        let declared = VariableType {
+            typing: optional_annotation(this, it)?,
+            ..Default::default()
+        };
+        typing(this, mc, f, it, ident, Some((constant, location)), declared)?;
     }
     Ok(())
 }
@@ -1180,10 +1515,21 @@ fn declaration_scope<'a, 'c: 'a>(
 /** Store location as a local to resolve getters with, the index pointing to the stack */
 fn add_local(
     this: &mut Compiler,
+    f: FnRef,
     it: &mut Peekable<Lexer>,
     ident: String,
 ) -> Result<u8, ErrorTuple> {
-    _add_local(this, it, Some(ident))
+    let i = _add_local(this, it, Some(ident.clone()))?;
+    // the slot GET_LOCAL will read it from, as resolve_local works it out
+    let slot = match this.functional_depth {
+        0 => this.locals.len() - 1,
+        d => this.locals.len() - 1 - this.local_functional_offset[d - 1],
+    };
+    let handle = f.chunk.begin_local_name(ident, slot as u8);
+    if let Some(l) = this.locals.last_mut() {
+        l.debug = Some(handle);
+    }
+    Ok(i)
 }
 
 /** Store location on the stack with a placeholder that cannot be resolved as a variable, only reserves for operations */
@@ -1211,6 +1557,8 @@ fn _add_local(
         depth: this.scope_depth,
         functional_depth: this.functional_depth,
         is_captured: false,
+        known: VariableType::default(),
+        debug: None,
     });
     this.local_count += 1;
     // let offset = if this.functional_depth > 0 {
@@ -1327,44 +1675,246 @@ fn resolve_upvalue(
 //     Ok(None)
 // }
 
+/** declaration with an optional `: type` annotation, a known initializer type must fit it. The name is either the freshly added local or the global behind ident_tuple */
 fn typing<'a, 'c: 'a>(
     this: &mut Compiler,
-    f: FnRef,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
     it: &mut Peekable<Lexer>,
+    ident: String,
     ident_tuple: Option<(Ident, TokenCell)>,
+    declared: VariableType,
 ) -> Catch {
     devnote!(this it "typing");
+    let (annotation, attribute) = (declared.typing, declared.attribute);
+    let global = ident_tuple.is_some();
+    let start = this.get_chunk_size(f);
+    define_declaration(this, mc, f, it, ident_tuple)?;
+    check_initializer(this, annotation)?;
+    let folded = if attribute == Attribute::Const {
+        folded_literal(f, start)
+    } else {
+        None
+    };
+    if global {
+        let known = VariableType {
+            typing: annotation,
+            attribute,
+            folded,
+            ..Default::default()
+        };
+        set_global_type(this, &ident, known);
+    } else {
+        let known = VariableType {
+            typing: annotation,
+            inferred: this.expression_type,
+            signature: this.expression_signature.take(),
+            attribute,
+            folded,
+        };
+        set_local_type(this, known);
+        if attribute == Attribute::Close {
+            let index = (this.local_count - 1) as u8;
+            this.emit_at(f, OpCode::TO_BE_CLOSED { index });
+        }
+    }
+    Ok(())
+}
+
+/** parse `<const>` or `<close>` if one follows a declared name */
+fn optional_attribute(
+    this: &mut Compiler,
+    it: &mut Peekable<Lexer>,
+) -> Result<Attribute, ErrorTuple> {
+    if let Token::Op(Operator::Less) = this.peek(it)? {
+        this.eat(it);
+    } else {
+        return Ok(Attribute::None);
+    }
+    let (res, location) = this.pop(it);
+    this.current_location = location;
+    let attribute = match res? {
+        Token::Identifier(name) => match name.as_str() {
+            "const" => Attribute::Const,
+            "close" => Attribute::Close,
+            _ => return Err(this.error_at(SiltError::UnknownAttribute(name))),
+        },
+        t => return Err(this.error_at(SiltError::UnknownAttribute(t.to_string()))),
+    };
+    if let Token::Op(Operator::Greater) = this.peek(it)? {
+        this.eat(it);
+    } else {
+        return Err(this.error_at(SiltError::ExpectedToken(Token::Op(Operator::Greater))));
+    }
+    Ok(attribute)
+}
+
+/** the literal a declaration was initialised with, only if the initializer compiled to nothing else */
+fn folded_literal(f: FnRef, start: usize) -> Option<Token> {
+    let literal = match &f.chunk.code[start..] {
+        [op] | [op, OpCode::DEFINE_GLOBAL { .. }] => op,
+        _ => return None,
+    };
+    match literal {
+        OpCode::NIL => Some(Token::Nil),
+        OpCode::TRUE => Some(Token::True),
+        OpCode::FALSE => Some(Token::False),
+        OpCode::CONSTANT { constant } => match f.chunk.get_constant(*constant) {
+            Value::Integer(i) => Some(Token::Integer(*i)),
+            Value::Number(n) => Some(Token::Number(*n)),
+            Value::String(s) => Some(Token::StringLiteral(s.as_str().into())),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/** emit a folded const as the literal it was declared with */
+fn folded_code<'c>(this: &mut Compiler, f: FnRef<'_, 'c>, literal: &Token) -> OpCode {
+    let value = match literal {
+        Token::Nil => return OpCode::NIL,
+        Token::True => return OpCode::TRUE,
+        Token::False => return OpCode::FALSE,
+        Token::Integer(i) => Value::Integer(*i),
+        Token::Number(n) => Value::Number(*n),
+        Token::StringLiteral(s) => Value::String(s.to_string()),
+        _ => unreachable!(),
+    };
+    OpCode::CONSTANT {
+        constant: this.write_constant(f, value),
+    }
+}
+
+/** parse `: type` if a colon is up next, otherwise the declaration is untyped */
+fn optional_annotation(this: &mut Compiler, it: &mut Peekable<Lexer>) -> Result<Type, ErrorTuple> {
     if let Token::Colon = this.peek(it)? {
-        // typing or self calling
         this.eat(it);
-        this.store(it);
-        let t = this.get_current()?;
-        if let Token::ColonIdentifier(target) = t {
-            // method or type name
-            // if let Some(&Token::OpenParen) = self.peek() {
-            //     // self call
-
-            //     Statement::InvalidStatement
-            // } else {
-            //     // typing
-            //     // return self.assign(self.peek(), ident);
-            //     Statement::InvalidStatement
-            // }
-            define_declaration(this, f, it, ident_tuple)?;
+        type_annotation(this, it)
+    } else {
+        Ok(Type::None)
+    }
+}
+
+/** the type name following an annotation's colon, nil and function are keywords so they arrive as their own tokens */
+fn type_annotation(this: &mut Compiler, it: &mut Peekable<Lexer>) -> Result<Type, ErrorTuple> {
+    let (res, location) = this.pop(it);
+    this.current_location = location;
+    match res? {
+        Token::Identifier(name) => match Type::from_name(&name) {
+            Some(t) => Ok(t),
+            None => Err(this.error_at(SiltError::UnknownType(name))),
+        },
+        Token::Nil => Ok(Type::Nil),
+        Token::Function => Ok(Type::Function),
+        t => Err(this.error_at(SiltError::UnknownType(t.to_string()))),
+    }
+}
+
+/** error if the last expression's type is known and doesn't fit the expected type, an inferred type could be wrong so it only warns */
+fn check_type(this: &mut Compiler, expected: Type) -> Catch {
+    if expected.accepts(this.expression_type) {
+        Ok(())
+    } else {
+        let code = SiltError::TypeMismatch(expected, this.expression_type);
+        if this.expression_inferred {
+            this.warn_at(code);
+            Ok(())
         } else {
-            todo!("typing");
-            // self.error(SiltError::InvalidColonPlacement);
-            // Statement::InvalidStatement
+            Err(this.error_at(code))
         }
+    }
+}
+
+/** a declared variable may start out nil whether or not the nil is written out, otherwise the value has to fit */
+fn check_initializer(this: &mut Compiler, expected: Type) -> Catch {
+    if this.expression_type == Type::Nil {
+        Ok(())
     } else {
-        define_declaration(this, f, it, ident_tuple)?;
+        check_type(this, expected)
     }
-    Ok(())
+}
+
+/** annotate the most recently added local */
+fn set_local_type(this: &mut Compiler, known: VariableType) {
+    if let Some(local) = this.locals.last_mut() {
+        local.known = known;
+    }
+}
+
+/** annotate a global, a redeclaration with nothing to go on forgets the old type */
+fn set_global_type(this: &mut Compiler, ident: &str, known: VariableType) {
+    if known.typing == Type::None && known.signature.is_none() && known.attribute == Attribute::None
+    {
+        this.global_types.remove(ident);
+    } else {
+        this.global_types.insert(ident.to_string(), known);
+    }
+}
+
+/** what we know of a variable in scope, locals shadow globals */
+fn declared_type(this: &Compiler, ident: &str) -> VariableType {
+    for l in this.locals.iter().rev() {
+        if l.ident.as_deref() == Some(ident) {
+            return l.known.clone();
+        }
+    }
+    this.global_types.get(ident).cloned().unwrap_or_default()
+}
+
+/** an assignment replaces what we inferred, unless it's from a nested block or function that may not run, then both types are possible */
+fn assign_inferred(this: &mut Compiler, ident: &str, value: Option<(Type, Option<FunctionType>)>) {
+    let (scope_depth, functional_depth) = (this.scope_depth, this.functional_depth);
+    let (known, certain) = match this
+        .locals
+        .iter_mut()
+        .rev()
+        .find(|l| l.ident.as_deref() == Some(ident))
+    {
+        Some(l) => (
+            &mut l.known,
+            l.depth == scope_depth && l.functional_depth == functional_depth,
+        ),
+        None => match this.global_types.get_mut(ident) {
+            Some(known) => (known, scope_depth == 0 && functional_depth == 0),
+            None => return,
+        },
+    };
+    let (inferred, signature) = value.unwrap_or_default();
+    if certain {
+        known.inferred = inferred;
+        known.signature = signature;
+    } else {
+        known.inferred = known.inferred.merge(inferred);
+        if known.signature != signature {
+            known.signature = None;
+        }
+    }
+}
+
+/** a variable read is as good as it's annotation, otherwise the inferred type is a hint */
+fn variable_expression(this: &mut Compiler, known: VariableType) {
+    if known.typing == Type::None {
+        this.expression_type = known.inferred;
+        this.expression_inferred = true;
+    } else {
+        this.expression_type = known.typing;
+        this.expression_inferred = false;
+    }
+    this.expression_signature = known.signature;
+}
+
+/** merge a function's returned type into what it's been seen to return so far */
+fn infer_return(this: &mut Compiler, returned: Type) {
+    this.inferred_return = Some(match this.inferred_return {
+        Some(t) => t.merge(returned),
+        None => returned,
+    });
 }
 
 fn define_declaration<'a, 'c: 'a>(
     this: &mut Compiler,
-    f: FnRef,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
     it: &mut Peekable<Lexer>,
     ident_tuple: Option<(Ident, TokenCell)>,
 ) -> Catch {
@@ -1373,7 +1923,7 @@ fn define_declaration<'a, 'c: 'a>(
     let t = this.get_current()?;
     match t {
         Token::Assign => {
-            expression(this, f, it, false)?;
+            expression(this, mc, f, it, false)?;
         }
         // we can't increment what doesn't exist yet, like what are you even doing?
         Token::AddAssign
@@ -1381,12 +1931,15 @@ fn define_declaration<'a, 'c: 'a>(
         | Token::MultiplyAssign
         | Token::DivideAssign
         | Token::ModulusAssign => {
-            // let tt = t.unwrap().clone(); // TODO
-            // self.error(SiltError::InvalidAssignment(tt));
-            // Statement::InvalidStatement
-            todo!()
+            let t = t.clone();
+            return Err(this.error_at(SiltError::InvalidAssignment(t)));
+        }
+        _ => {
+            // TODO are more then just declarations hitting this syntactic sugar?
+            this.emit_at(f, OpCode::NIL);
+            // an annotated declaration may start out empty
+            this.expression_type = Type::None;
         }
-        _ => this.emit_at(f, OpCode::NIL), // TODO are more then just declarations hitting this syntactic sugar?
     }
     define_variable(this, it, f, ident_tuple)?;
     Ok(())
@@ -1425,22 +1978,77 @@ fn define_function<'c>(
         ("anonymous".to_string(), this.current_location)
     };
 
+    // function T.a.b:m() sets a field on T instead of declaring a variable
+    if let Token::Dot | Token::Colon = this.peek(it)? {
+        return define_field_function(this, mc, f, it, ident, location);
+    }
+
     let ident_clone = ident.clone();
-    let global_ident = if this.scope_depth > 0 && local {
+    let (global_ident, local_index) = if this.scope_depth > 0 && local {
         //local
         //TODO should we warn? redefine_behavior(this,ident)?
-        add_local(this, it, ident)?;
-        None
+        add_local(this, f, it, ident.clone())?;
+        (None, Some(this.locals.len() - 1))
     } else {
-        Some((this.identifer_constant(f, ident), location))
+        (
+            Some((this.identifer_constant(f, ident.clone()), location)),
+            None,
+        )
+    };
+
+    let signature = build_function(this, mc, f, it, ident_clone, global_ident, false)?;
+    // it's still just a variable that can be reassigned, only the signature is enforced
+    let known = VariableType {
+        typing: Type::None,
+        inferred: Type::Function,
+        signature: Some(signature),
+        ..Default::default()
     };
+    match local_index {
+        Some(i) => this.locals[i].known = known,
+        None => set_global_type(this, &ident, known),
+    }
 
-    build_function(this, mc, f, it, ident_clone, global_ident, false)?;
+    Ok(())
+}
 
+/** `function T.a.b:m()`, walks the field path and stores the built function with TABLE_SET. A colon name must be last and gives the function an implicit self */
+fn define_field_function<'c>(
+    this: &mut Compiler,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    it: &mut Peekable<Lexer>,
+    ident: String,
+    location: TokenCell,
+) -> Catch {
+    devnote!(this it "define_field_function");
+    let (_, getter) = resolve_etters(this, f, it, ident);
+    this.emit(f, getter, location);
+    let mut depth = 0;
+    let mut name = String::new();
+    while let Token::Dot | Token::Colon = this.peek(it)? {
+        let is_method = matches!(this.peek(it)?, Token::Colon);
+        this.eat(it);
+        let t = this.pop(it);
+        this.current_location = t.1;
+        if let Token::Identifier(field) = t.0? {
+            name = field.clone();
+            this.emit_identifer_constant_at(f, field);
+        } else {
+            return Err(this.error_at(SiltError::ExpectedFieldIdentifier));
+        }
+        depth += 1;
+        if is_method {
+            this.method_self = true;
+            break;
+        }
+    }
+    build_function(this, mc, f, it, name, None, false)?;
+    this.emit_at(f, OpCode::TABLE_SET { depth });
     Ok(())
 }
 
-/** builds function, implicit return specifices whether a nil is return or the last value popped */
+/** builds function, implicit return specifices whether a nil is return or the last value popped. Returns the parameter and return annotations */
 fn build_function<'c>(
     this: &mut Compiler,
     mc: &Mutation<'c>,
@@ -1449,7 +2057,7 @@ fn build_function<'c>(
     ident: String,
     global_ident: Option<(u8, TokenCell)>,
     is_script: bool,
-) -> Catch {
+) -> Result<FunctionType, ErrorTuple> {
     // TODO this function could be called called rercursivelly due to the recursive decent nature of the parser, we should add a check to make sure we don't overflow the stack
     devnote!(this it "build_function");
     let mut f2 = FunctionObject::new(Some(ident), is_script);
@@ -1458,37 +2066,104 @@ fn build_function<'c>(
     // swap(f, &mut sidelined_func);
     begin_scope(this);
     begin_functional_scope(this);
+    let start = this.current_location;
     expect_token!(this it OpenParen);
     let mut arity = 0;
-    if let Token::Identifier(_) = this.peek(it)? {
+    if std::mem::take(&mut this.method_self) {
+        add_local(this, fr2, it, "self".to_string())?;
         arity += 1;
-        build_param(this, it)?;
-
-        while let Token::Comma = this.peek(it)? {
-            this.eat(it);
-            arity += 1;
-            if arity > 255 {
-                // TODO we should use an arity value on the function object but let's make it only exist on compile time
-                return Err(this.error_at(SiltError::TooManyParameters));
+    }
+    let mut variadic = false;
+    let mut params = vec![];
+    if !matches!(this.peek(it)?, Token::CloseParen) {
+        while {
+            if let Token::VarArgs = this.peek(it)? {
+                // must be the last parameter, the close paren check below catches anything after
+                this.eat(it);
+                variadic = true;
+                false
+            } else {
+                arity += 1;
+                if arity > 255 {
+                    return Err(this.error_at(SiltError::TooManyParameters));
+                }
+                params.push(build_param(this, fr2, it)?);
+                if let Token::Comma = this.peek(it)? {
+                    this.eat(it);
+                    true
+                } else {
+                    false
+                }
             }
-            build_param(this, it)?;
-        }
+        } {}
     }
+    expect_token!(
+        this,
+        it,
+        CloseParen,
+        this.error_at(SiltError::UnterminatedParenthesis(start.0, start.1))
+    );
+    fr2.arity = arity as u8;
+    fr2.is_variadic = variadic;
+    let returns = optional_annotation(this, it)?;
+
+    let implicit_return = this.language_flags.implicit_returns;
+    let outer_return = std::mem::replace(&mut this.return_type, returns);
+    let outer_inferred = this.inferred_return.take();
+    function_body(this, mc, f, it, f2, variadic, implicit_return)?;
+    this.return_type = outer_return;
+    let inferred_returns = std::mem::replace(&mut this.inferred_return, outer_inferred);
+    define_variable(this, it, f, global_ident)?;
+
+    Ok(FunctionType {
+        params,
+        returns,
+        inferred_returns: inferred_returns.unwrap_or_default(),
+    })
+}
 
+/** compile a function's block after its parameters are set up, then close it onto the stack of the enclosing function */
+fn function_body<'c>(
+    this: &mut Compiler,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    it: &mut Peekable<Lexer>,
+    mut f2: FunctionObject<'c>,
+    variadic: bool,
+    implicit_return: bool,
+) -> Catch {
     // this.override_pop=true; // the function declare is inside our scope and it would trigger a pop
+    this.last_was_return = false;
+    // a break can't reach a loop outside of the function
+    let outer_loops = std::mem::take(&mut this.loops);
+    // nor can a goto reach a label outside of it
+    let outer_labels = std::mem::take(&mut this.labels);
+    let outer_end_labels = std::mem::take(&mut this.end_labels);
+    let outer_gotos = std::mem::take(&mut this.pending_gotos);
+    let outer_variadic = std::mem::replace(&mut this.variadic, variadic);
+    let outer_closed = std::mem::replace(&mut this.closed_at, 0);
+    let fr2 = &mut f2;
     block(this, mc, fr2, it)?;
+    this.loops = outer_loops;
+    this.variadic = outer_variadic;
+    this.closed_at = outer_closed;
 
-    if let &OpCode::RETURN(_) = fr2.chunk.code.last().unwrap() { //read_last_code
+    if this.last_was_return {
     } else {
-        this.drop_last_if(fr2, &OpCode::POP);
         // println!("impli {}",implicit_return);
         // TODO if last was semicolon we also push a nil
         // Check if implicit returns are enabled and last statement was an expression
-        if this.language_flags.implicit_returns && this.last_was_expression {
+        if implicit_return && this.last_was_expression {
+            // the statement's POP is only dropped when it's value is returned, otherwise it may be a jump target
+            this.drop_last_if(fr2, &OpCode::POP);
             // Don't emit NIL, the last expression value(s) are already on the stack
             // If we have multiple expressions, they're all on the stack for multiple returns
+            let returned = this.expression_type;
+            infer_return(this, returned);
         } else {
-            this.emit_at(fr2, OpCode::NIL);
+            // falling off the end returns nothing at all
+            this.expression_count = 0;
+            infer_return(this, Type::Nil);
         }
         print_var_stack(&this.var_stack);
         devout!(
@@ -1499,18 +2174,35 @@ fn build_function<'c>(
         this.emit_at(fr2, OpCode::RETURN(this.expression_count));
         this.expression_count = 0;
     }
+    let end = this.get_chunk_size(fr2) - 1;
+    resolve_gotos(this, fr2, end);
+    this.labels = outer_labels;
+    this.end_labels = outer_end_labels;
+    this.pending_gotos = outer_gotos;
 
     // TODO why do we need to eat again? This prevents an expression_statement of "End" being called but block should have eaten it?
     // if let Token::End = this.peek()? {
     //     this.eat();
     // }
 
-    end_scope(this, fr2, true);
+    close_function(this, mc, f, f2);
+    Ok(())
+}
+
+/** end the function's scopes and emit it as a closure with it's upvalues */
+fn close_function<'c>(
+    this: &mut Compiler,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    mut f2: FunctionObject<'c>,
+) {
+    end_scope(this, &mut f2, true);
     let upvals = end_functional_scope(this);
     // When we're done compiling the function object we drop the current body function back in and push the compiled func as a constant within that body
     // this.swap_function(&mut sidelined_func);
     // swap(f, &mut sidelined_func);
     f2.upvalue_count = upvals.len() as u8;
+    f2.chunk_name = f.chunk_name.clone();
     let func_value = Value::Function(Gc::new(mc, f2));
     if true {
         // need closure
@@ -1532,22 +2224,32 @@ fn build_function<'c>(
         // no closure needed
         // this.constant_at(f, func_value);
     }
-    define_variable(this, it, f, global_ident)?;
-
-    Ok(())
 }
 
-fn build_param(this: &mut Compiler, it: &mut Peekable<Lexer>) -> Catch {
+/** a named parameter with an optional annotation, the local is typed for the body */
+fn build_param(
+    this: &mut Compiler,
+    f: FnRef,
+    it: &mut Peekable<Lexer>,
+) -> Result<Type, ErrorTuple> {
     let (res, _) = this.pop(it);
     match res? {
         Token::Identifier(ident) => {
-            add_local(this, it, ident)?;
+            add_local(this, f, it, ident)?;
         }
         _ => {
             return Err(this.error_at(SiltError::ExpectedLocalIdentifier));
         }
     }
-    Ok(())
+    let typing = optional_annotation(this, it)?;
+    set_local_type(
+        this,
+        VariableType {
+            typing,
+            ..Default::default()
+        },
+    );
+    Ok(typing)
 }
 
 fn statement<'c>(
@@ -1565,7 +2267,7 @@ fn statement<'c>(
     this.can_multivar_set=true;
 
     match this.peek(it)? {
-        Token::Print => print(this, f, it)?,
+        Token::Print => print(this, mc, f, it)?,
         Token::If => if_statement(this, mc, f, it)?,
         Token::Do => {
             this.eat(it);
@@ -1575,7 +2277,9 @@ fn statement<'c>(
         }
         Token::While => while_statement(this, mc, f, it)?,
         Token::For => for_statement(this, mc, f, it)?,
-        Token::Return => return_statement(this, f, it)?,
+        Token::Repeat => repeat_statement(this, mc, f, it)?,
+        Token::Break => break_statement(this, f, it)?,
+        Token::Return => return_statement(this, mc, f, it)?,
         // Token::OpenBrace => block(this),
         Token::ColonColon => set_goto_label(this, f, it)?,
         Token::Goto => goto_statement(this, f, it)?,
@@ -1587,7 +2291,7 @@ fn statement<'c>(
         //     // this.eat();
         //     // TODO ???
         // }
-        _ => expression_statement(this, f, it)?, // This will set last_was_expression = true
+        _ => expression_statement(this, mc, f, it)?, // This will set last_was_expression = true
     }
     Ok(())
 }
@@ -1624,13 +2328,100 @@ fn begin_functional_scope(this: &mut Compiler) {
 fn end_scope(this: &mut Compiler, f: FnRef, skip_code: bool) {
     this.scope_depth -= 1;
 
-    let mut last_was_pop = true;
-    let mut count = 0;
-    let mut v = vec![];
+    let base = this
+        .locals
+        .iter()
+        .rposition(|l| l.depth <= this.scope_depth)
+        .map_or(0, |i| i + 1);
+    let close = close_slot(this, base);
+
+    let mut captured = vec![];
     while !this.locals.is_empty() && this.locals.last().unwrap().depth > this.scope_depth {
         let l = this.locals.pop().unwrap();
         this.local_count -= 1;
-        if l.is_captured {
+        if let Some(handle) = l.debug {
+            f.chunk.end_local_name(handle);
+        }
+        // any break that jumps over this local needs to know if it has to be closed
+        let position = this.locals.len();
+        for b in this.loops.iter_mut() {
+            if position >= b.base {
+                for pending in b.breaks.iter_mut() {
+                    if let Some(c) = pending.captured.get_mut(position - b.base) {
+                        *c |= l.is_captured;
+                    }
+                }
+            }
+        }
+        leave_goto_local(this, position, l.is_captured);
+        captured.push(l.is_captured);
+    }
+    let depth = this.scope_depth;
+    // if we're not dealing with upvalues and we're skipping code due to functional scope our stack will get moved off anyway
+    if !skip_code {
+        if let Some(index) = close {
+            this.emit_at(f, OpCode::CLOSE { index });
+        }
+        for op in scope_exit_codes(captured.into_iter()) {
+            this.emit_at(f, op);
+        }
+        // a label closing out the block lands past the ops above, gotos in the block can still see it
+        let label = GotoLabel {
+            index: this.get_chunk_size(f),
+            locals: this.locals.len(),
+            depth: depth + 1,
+        };
+        while this.end_labels.last().is_some_and(|(_, d)| *d > depth) {
+            let (ident, _) = this.end_labels.pop().unwrap();
+            if let Some(e) = target_gotos(this, &ident, &label) {
+                this.push_error(e);
+            }
+        }
+    }
+    // gotos left in the block can now only find labels further out
+    this.labels.retain(|_, l| l.depth <= depth);
+    for pending in this.pending_gotos.iter_mut() {
+        pending.depth = pending.depth.min(depth);
+    }
+}
+
+/** a local at `position` left scope, any goto that was past it needs to know if it has to be closed */
+fn leave_goto_local(this: &mut Compiler, position: usize, is_captured: bool) {
+    for pending in this.pending_gotos.iter_mut() {
+        if position < pending.live {
+            pending.captured[position - pending.base] |= is_captured;
+            pending.live = position;
+        }
+    }
+}
+
+/** how far the current function's frame slots are from the index into locals */
+fn slot_offset(this: &Compiler) -> usize {
+    if this.functional_depth > 0 {
+        this.local_functional_offset[this.functional_depth - 1]
+    } else {
+        0
+    }
+}
+
+/** frame slot of the lowest `<close>` local from locals[base] upward, leaving past it has to close them first */
+fn close_slot(this: &Compiler, base: usize) -> Option<u8> {
+    let offset = slot_offset(this);
+    this.locals
+        .iter()
+        .enumerate()
+        .skip(base)
+        .find(|(_, l)| l.known.attribute == Attribute::Close)
+        .map(|(i, _)| (i - offset) as u8)
+}
+
+/** Build the ops that drop locals off the stack, ordered from the top of the stack down by whether they were captured */
+fn scope_exit_codes(captured: impl Iterator<Item = bool>) -> Vec<OpCode> {
+    let mut last_was_pop = true;
+    let mut count = 0;
+    let mut v = vec![];
+    for is_captured in captured {
+        if is_captured {
             if last_was_pop {
                 v.push(count);
                 count = 0;
@@ -1649,22 +2440,41 @@ fn end_scope(this: &mut Compiler, f: FnRef, skip_code: bool) {
     if count > 0 {
         v.push(count);
     }
-    // if we're not dealing with upvalues and we're skipping code due to functional scope our stack will get moved off anyway
-    if skip_code {
-        //&& v.len() <= 1 {
-        return;
-    }
 
     // index 0 is always OP_POPS but could be count of 0 if the first local is captured. Otherwise we can safely stagger even as pop, odds as close
-    v.iter().enumerate().for_each(|(i, c)| {
-        if i % 2 == 0 {
-            this.emit_at(f, OpCode::POPS(*c));
-        } else {
-            this.emit_at(f, OpCode::CLOSE_UPVALUES(*c));
-        }
+    v.iter()
+        .enumerate()
+        .map(|(i, c)| {
+            if i % 2 == 0 {
+                OpCode::POPS(*c)
+            } else {
+                OpCode::CLOSE_UPVALUES(*c)
+            }
+        })
+        .collect()
+}
+
+/** start tracking a loop body for break statements, call right after the body's begin_scope */
+fn begin_loop(this: &mut Compiler) {
+    this.loops.push(LoopScope {
+        base: this.locals.len(),
+        breaks: vec![],
     });
 }
 
+/** fill in each break's reserved ops now that captures are known and point it at the current op, which should follow the loop body's end_scope */
+fn end_loop(this: &mut Compiler, f: FnRef) -> Catch {
+    let scope = this.loops.pop().unwrap();
+    for pending in scope.breaks {
+        let reserved = pending.captured.len() + 1;
+        for (i, op) in scope_exit_codes(pending.captured.into_iter().rev()).into_iter().enumerate() {
+            this.change_code(f, pending.index + i, op);
+        }
+        this.patch(f, pending.index + reserved)?;
+    }
+    Ok(())
+}
+
 /** raise functional depth */
 fn end_functional_scope(this: &mut Compiler) -> Vec<UpLocal> {
     this.functional_depth -= 1;
@@ -1685,7 +2495,7 @@ fn if_statement<'c>(
 ) -> Catch {
     devnote!(this it "if_statement");
     this.eat(it);
-    expression(this, f, it, false)?;
+    expression(this, mc, f, it, false)?;
     expect_token!(this it Then);
     let skip_if = this.emit_index(f, OpCode::POP_AND_GOTO_IF_FALSE(0));
     scope_and_block_until!(this, mc, f, it, End | Else | ElseIf);
@@ -1700,13 +2510,15 @@ fn if_statement<'c>(
             expect_token!(this it End);
         }
         Token::ElseIf => {
-            this.eat(it);
-            // this.emit_at(OpCode::POP);
+            // if_statement eats the elseif for us
+            let skip_else = this.emit_index(f, OpCode::FORWARD(0));
             this.patch(f, skip_if)?;
             if_statement(this, mc, f, it)?;
+            this.patch(f, skip_else)?;
         }
         _ => {
             this.patch(f, skip_if)?;
+            expect_token!(this it End);
         }
     }
     Ok(())
@@ -1721,12 +2533,81 @@ fn while_statement<'c>(
     devnote!(this it "while_statement");
     this.eat(it);
     let loop_start = this.get_chunk_size(f);
-    expression(this, f, it, false)?;
+    expression(this, mc, f, it, false)?;
     expect_token!(this it Do);
     let exit_jump = this.emit_index(f, OpCode::POP_AND_GOTO_IF_FALSE(0));
+    begin_scope(this);
+    begin_loop(this);
     build_block_until_then_eat!(this, mc, f, it, End);
+    end_scope(this, f, false);
+    this.emit_rewind(f, loop_start);
+    this.patch(f, exit_jump)?;
+    end_loop(this, f)
+}
+
+/**
+ * Run the block at least once, the until condition is evaluated within the block's scope so it can see its locals
+ * If the condition is false we drop the block's locals and rewind, otherwise drop them and forward past the rewind
+ */
+fn repeat_statement<'c>(
+    this: &mut Compiler,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    it: &mut Peekable<Lexer>,
+) -> Catch {
+    devnote!(this it "repeat_statement");
+    this.eat(it);
+    let loop_start = this.get_chunk_size(f);
+    begin_scope(this);
+    begin_loop(this);
+    build_block_until_then_eat!(this, mc, f, it, Until);
+    expression_single(this, mc, f, it, false)?;
+    let again_jump = this.emit_index(f, OpCode::POP_AND_GOTO_IF_FALSE(0));
+    // the block's locals are still in scope so drop them here without forgetting them for the rewind path
+    let captured = this
+        .locals
+        .iter()
+        .rev()
+        .take_while(|l| l.depth >= this.scope_depth)
+        .map(|l| l.is_captured)
+        .collect::<Vec<bool>>();
+    for op in scope_exit_codes(captured.into_iter()) {
+        this.emit_at(f, op);
+    }
+    let exit_jump = this.emit_index(f, OpCode::FORWARD(0));
+    this.patch(f, again_jump)?;
+    end_scope(this, f, false);
     this.emit_rewind(f, loop_start);
     this.patch(f, exit_jump)?;
+    end_loop(this, f)
+}
+
+/** Drop the locals of the innermost loop's body and forward to just past the loop, the ops are filled in by end_loop */
+fn break_statement(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>) -> Catch {
+    devnote!(this it "break_statement");
+    let location = this.pop(it).1;
+    let locals_len = this.locals.len();
+    let Some(scope) = this.loops.last() else {
+        return Err(this.error_syntax(SiltError::BreakOutsideLoop, location));
+    };
+    let captured = this.locals[scope.base..locals_len]
+        .iter()
+        .map(|l| l.is_captured)
+        .collect::<Vec<bool>>();
+    if let Some(index) = close_slot(this, scope.base) {
+        this.emit_at(f, OpCode::CLOSE { index });
+    }
+    let index = this.get_chunk_size(f);
+    // at most one op per local plus a leading empty pop, the spares stay as harmless POPS(0)
+    for _ in 0..=captured.len() {
+        this.emit_at(f, OpCode::POPS(0));
+    }
+    this.emit_at(f, OpCode::FORWARD(0));
+    this.loops
+        .last_mut()
+        .unwrap()
+        .breaks
+        .push(PendingBreak { index, captured });
     Ok(())
 }
 
@@ -1747,21 +2628,24 @@ fn for_statement<'c>(
     let pair = this.pop(it);
     let t = pair.0?;
     if let Token::Identifier(ident) = t {
+        if let Token::Comma | Token::In = this.peek(it)? {
+            return generic_for_statement(this, mc, f, it, ident);
+        }
         // let offset = this.local_functional_offset[this.functional_depth - 1];
         let iterator = add_local_placeholder(this, it)?; // reserve iterator with placeholder
         expect_token!(this it Assign);
         add_local_placeholder(this, it)?; // reserve end value with placeholder
         add_local_placeholder(this, it)?; // reserve step value with placeholder
-        expression(this, f, it, false)?; // expression for iterator
+        expression_single(this, mc, f, it, false)?; // expression for iterator
         expect_token!(this it Comma);
-        expression(this, f, it, false)?; // expression for end value
+        expression_single(this, mc, f, it, false)?; // expression for end value
 
         // let exit_jump = this.emit_index(OpCode::GOTO_IF_FALSE(0));
         // this.emit_at(OpCode::POP);
         // either we have an expression for the step or we set it to 1i
         if let Token::Comma = this.peek(it)? {
             this.eat(it);
-            expression(this, f, it, false)?;
+            expression_single(this, mc, f, it, false)?;
         } else {
             this.constant_at(f, Value::Integer(1))
         };
@@ -1775,13 +2659,15 @@ fn for_statement<'c>(
         // this.emit_at(OpCode::POP);
         expect_token!(this it Do);
         begin_scope(this);
-        add_local(this, it, ident)?; // we add the local inside the scope which was actually added on by the for opcode already
+        begin_loop(this);
+        add_local(this, f, it, ident)?; // we add the local inside the scope which was actually added on by the for opcode already
         build_block_until_then_eat!(this, mc, f, it, End);
         end_scope(this, f, false);
 
         this.emit_at(f, OpCode::INCREMENT { index: iterator });
         this.emit_rewind(f, for_start);
         this.patch(f, for_start)?;
+        end_loop(this, f)?;
         this.force_stack_pop(f, 3);
         Ok(())
     } else {
@@ -1790,12 +2676,68 @@ fn for_statement<'c>(
 }
 
 /**
- * We run closure and if value is not nil we set that to iterator and push onto blocks scope, when we hit end we rewind and re-eval
- * If the for's iterator is nil we forward to end of do block and pop off the iterator
+ * Reserve the iterator function, state and control value as hidden locals, then each pass we call the function with
+ * state and control and receive one value per named variable
+ * If the first value is not nil it becomes the new control value and the values become the block's fresh locals, when we hit end we rewind and re-eval
+ * If the first value is nil we forward to end of do block and pop off the hidden locals
  */
-fn generic_for_statement() {}
+fn generic_for_statement<'c>(
+    this: &mut Compiler,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    it: &mut Peekable<Lexer>,
+    first: String,
+) -> Catch {
+    devnote!(this it "generic_for_statement");
+    let mut names = vec![first];
+    while let Token::Comma = this.peek(it)? {
+        this.eat(it);
+        match this.pop(it).0? {
+            Token::Identifier(ident) => names.push(ident),
+            _ => return Err(this.error_at(SiltError::ExpectedLocalIdentifier)),
+        }
+    }
+    if names.len() > u8::MAX as usize {
+        return Err(this.error_at(SiltError::TooManyLocals));
+    }
+    expect_token!(this it In);
+    let function = add_local_placeholder(this, it)?; // reserve iterator function with placeholder
+    add_local_placeholder(this, it)?; // reserve invariant state with placeholder
+    add_local_placeholder(this, it)?; // reserve control value with placeholder
+    this.expression_count = 1;
+    this.can_multivar_set = false;
+    expression(this, mc, f, it, false)?;
+    this.can_multivar_set = true;
+    balance_expressions(this, f, 3);
+    expect_token!(this, it, Do, this.error_at(SiltError::ExpectedDo));
+
+    let loop_start = this.get_chunk_size(f);
+    for i in 0..3 {
+        this.emit_at(f, OpCode::GET_LOCAL { index: function + i });
+    }
+    this.emit_at(f, OpCode::CALL(2, names.len() as u8));
+    let for_start = this.emit_index(f, OpCode::FOR_GENERIC(names.len() as u8, 0));
+    begin_scope(this);
+    begin_loop(this);
+    for ident in names {
+        add_local(this, f, it, ident)?; // pushed on by the call, we just name them inside the scope
+    }
+    build_block_until_then_eat!(this, mc, f, it, End);
+    end_scope(this, f, false);
 
-fn return_statement(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>) -> Catch {
+    this.emit_rewind(f, loop_start);
+    this.patch(f, for_start)?;
+    end_loop(this, f)?;
+    this.force_stack_pop(f, 3);
+    Ok(())
+}
+
+fn return_statement<'c>(
+    this: &mut Compiler,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    it: &mut Peekable<Lexer>,
+) -> Catch {
     this.can_multivar_set=false;
     devnote!(this it "return_statement");
     devout!("{} {}", "HERE".on_red(), this.expression_count);
@@ -1803,10 +2745,41 @@ fn return_statement(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>) ->
     if let Token::End | Token::Else | Token::ElseIf | Token::SemiColon | Token::EOF =
         this.peek(it)?
     {
-        this.emit_at(f, OpCode::NIL);
+        // a function returns nothing, the script still needs a value on top
+        if this.functional_depth == 0 {
+            this.emit_at(f, OpCode::NIL);
+            this.expression_count = 1;
+        } else {
+            this.expression_count = 0;
+        }
+        this.expression_type = Type::Nil;
+        this.expression_inferred = false;
+        let expected = this.return_type;
+        check_type(this, expected)?;
+        infer_return(this, Type::Nil);
     } else {
-        expression(this, f, it, false)?;
+        expression(this, mc, f, it, false)?;
+        if this.expression_count == 1 {
+            let expected = this.return_type;
+            check_type(this, expected)?;
+            let returned = this.expression_type;
+            infer_return(this, returned);
+            if let Some(op) = tail_call(this, f) {
+                this.can_multivar_set = true;
+                f.chunk.patch_last(op);
+                // only reached when a native function was called, the frame is replaced otherwise
+                this.emit_at(f, OpCode::RETURN_OPEN(0));
+                return Ok(());
+            }
+        } else {
+            infer_return(this, Type::None);
+        }
         // expression() will set this.expression_count to the number of comma-separated expressions
+        if open_last_values(this, f) {
+            this.can_multivar_set = true;
+            this.emit_at(f, OpCode::RETURN_OPEN(this.expression_count - 1));
+            return Ok(());
+        }
     }
     this.can_multivar_set=true;
 
@@ -1815,171 +2788,253 @@ fn return_statement(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>) ->
     Ok(())
 }
 
+/** `return f(x)` reuses the frame when the call is the whole expression. Not at the top level or when a `<close>` local still has to run after the call */
+fn tail_call(this: &Compiler, f: FnRef) -> Option<OpCode> {
+    if this.functional_depth == 0 {
+        return None;
+    }
+    let base = this.local_functional_offset[this.functional_depth - 1];
+    if close_slot(this, base).is_some() {
+        return None;
+    }
+    if this.closed_at == f.chunk.code.len() {
+        return None;
+    }
+    match f.chunk.read_last_code() {
+        OpCode::CALL(a, _) => Some(OpCode::TAIL_CALL(*a)),
+        OpCode::CALL_OPEN(a, _) => Some(OpCode::TAIL_CALL_OPEN(*a)),
+        _ => None,
+    }
+}
+
+/** Declare a label, a label closing out it's block is placed after the block's locals are dropped so gotos from before them can still reach it */
 fn set_goto_label(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>) -> Catch {
     devnote!(this it "goto_label");
     this.eat(it);
-    let token = this.pop(it).0?;
-    if let Token::Identifier(ident) = token {
-        this.labels.insert(ident, this.get_chunk_size(f));
-    } else {
+    let Token::Identifier(ident) = this.pop(it).0? else {
         return Err(this.error_at(SiltError::ExpectedLabelIdentifier));
+    };
+    expect_token!(
+        this,
+        it,
+        ColonColon,
+        this.error_at(SiltError::ExpectedLabelIdentifier)
+    );
+    if let Token::End | Token::Else | Token::ElseIf | Token::EOF = this.peek(it)? {
+        this.end_labels.push((ident, this.scope_depth));
+        return Ok(());
+    }
+    let label = GotoLabel {
+        index: this.get_chunk_size(f),
+        locals: this.locals.len(),
+        depth: this.scope_depth,
+    };
+    if let Some(e) = target_gotos(this, &ident, &label) {
+        return Err(e);
     }
+    this.labels.insert(ident, label);
     Ok(())
 }
 
+/** Point the unresolved gotos that can see the label at it, a goto can't jump into the scope of a local declared after it */
+fn target_gotos(this: &mut Compiler, ident: &str, label: &GotoLabel) -> Option<ErrorTuple> {
+    let mut into_scope = None;
+    for pending in this.pending_gotos.iter_mut() {
+        if pending.target.is_some() || pending.depth < label.depth || pending.label != ident {
+            continue;
+        }
+        if pending.live < label.locals {
+            into_scope = Some(pending.location);
+        }
+        pending.target = Some((label.index, label.locals));
+    }
+    into_scope
+        .map(|location| this.error_syntax(SiltError::GotoIntoScope(ident.to_owned()), location))
+}
+
+/** Reserve the goto's ops, they're filled in by resolve_gotos once the function ends */
 fn goto_statement(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>) -> Catch {
     devnote!(this it "goto_statement");
     this.eat(it);
-    let token = this.pop(it).0?;
-    if let Token::Identifier(ident) = token {
-        let size = this.get_chunk_size(f);
-        resolve_goto(this, f, &*ident, size, None)?;
-        // match this.labels.get(ident) {
-        //     Some(i) => {
-        //         let c = this.chunk.code.len();
-        //         let o = *i;
-        //         if c > o {
-        //             let offset = c - o;
-        //             if offset > u16::MAX as usize {
-        //                 return Err(this.error_at(SiltError::TooManyOperations));
-        //             }
-        //             this.emit_at(OpCode::REWIND(offset as u16));
-        //         } else {
-        //             let offset = o - c;
-        //             if offset > u16::MAX as usize {
-        //                 return Err(this.error_at(SiltError::TooManyOperations));
-        //             }
-        //             this.emit_at(OpCode::FORWARD(offset as u16));
-        //         }
-        //     }
-        //     None => {
-        //         let index = this.emit_index(OpCode::FORWARD(0));
-        //         this.pending_gotos.push((ident, index));
-        //         // this.labels.insert(*ident, index);
-        //     }
-        // }
-    } else {
+    let (token, location) = this.pop(it);
+    let Token::Identifier(label) = token? else {
         return Err(this.error_at(SiltError::ExpectedGotoIdentifier));
+    };
+    let base = this.locals.len() + 1 - this.local_count;
+    let alive = &this.locals[base..];
+    let captured = alive.iter().map(|l| l.is_captured).collect::<Vec<bool>>();
+    let closing = alive
+        .iter()
+        .map(|l| l.known.attribute == Attribute::Close)
+        .collect::<Vec<bool>>();
+    // a label we've already passed is still in scope, otherwise we wait for it
+    let target = this.labels.get(&label).map(|l| (l.index, l.locals));
+    let index = this.get_chunk_size(f);
+    // a CLOSE, at most one op per local plus a leading empty pop, the spares stay as harmless POPS(0)
+    for _ in 0..captured.len() + 2 {
+        this.emit_at(f, OpCode::POPS(0));
     }
-
-    // n end_scope(this: &mut Compiler) {
-    //     this.scope_depth -= 1;
-    //     let mut i = 0;
-    //     while !this.locals.is_empty() && this.locals.last().unwrap().depth > this.scope_depth {
-    //         this.locals.pop();
-    //         i += 1;
-    //     }
-    //     this.emit_at(OpCode::POPN(i));
-    // }
-
+    this.emit_at(f, OpCode::FORWARD(0));
+    this.pending_gotos.push(PendingGoto {
+        label,
+        index,
+        location,
+        base,
+        captured,
+        closing,
+        live: this.locals.len(),
+        depth: this.scope_depth,
+        target,
+    });
     Ok(())
 }
 
-fn resolve_goto(
-    this: &mut Compiler,
-    f: FnRef,
-    ident: &str,
-    op_count: usize,
-    replace: Option<(usize, TokenCell)>,
-) -> Catch {
-    match this.labels.get(ident) {
-        Some(i) => {
-            let c = op_count;
-            let o = *i;
-            let code = if c > o {
-                let offset = c - o;
-                if offset > u16::MAX as usize {
-                    return Err(this.error_at(SiltError::TooManyOperations));
-                }
-                OpCode::REWIND(offset as u16)
-            } else {
-                let offset = o - c;
-                if offset > u16::MAX as usize {
-                    return Err(this.error_at(SiltError::TooManyOperations));
-                }
-                OpCode::FORWARD(offset as u16)
-            };
+/**
+ * Fill in every goto of the function now that it's fully compiled, labels closing out the function body point at `end` with none of the function's locals left
+ * Each goto closes and drops the locals between it and it's label before jumping
+ */
+fn resolve_gotos(this: &mut Compiler, f: FnRef, end: usize) {
+    let base = this.locals.len() + 1 - this.local_count;
+    let finale = GotoLabel {
+        index: end,
+        locals: base,
+        depth: this.scope_depth,
+    };
+    for (ident, _) in std::mem::take(&mut this.end_labels) {
+        if let Some(e) = target_gotos(this, &ident, &finale) {
+            this.push_error(e);
+        }
+    }
+    let offset = slot_offset(this);
+    for mut pending in std::mem::take(&mut this.pending_gotos) {
+        let Some((target, locals)) = pending.target else {
+            let e = this.error_syntax(SiltError::UndefinedLabel(pending.label), pending.location);
+            this.push_error(e);
+            continue;
+        };
+        // jumping into a local's scope was already reported
+        if locals > pending.base + pending.captured.len() {
+            continue;
+        }
+        // locals still alive may have been captured after the goto
+        for (c, l) in pending
+            .captured
+            .iter_mut()
+            .zip(&this.locals[pending.base..pending.live])
+        {
+            *c |= l.is_captured;
+        }
+        let left = locals - pending.base;
+        if let Some(i) = pending.closing.iter().skip(left).position(|c| *c) {
+            let index = (pending.base + left + i - offset) as u8;
+            this.change_code(f, pending.index, OpCode::CLOSE { index });
+        }
+        let reserved = pending.captured.len() + 1;
+        let codes = scope_exit_codes(pending.captured.drain(left..).rev());
+        for (i, op) in codes.into_iter().enumerate() {
+            this.change_code(f, pending.index + 1 + i, op);
+        }
+        let jump = pending.index + 1 + reserved;
+        let code = if target > jump {
+            OpCode::FORWARD((target - jump - 1) as u16)
+        } else {
+            OpCode::REWIND((jump + 1 - target) as u16)
+        };
+        if target.abs_diff(jump) > u16::MAX as usize {
+            let e = this.error_syntax(SiltError::TooManyOperations, pending.location);
+            this.push_error(e);
+        }
+        this.change_code(f, jump, code);
+    }
+}
 
-            match replace {
-                Some((i, _)) => {
-                    this.change_code(f, i, code);
+/** Pad or trim the values of the last expression list to exactly `need`, a trailing call is asked for the difference instead of padding with nils */
+fn balance_expressions(this: &mut Compiler, f: FnRef, need: isize) {
+    // a,b,c,d,e = 1, fn(), fn()
+    // 5 = 1, 2 , 3..
+    let remainder = need - this.expression_count as isize;
+    match remainder.cmp(&0) {
+        Ordering::Greater => {
+            // we have room so spread the last if possible
+            match f.chunk.read_last_code() {
+                OpCode::CALL(u, _) => {
+                    // the remainder is how much MORE we would need, at least 1 is
+                    // already assumed so we add 1+remainder
+                    f.chunk.patch_last(OpCode::CALL(*u, (remainder + 1) as u8));
                 }
-                None => {
-                    this.emit_at(f, code);
+                OpCode::CALL_OPEN(u, _) => {
+                    f.chunk
+                        .patch_last(OpCode::CALL_OPEN(*u, (remainder + 1) as u8));
                 }
+                OpCode::VARARGS(1) => {
+                    f.chunk.patch_last(OpCode::VARARGS((remainder + 1) as u8));
+                }
+                _ => this.emit_at(f, OpCode::NILS(remainder as u8)),
             }
         }
-        None => match replace {
-            Some((i, location)) => {
-                return Err(this.error_syntax(SiltError::UndefinedLabel(ident.to_owned()), location))
-            }
-            None => {
-                let index = this.emit_index(f, OpCode::FORWARD(0));
-                this.pending_gotos
-                    .push((ident.to_owned(), index, this.current_location));
-            } // this.labels.insert(*ident, index);
-        },
-    };
-    Ok(())
-}
-
-fn final_resolve_goto(this: &mut Compiler) {
-    this.pending_gotos
-        .iter()
-        .for_each(|(ident, index, location)| {});
+        Ordering::Less => {
+            // pop extra
+            this.emit_at(f, OpCode::POPS((-remainder) as u8));
+        }
+        Ordering::Equal => {}
+    }
 }
 
-fn goto_scope_skip(this: &mut Compiler, f: FnRef) {
-    if this.locals.is_empty() {
-        return;
+/** If the last value emitted was a call or a single `...` widen it to leave every value on the stack, the caller then emits the open variant of its instruction */
+fn open_last_values(this: &Compiler, f: FnRef) -> bool {
+    if this.closed_at == f.chunk.code.len() {
+        return false;
     }
-    let mut i = 0;
-    this.locals
-        .iter()
-        .rev()
-        .take_while(|l| l.depth > this.scope_depth)
-        .for_each(|_| {
-            i += 1;
-        });
-
-    this.emit_at(f, OpCode::POPS(i));
+    let open = match f.chunk.read_last_code() {
+        OpCode::VARARGS(1) => OpCode::VARARGS(0),
+        OpCode::CALL(a, 1) => OpCode::CALL(*a, 0),
+        OpCode::CALL_OPEN(a, 1) => OpCode::CALL_OPEN(*a, 0),
+        _ => return false,
+    };
+    f.chunk.patch_last(open);
+    true
 }
 
-fn expression(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>, skip_step: bool) -> Catch {
+fn expression<'c>(
+    this: &mut Compiler,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    it: &mut Peekable<Lexer>,
+    skip_step: bool,
+) -> Catch {
     devnote!(this it "expression");
-    this.parse_precedence(f, it, Precedence::Assignment, skip_step)?;
+    this.parse_precedence(mc, f, it, Precedence::Assignment, skip_step)?;
 
     while let Token::Comma = this.peek(it)? {
         add!(this);
         devout!("{}", "COMMAS".on_red());
         this.eat(it);
         devout!("===================exp count {}", this.expression_count);
-        this.parse_precedence(f, it, Precedence::Assignment, false)?;
+        this.parse_precedence(mc, f, it, Precedence::Assignment, false)?;
     }
 
     Ok(())
 }
 
 /// Walk through expression precedence but stop at commas, used by arguments, and table building
-fn expression_single(
+fn expression_single<'c>(
     this: &mut Compiler,
-    f: FnRef,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
     it: &mut Peekable<Lexer>,
     skip_step: bool,
 ) -> Catch {
     devnote!(this it "expression_single");
-    this.parse_precedence(f, it, Precedence::Assignment, skip_step)?;
-    Ok(())
-}
-
-fn next_expression(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>) -> Catch {
-    devnote!(this it "next_expression");
-    this.eat(it);
-    expression(this, f, it, false)?;
+    this.parse_precedence(mc, f, it, Precedence::Assignment, skip_step)?;
     Ok(())
 }
 
-fn expression_statement(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>) -> Catch {
+fn expression_statement<'c>(
+    this: &mut Compiler,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    it: &mut Peekable<Lexer>,
+) -> Catch {
     devnote!(this it "expression_statement");
     devout!(
         "{} {}",
@@ -1987,7 +3042,7 @@ fn expression_statement(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>)
         this.expression_count
     );
 
-    expression(this, f, it, false)?;
+    expression(this, mc, f, it, false)?;
 
     // Mark that the last statement was an expression for implicit returns
     this.last_was_expression = true;
@@ -2012,7 +3067,13 @@ fn expression_statement(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>)
     Ok(())
 }
 
-fn variable(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>, can_assign: bool) -> Catch {
+fn variable<'c>(
+    this: &mut Compiler,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    it: &mut Peekable<Lexer>,
+    can_assign: bool,
+) -> Catch {
     devnote!(this it "variable");
     // let t = this.previous.clone();
     // let ident = if let Token::Identifier(ident) = t.0 {
@@ -2028,7 +3089,7 @@ fn variable(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>, can_assign:
     //     this.emit(OpCode::LITERAL { dest: ident, literal: ident }, t.1);
     // }
 
-    named_variable(this, f, it, can_assign)?;
+    named_variable(this, mc, f, it, can_assign)?;
     Ok(())
 }
 
@@ -2038,10 +3099,16 @@ fn resolve_etters(
     it: &mut Peekable<Lexer>,
     ident: String,
 ) -> (OpCode, OpCode) {
+    // a folded const is read as it's literal, assigning to it is rejected before the setter is used
+    if let Some(literal) = declared_type(this, &ident).folded {
+        let getter = folded_code(this, f, &literal);
+        return (OpCode::NIL, getter);
+    }
     // TODO currently this mechanism searches the entire local stack to determine local and then up values,  ideally we check up values first once we raise out of the functional scope instead of continuing to walk the local stack, but this will work for now.
     match resolve_local(this, it, &ident) {
         Some((i, is_up)) => {
             if is_up {
+                f.chunk.set_upvalue_name(i, &ident);
                 (
                     OpCode::SET_UPVALUE { index: i },
                     OpCode::GET_UPVALUE { index: i },
@@ -2078,9 +3145,10 @@ fn print_var_stack(_v: &[(OpCode, OpCode)]) {
     }
 }
 
-fn named_variable(
+fn named_variable<'c>(
     this: &mut Compiler,
-    f: FnRef,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
     it: &mut Peekable<Lexer>,
     can_assign: bool,
 ) -> Catch {
@@ -2098,22 +3166,39 @@ fn named_variable(
     {
         if let Token::Identifier(ident) = t {
             // short declare
-            add_local(this, it, ident)?;
+            add_local(this, f, it, ident)?;
             this.override_pop = true;
             this.eat(it);
-            expression(this, f, it, false)?;
+            expression(this, mc, f, it, false)?;
+            let known = VariableType {
+                typing: Type::None,
+                inferred: this.expression_type,
+                signature: this.expression_signature.take(),
+                ..Default::default()
+            };
+            set_local_type(this, known);
         } else {
             unreachable!()
         }
         return Ok(());
     }
 
+    if let Token::ArrowFunction = this.peek(it)? {
+        if let Token::Identifier(ident) = t {
+            return arrow_function(this, mc, f, it, vec![ident]);
+        }
+    }
+
     // ident getter/setter gather for 1 variable, then continue on our while loop to check for
     // more. This should usually only hit for multi var assignment
 
-    let ops = if let Token::Identifier(ident) = t {
+    // every variable named here in case we end up assigning to them
+    let mut targets = vec![];
+    let (ops, declared) = if let Token::Identifier(ident) = t {
         // devout!("assigning to identifier: {}", ident);
-        resolve_etters(this, f, it, ident)
+        let declared = declared_type(this, &ident);
+        targets.push(ident.clone());
+        (resolve_etters(this, f, it, ident), declared)
     } else {
         unreachable!()
     };
@@ -2131,6 +3216,7 @@ fn named_variable(
                 this.current_location = t.1;
 
                 let ops = if let Token::Identifier(ident) = t.0? {
+                    targets.push(ident.clone());
                     resolve_etters(this, f, it, ident)
                 } else {
                     unreachable!()
@@ -2152,13 +3238,13 @@ fn named_variable(
                 // For retrieval context, we need to drain the getters we've collected so far
                 // and then continue parsing as a regular expression
                 // this.return_count = this.var_stack.len() as u8;
-                println!("multivar drain 1");
+                // println!("multivar drain 1");
                 this.drain_getters(f);
 
                 // Now parse the remaining expression starting from current position
                 // We need to handle this as part of a larger comma-separated expression
                 // this.return_count += 1;
-                this.parse_precedence(f, it, Precedence::Assignment, false)?;
+                this.parse_precedence(mc, f, it, Precedence::Assignment, false)?;
 
                 return Ok(());
             }
@@ -2194,6 +3280,9 @@ fn named_variable(
     match this.peek(it)? {
         Token::Assign => {
             if can_assign {
+                for target in targets.iter() {
+                    check_assignable(this, target)?;
+                }
                 this.eat(it);
                 let assign_need = this.var_stack.len() as isize;
                 this.expression_count = 1;
@@ -2204,8 +3293,18 @@ fn named_variable(
                 std::mem::swap(&mut this.var_stack, &mut this.var_set_stack);
                 this.override_pop = true;
                 this.can_multivar_set = false;
-                expression(this, f, it, false)?;
+                expression(this, mc, f, it, false)?;
                 this.can_multivar_set = true;
+                if assign_need == 1 && this.expression_count == 1 {
+                    check_type(this, declared.typing)?;
+                    let value = (this.expression_type, this.expression_signature.take());
+                    assign_inferred(this, &targets[0], Some(value));
+                } else {
+                    // we don't follow which value lands where
+                    for target in targets.iter() {
+                        assign_inferred(this, target, None);
+                    }
+                }
                 // println!("=============== setters? {}", this.var_stack.len());
                 print_var_stack(&this.var_set_stack);
                 print_var_stack(&this.var_stack);
@@ -2218,28 +3317,7 @@ fn named_variable(
 
                 // a,b,c,d,e = 1, fn(), fn()
                 // 5 = 1, 2 , 3..
-                let remainder = assign_need - this.expression_count as isize;
-                // println!("reaminder is {}", remainder);
-                match remainder.cmp(&0) {
-                    Ordering::Greater => {
-                        // we have room so spread the last if possible
-                        // let offset = this.current_index - 1;
-                        match f.chunk.read_last_code() {
-                            OpCode::CALL(u, _) => {
-                                // the remainder is how much MORE we would need, at least 1 is
-                                // already assumed so we add 1+remainder
-                                // devout!("{} {}", "modify call to ".red(), remainder + 1);
-                                f.chunk.patch_last(OpCode::CALL(*u, (remainder + 1) as u8));
-                            }
-                            _ => this.emit_at(f, OpCode::NILS(remainder as u8)),
-                        }
-                    }
-                    Ordering::Less => {
-                        // pop extra
-                        this.emit_at(f, OpCode::POPS((-remainder) as u8));
-                    }
-                    Ordering::Equal => {}
-                }
+                balance_expressions(this, f, assign_need);
                 // for _ in 0..remainder {
                 //     this.emit_at(f, OpCode::NIL);
                 // }
@@ -2249,38 +3327,80 @@ fn named_variable(
             } else {
                 // this.return_count = this.var_stack.len() as u8;
                 // println!("multivar drain 3");
+                getter_type(this, declared);
                 this.drain_getters(f);
             }
         }
+        Token::AddAssign
+        | Token::SubAssign
+        | Token::MultiplyAssign
+        | Token::DivideAssign
+        | Token::ModulusAssign => {
+            if !can_assign || this.var_stack.len() > 1 {
+                let t = this.peek(it)?.clone();
+                return Err(this.error_at(SiltError::InvalidAssignment(t)));
+            }
+            check_assignable(this, &targets[0])?;
+            // the single var was never drained so we emit it's getter and setter ourselves
+            let (setter, getter) = this.var_stack.pop().unwrap();
+            this.emit_at(f, getter);
+            variable_expression(this, declared.clone());
+            compound_assignment(this, mc, f, it)?;
+            check_type(this, declared.typing)?;
+            assign_inferred(this, &targets[0], Some((this.expression_type, None)));
+            this.emit_at(f, setter);
+            this.emit_at(f, OpCode::POP);
+            this.override_pop = true;
+        }
         Token::OpenBracket | Token::Dot => {
             // println!("drain 4");
-            this.drain_getters(f); // TODO we should probably error if this is higher then 1
-            let count = table_indexer(this, f, it)? as u8;
+            // any names before the path are plain variables, read them in case this isn't an assignment
+            let base = this.var_stack.pop().unwrap();
+            let pending: Vec<_> = this.var_stack.drain(..).collect();
+            let mut list = vec![];
+            let mut reads = vec![];
+            for ((setter, getter), target) in pending.into_iter().zip(targets) {
+                reads.push(this.emit_index(f, getter));
+                list.push(AssignTarget::Variable(setter, target));
+            }
+            this.emit_at(f, base.1);
+            let count = table_indexer(this, mc, f, it)? as u8;
+            if !list.is_empty()
+                || (can_assign && this.can_multivar_set && matches!(this.peek(it)?, Token::Comma))
+            {
+                reads.push(this.emit_index(f, OpCode::TABLE_GET { depth: count }));
+                list.push(AssignTarget::Field(count));
+                return field_targets(this, mc, f, it, can_assign, list, reads);
+            }
             if let Token::Assign = this.peek(it)? {
                 this.eat(it);
-                expression(this, f, it, false)?;
+                expression(this, mc, f, it, false)?;
                 this.emit_at(f, OpCode::TABLE_SET { depth: count });
                 // override statement end pop because instruction takes care of it
                 this.override_pop = true;
+            } else if compound_operator(this.peek(it)?).is_some() {
+                if !can_assign {
+                    let t = this.peek(it)?.clone();
+                    return Err(this.error_at(SiltError::InvalidAssignment(t)));
+                }
+                // read through a copy of the path so the keys are only evaluated once
+                this.emit_at(f, OpCode::DUPLICATE(count + 1));
+                this.emit_at(f, OpCode::TABLE_GET { depth: count });
+                this.expression_type = Type::None;
+                this.expression_inferred = false;
+                compound_assignment(this, mc, f, it)?;
+                this.emit_at(f, OpCode::TABLE_SET { depth: count });
+                this.override_pop = true;
             } else {
                 this.emit_at(f, OpCode::TABLE_GET { depth: count });
                 // add!(this);
             }
         }
-        Token::Colon => {
-            let target = this.pull_getter(f);
-            this.drain_getters(f);
-            single_table_index(this, f, it)?;
-            // we should only have one getter, table_indexer is just a faster getter opcode
-            // sequence anyway
-            this.emit_at(f, OpCode::TABLE_GET { depth: 1 });
-            this.emit_at(f, target);
-            this.self_arg = true;
-        }
         _ => {
             // this.return_count = this.var_stack.len() as u8;
             // devnote!(this it "drain 5");
 
+            getter_type(this, declared);
             this.drain_getters(f);
         }
     }
@@ -2293,7 +3413,7 @@ fn named_variable(
     //     }
     // {
     //     this.eat();
-    //     expression(this, false)?;
+    //     expression(this, mc, false)?;
     //     this.emit_at(setter);
     // } else {
     //     this.emit_at(getter);
@@ -2301,7 +3421,7 @@ fn named_variable(
 
     // if let &Token::Assign = this.get_current()? {
     //     let loc = this.current_location;
-    //     expression(this, false)?;
+    //     expression(this, mc, false)?;
     //     this.emit(OpCode::DEFINE_GLOBAL { constant: ident }, loc);
     // } else {
     //     this.emit_at(OpCode::GET_GLOBAL { constant: ident });
@@ -2309,22 +3429,352 @@ fn named_variable(
     Ok(())
 }
 
-fn grouping(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>, _can_assign: bool) -> Catch {
-    devnote!(this it "-> grouping");
-    expression(this, f, it, false)?;
-    //TODO expect
-    // expect_token!(self, CloseParen, SiltError::UnterminatedParenthesis(0, 0));
-    // self.consume(TokenType::RightParen, "Expect ')' after expression.");
-    Ok(())
-}
-
-fn tabulate(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>, _can_assign: bool) -> Catch {
-    devnote!(this it "-> tabulate");
-    this.emit_at(f, OpCode::NEW_TABLE);
-    // not immediately closed
-    if !matches!(this.peek(it)?, &Token::CloseBrace) {
-        let mut count = 0;
-        // check if index provided via brackets or ident, otherwise increment our count to build array at the end
+/** the rest of a list like `t.x, a[i], y` once a table path shows up. Until we reach an `=` it may only be reads, so each target is read as we go and those reads are blanked out if it's an assignment. Every value is evaluated before the first store, which then works back from the last target */
+fn field_targets<'c>(
+    this: &mut Compiler,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    it: &mut Peekable<Lexer>,
+    can_assign: bool,
+    mut list: Vec<AssignTarget>,
+    mut reads: Vec<usize>,
+) -> Catch {
+    while let Token::Comma = this.peek(it)? {
+        add!(this);
+        this.eat(it);
+        if !matches!(this.peek(it)?, Token::Identifier(_)) {
+            let t = this.peek(it)?;
+            if can_assign && matches!(t, Token::Assign) {
+                return Err(this.error_at(SiltError::InvalidAssignment(t.clone())));
+            }
+            // can't be assigned to so this was a list of values all along
+            this.can_multivar_set = false;
+            return this.parse_precedence(mc, f, it, Precedence::Assignment, false);
+        }
+        let t = this.pop(it);
+        this.current_location = t.1;
+        let ident = if let Token::Identifier(ident) = t.0? {
+            ident
+        } else {
+            unreachable!()
+        };
+        let (setter, getter) = resolve_etters(this, f, it, ident.clone());
+        if let Token::OpenBracket | Token::Dot = this.peek(it)? {
+            this.emit_at(f, getter);
+            let depth = table_indexer(this, mc, f, it)? as u8;
+            reads.push(this.emit_index(f, OpCode::TABLE_GET { depth }));
+            list.push(AssignTarget::Field(depth));
+        } else {
+            reads.push(this.emit_index(f, getter));
+            list.push(AssignTarget::Variable(setter, ident));
+        }
+    }
+
+    if !(can_assign && matches!(this.peek(it)?, Token::Assign)) {
+        this.expression_type = Type::None;
+        return Ok(());
+    }
+    for target in list.iter() {
+        if let AssignTarget::Variable(_, ident) = target {
+            check_assignable(this, ident)?;
+        }
+    }
+    // the paths stay on the stack for the stores
+    for index in reads {
+        this.change_code(f, index, OpCode::POPS(0));
+    }
+    this.eat(it);
+    this.expression_count = 1;
+    this.override_pop = true;
+    this.can_multivar_set = false;
+    expression(this, mc, f, it, false)?;
+    this.can_multivar_set = true;
+    balance_expressions(this, f, list.len() as isize);
+
+    let held: usize = list
+        .iter()
+        .map(|target| match target {
+            AssignTarget::Field(depth) => *depth as usize + 1,
+            AssignTarget::Variable(..) => 0,
+        })
+        .sum();
+    // slots held by the paths of the targets before the current one
+    let mut below = held;
+    for (i, target) in list.into_iter().enumerate().rev() {
+        match target {
+            AssignTarget::Variable(setter, ident) => {
+                this.emit_at(f, setter);
+                this.emit_at(f, OpCode::POP);
+                assign_inferred(this, &ident, None);
+            }
+            AssignTarget::Field(depth) => {
+                below -= depth as usize + 1;
+                // values still waiting below this one plus the path slots from this table up
+                let offset = i + held - below;
+                if offset > u8::MAX as usize {
+                    return Err(this.error_at(SiltError::TooManyLocals));
+                }
+                this.emit_at(
+                    f,
+                    OpCode::TABLE_ASSIGN {
+                        offset: offset as u8,
+                        depth,
+                    },
+                );
+            }
+        }
+    }
+    if held > 0 {
+        this.emit_at(f, OpCode::POPS(held as u8));
+    }
+    Ok(())
+}
+
+/** `<const>` and `<close>` variables can only be given a value when declared */
+fn check_assignable(this: &mut Compiler, ident: &str) -> Catch {
+    if declared_type(this, ident).attribute == Attribute::None {
+        Ok(())
+    } else {
+        Err(this.error_at(SiltError::AssignToConst(ident.to_string())))
+    }
+}
+
+/** a lone variable read carries what we know of it, a list of them isn't a single value */
+fn getter_type(this: &mut Compiler, declared: VariableType) {
+    if this.var_stack.len() == 1 {
+        variable_expression(this, declared);
+    }
+}
+
+/** `+=` and friends with the operator and op code they apply */
+fn compound_operator(token: &Token) -> Option<(Operator, OpCode)> {
+    match token {
+        Token::AddAssign => Some((Operator::Add, OpCode::ADD)),
+        Token::SubAssign => Some((Operator::Sub, OpCode::SUB)),
+        Token::MultiplyAssign => Some((Operator::Multiply, OpCode::MULTIPLY)),
+        Token::DivideAssign => Some((Operator::Divide, OpCode::DIVIDE)),
+        Token::ModulusAssign => Some((Operator::Modulus, OpCode::MODULUS)),
+        _ => None,
+    }
+}
+
+/** the target's current value is on the stack and the compound operator is up next, apply it with the following expression. Leaves the new value for the setter */
+fn compound_assignment<'c>(
+    this: &mut Compiler,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    it: &mut Peekable<Lexer>,
+) -> Catch {
+    devnote!(this it "compound_assignment");
+    let (res, location) = this.pop(it);
+    this.current_location = location;
+    let (op, code) = match compound_operator(&res?) {
+        Some(pair) => pair,
+        None => unreachable!(),
+    };
+    let left = this.expression_type;
+    let left_inferred = this.expression_inferred;
+    this.can_multivar_set = false;
+    expression_single(this, mc, f, it, false)?;
+    this.can_multivar_set = true;
+    let right = this.expression_type;
+    operand_warnings(this, &op, left, right, location);
+    this.expression_type = operator_type(&op, left, right);
+    this.expression_inferred |= left_inferred;
+    this.expression_signature = None;
+    this.emit(f, code, location);
+    Ok(())
+}
+
+fn grouping<'c>(
+    this: &mut Compiler,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    it: &mut Peekable<Lexer>,
+    _can_assign: bool,
+) -> Catch {
+    devnote!(this it "-> grouping");
+    let start = this.current_location;
+    // a group of bare identifiers may turn out to be arrow function parameters
+    match this.peek(it)? {
+        Token::CloseParen => {
+            this.eat(it);
+            if let Token::ArrowFunction = this.peek(it)? {
+                return arrow_function(this, mc, f, it, vec![]);
+            }
+            return Err(this.error_at(SiltError::ExpInvalid));
+        }
+        Token::Identifier(_) => {
+            this.store(it);
+            match this.peek(it)? {
+                Token::Comma => {
+                    let params = arrow_parameters(this, it, start)?;
+                    return arrow_function(this, mc, f, it, params);
+                }
+                Token::CloseParen => {
+                    this.eat(it);
+                    let ident = match this.copy_store()? {
+                        Token::Identifier(ident) => ident,
+                        _ => unreachable!(),
+                    };
+                    if let Token::ArrowFunction = this.peek(it)? {
+                        return arrow_function(this, mc, f, it, vec![ident]);
+                    }
+                    let known = declared_type(this, &ident);
+                    variable_expression(this, known);
+                    let (_, getter) = resolve_etters(this, f, it, ident);
+                    this.emit_at(f, getter);
+                    return Ok(());
+                }
+                // carry on with the identifier as the start of the expression
+                _ => expression(this, mc, f, it, true)?,
+            }
+        }
+        _ => expression(this, mc, f, it, false)?,
+    }
+    expect_token!(
+        this,
+        it,
+        CloseParen,
+        this.error_at(SiltError::UnterminatedParenthesis(start.0, start.1))
+    );
+    // `(f())` is only ever the first value
+    this.closed_at = this.get_chunk_size(f);
+    Ok(())
+}
+
+/** Rest of a `(a, b, ...)` parameter list for an arrow function, the first identifier is stored */
+fn arrow_parameters(
+    this: &mut Compiler,
+    it: &mut Peekable<Lexer>,
+    start: TokenCell,
+) -> Result<Vec<String>, ErrorTuple> {
+    let mut params = match this.copy_store()? {
+        Token::Identifier(ident) => vec![ident],
+        _ => unreachable!(),
+    };
+    while let Token::Comma = this.peek(it)? {
+        this.eat(it);
+        let (res, location) = this.pop(it);
+        this.current_location = location;
+        match res? {
+            Token::Identifier(ident) => params.push(ident),
+            _ => return Err(this.error_at(SiltError::ExpectedLocalIdentifier)),
+        }
+    }
+    expect_token!(
+        this,
+        it,
+        CloseParen,
+        this.error_at(SiltError::UnterminatedParenthesis(start.0, start.1))
+    );
+    if !matches!(this.peek(it)?, Token::ArrowFunction) {
+        return Err(this.error_at(SiltError::ExpectedToken(Token::ArrowFunction)));
+    }
+    Ok(params)
+}
+
+fn arrow_functions_enabled(this: &Compiler) -> bool {
+    cfg!(feature = "arrow-function") && this.language_flags.arrow_functions
+}
+
+/** `a -> expr`, `(a, b) -> expr` or `() -> do ... end` with the arrow up next. The body always returns it's last expression regardless of language flags */
+fn arrow_function<'c>(
+    this: &mut Compiler,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    it: &mut Peekable<Lexer>,
+    params: Vec<String>,
+) -> Catch {
+    devnote!(this it "arrow_function");
+    if !arrow_functions_enabled(this) {
+        return Err(this.error_at(SiltError::ArrowFunctionsDisabled));
+    }
+    this.eat(it);
+    if params.len() > 255 {
+        return Err(this.error_at(SiltError::TooManyParameters));
+    }
+    // we're in the middle of the enclosing expression, keep it's state away from the body
+    let expression_count = this.expression_count;
+    let last_was_expression = this.last_was_expression;
+    let can_multivar_set = this.can_multivar_set;
+    let override_pop = this.override_pop;
+    let var_stack = std::mem::take(&mut this.var_stack);
+    let var_set_stack = std::mem::take(&mut this.var_set_stack);
+    let return_type = std::mem::replace(&mut this.return_type, Type::None);
+    let inferred_return = this.inferred_return.take();
+
+    let mut f2 = FunctionObject::new(Some("arrow".to_string()), false);
+    f2.arity = params.len() as u8;
+    let arity = params.len();
+    begin_scope(this);
+    begin_functional_scope(this);
+    for param in params {
+        add_local(this, &mut f2, it, param)?;
+    }
+    if let Token::Do = this.peek(it)? {
+        this.eat(it);
+        function_body(this, mc, f, it, f2, false, true)?;
+    } else {
+        let outer_loops = std::mem::take(&mut this.loops);
+        let outer_variadic = std::mem::replace(&mut this.variadic, false);
+        this.can_multivar_set = false;
+        expression_single(this, mc, &mut f2, it, false)?;
+        let returned = this.expression_type;
+        infer_return(this, returned);
+        this.loops = outer_loops;
+        this.variadic = outer_variadic;
+        this.emit_at(&mut f2, OpCode::RETURN(1));
+        close_function(this, mc, f, f2);
+    }
+
+    this.expression_count = expression_count;
+    this.last_was_expression = last_was_expression;
+    this.can_multivar_set = can_multivar_set;
+    this.override_pop = override_pop;
+    this.var_stack = var_stack;
+    this.var_set_stack = var_set_stack;
+    this.return_type = return_type;
+    let inferred_returns = std::mem::replace(&mut this.inferred_return, inferred_return);
+    this.expression_type = Type::Function;
+    this.expression_inferred = false;
+    this.expression_signature = Some(FunctionType {
+        params: vec![Type::None; arity],
+        returns: Type::None,
+        inferred_returns: inferred_returns.unwrap_or_default(),
+    });
+    Ok(())
+}
+
+/** an arrow that doesn't follow parameters */
+fn misplaced_arrow<'c>(
+    this: &mut Compiler,
+    _mc: &Mutation<'c>,
+    _f: FnRef<'_, 'c>,
+    _it: &mut Peekable<Lexer>,
+    _can_assign: bool,
+) -> Catch {
+    if arrow_functions_enabled(this) {
+        Err(this.error_at(SiltError::InvalidTokenPlacement(Token::ArrowFunction)))
+    } else {
+        Err(this.error_at(SiltError::ArrowFunctionsDisabled))
+    }
+}
+
+fn tabulate<'c>(
+    this: &mut Compiler,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    it: &mut Peekable<Lexer>,
+    _can_assign: bool,
+) -> Catch {
+    devnote!(this it "-> tabulate");
+    this.emit_at(f, OpCode::NEW_TABLE);
+    // not immediately closed
+    if !matches!(this.peek(it)?, &Token::CloseBrace) {
+        let mut count = 0;
+        let mut open;
+        // check if index provided via brackets or ident, otherwise increment our count to build array at the end
         while {
             let start_brace = this.current_location;
             if match this.peek(it)? {
@@ -2336,13 +3786,13 @@ fn tabulate(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>, _can_assign
                         this.eat(it);
                         true
                     } else {
-                        expression_single(this, f, it, true)?; // we skip the store because the ip is already where it needs to be
+                        expression_single(this, mc, f, it, true)?; // we skip the store because the ip is already where it needs to be
                         false
                     }
                 }
                 Token::OpenBracket => {
                     this.eat(it);
-                    expression_single(this, f, it, false)?;
+                    expression_single(this, mc, f, it, false)?;
                     expect_token!(
                         this,
                         it,
@@ -2353,14 +3803,16 @@ fn tabulate(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>, _can_assign
                     true
                 }
                 _ => {
-                    expression_single(this, f, it, false)?; // normal store expression
+                    expression_single(this, mc, f, it, false)?; // normal store expression
                     false
                 }
             } {
-                expression_single(this, f, it, false)?;
+                expression_single(this, mc, f, it, false)?;
                 this.emit_at(f, OpCode::TABLE_INSERT { offset: count });
+                open = false;
             } else {
                 count += 1;
+                open = true;
             }
 
             match this.peek(it)? {
@@ -2376,7 +3828,10 @@ fn tabulate(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>, _can_assign
             //     return Err(this.error_at(SiltError::TooManyParameters));
             // }
         }
-        if count > 0 {
+        // a trailing call or `...` spreads all of its values into the array part
+        if open && matches!(this.peek(it)?, Token::CloseBrace) && open_last_values(this, f) {
+            this.emit_at(f, OpCode::TABLE_BUILD_OPEN(count - 1));
+        } else if count > 0 {
             this.emit_at(f, OpCode::TABLE_BUILD(count));
         }
     }
@@ -2387,20 +3842,54 @@ fn tabulate(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>, _can_assign
         CloseBrace,
         this.error_at(SiltError::TableExpectedCommaOrCloseBrace)
     );
+    this.expression_type = Type::Table;
     Ok(())
 }
 
 /** op unary or primary */
-fn unary(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>, _can_assign: bool) -> Catch {
+fn unary<'c>(
+    this: &mut Compiler,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    it: &mut Peekable<Lexer>,
+    _can_assign: bool,
+) -> Catch {
     devnote!(this it "unary");
     let t = this.copy_store()?;
     // self.expression();
 
-    this.parse_precedence(f, it, Precedence::Unary, false)?;
+    this.parse_precedence(mc, f, it, Precedence::Unary, false)?;
+    let operand = this.expression_type;
+    this.expression_signature = None;
     match t {
-        Token::Op(Operator::Sub) => this.emit_at(f, OpCode::NEGATE),
-        Token::Op(Operator::Not) => this.emit_at(f, OpCode::NOT),
-        Token::Op(Operator::Length) => this.emit_at(f, OpCode::LENGTH),
+        Token::Op(Operator::Sub) => {
+            operand_warnings(
+                this,
+                &Operator::Sub,
+                operand,
+                Type::None,
+                this.current_location,
+            );
+            this.emit_at(f, OpCode::NEGATE);
+            this.expression_type = arithmetic_type(operand, operand, false);
+        }
+        Token::Op(Operator::Not) => {
+            this.emit_at(f, OpCode::NOT);
+            this.expression_type = Type::Boolean;
+        }
+        Token::Op(Operator::Length) => {
+            this.emit_at(f, OpCode::LENGTH);
+            // tables and userdata may answer with __len
+            this.expression_type = if operand == Type::String {
+                Type::Integer
+            } else {
+                Type::None
+            };
+        }
+        Token::Op(Operator::Tilde) => {
+            this.emit_at(f, OpCode::BIT_NOT);
+            this.expression_type = bitwise_type(operand, operand);
+        }
         _ => {}
     }
     //     let operator = Self::de_op(self.eat_out());
@@ -2418,16 +3907,17 @@ fn unary(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>, _can_assign: b
 }
 
 /// Walk down multiple table fields if necessary table1.table2.table3.field
-fn table_indexer(
+fn table_indexer<'c>(
     this: &mut Compiler,
-    f: FnRef,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
     it: &mut Peekable<Lexer>,
 ) -> Result<usize, ErrorTuple> {
     let mut count = 0;
     while match this.peek(it)? {
         Token::OpenBracket => {
             this.eat(it);
-            expression(this, f, it, false)?;
+            expression(this, mc, f, it, false)?;
             expect_token!(
                 this,
                 it,
@@ -2465,22 +3955,43 @@ fn single_table_index(
     Ok(())
 }
 
-fn binary(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>, _can_assign: bool) -> Catch {
+fn binary<'c>(
+    this: &mut Compiler,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    it: &mut Peekable<Lexer>,
+    _can_assign: bool,
+) -> Catch {
     devnote!(this it "binary");
     let t = this.copy_store()?;
     let l = this.current_location;
     let rule = Compiler::get_rule(&t);
-    this.parse_precedence(f, it, rule.precedence.next(), false)?;
+    let left = this.expression_type;
+    let left_inferred = this.expression_inferred;
+    this.parse_precedence(mc, f, it, rule.precedence.next(), false)?;
+    let right = this.expression_type;
+    this.expression_signature = None;
+    this.expression_inferred |= left_inferred;
+    if let Token::Op(op) = &t {
+        operand_warnings(this, op, left, right, l);
+        this.expression_type = operator_type(op, left, right);
+    }
     if let Token::Op(op) = t {
         match op {
             Operator::Add => this.emit(f, OpCode::ADD, l),
             Operator::Sub => this.emit(f, OpCode::SUB, l),
             Operator::Multiply => this.emit(f, OpCode::MULTIPLY, l),
             Operator::Divide => this.emit(f, OpCode::DIVIDE, l),
+            Operator::FloorDivide => this.emit(f, OpCode::FLOOR_DIVIDE, l),
+            Operator::Modulus => this.emit(f, OpCode::MODULUS, l),
+            Operator::BitAnd => this.emit(f, OpCode::BIT_AND, l),
+            Operator::BitOr => this.emit(f, OpCode::BIT_OR, l),
+            Operator::Tilde => this.emit(f, OpCode::BIT_XOR, l),
+            Operator::ShiftLeft => this.emit(f, OpCode::SHIFT_LEFT, l),
+            Operator::ShiftRight => this.emit(f, OpCode::SHIFT_RIGHT, l),
 
             Operator::Concat => this.emit(f, OpCode::CONCAT, l),
 
-            // Operator::Modulus => self.emit(OpCode::MODULUS, t.1),
             // Operator::Equal => self.emit(OpCode::EQUAL, t.1),
             Operator::Equal => this.emit(f, OpCode::EQUAL, l),
             Operator::NotEqual => this.emit(f, OpCode::NOT_EQUAL, l),
@@ -2495,12 +4006,149 @@ fn binary(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>, _can_assign:
     Ok(())
 }
 
-fn concat(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>, _can_assign: bool) -> Catch {
+/** result type of an operator when both operands are known primitives, tables and userdata may have metamethods so they tell us nothing */
+fn operator_type(op: &Operator, left: Type, right: Type) -> Type {
+    match op {
+        Operator::Add
+        | Operator::Sub
+        | Operator::Multiply
+        | Operator::FloorDivide
+        | Operator::Modulus => arithmetic_type(left, right, false),
+        Operator::Divide | Operator::Exponent => arithmetic_type(left, right, true),
+        Operator::BitAnd
+        | Operator::BitOr
+        | Operator::Tilde
+        | Operator::ShiftLeft
+        | Operator::ShiftRight => bitwise_type(left, right),
+        Operator::Concat => match (left, right) {
+            (
+                Type::String | Type::Number | Type::Integer,
+                Type::String | Type::Number | Type::Integer,
+            ) => Type::String,
+            _ => Type::None,
+        },
+        Operator::Equal
+        | Operator::NotEqual
+        | Operator::Less
+        | Operator::LessEqual
+        | Operator::Greater
+        | Operator::GreaterEqual => Type::Boolean,
+        _ => Type::None,
+    }
+}
+
+/** warn on the first operand that can only fail, tables and userdata are spared for arithmetic as they may have metamethods */
+fn operand_warnings(
+    this: &mut Compiler,
+    op: &Operator,
+    left: Type,
+    right: Type,
+    location: TokenCell,
+) {
+    let found = |invalid: fn(Type) -> bool| [left, right].into_iter().find(|t| invalid(*t));
+    let code = match op {
+        Operator::Add
+        | Operator::Sub
+        | Operator::Multiply
+        | Operator::Divide
+        | Operator::FloorDivide
+        | Operator::Modulus
+        | Operator::Exponent => {
+            found(|t| matches!(t, Type::Boolean | Type::Nil | Type::Function | Type::Thread))
+                .map(SiltError::AttemptArithmetic)
+        }
+        Operator::Concat => found(|t| {
+            matches!(
+                t,
+                Type::Boolean | Type::Nil | Type::Function | Type::Table | Type::Thread
+            )
+        })
+        .map(SiltError::AttemptConcat),
+        _ => None,
+    };
+    if let Some(code) = code {
+        this.warn(code, location);
+    }
+}
+
+/** warn if the value about to be called can't be, tables and userdata may have __call */
+fn callee_warning(this: &mut Compiler, location: TokenCell) {
+    let t = this.expression_type;
+    if matches!(
+        t,
+        Type::Number | Type::Integer | Type::String | Type::Boolean | Type::Nil
+    ) {
+        this.warn(SiltError::AttemptCall(t), location);
+    }
+}
+
+/** result of a call, an annotated return is guaranteed while one inferred from the body is a hint */
+fn call_result_type(this: &mut Compiler, signature: Option<FunctionType>) {
+    (this.expression_type, this.expression_inferred) = match signature {
+        Some(s) if s.returns != Type::None => (s.returns, false),
+        Some(s) => (s.inferred_returns, true),
+        None => (Type::None, false),
+    };
+    this.expression_signature = None;
+}
+
+/** integers stay integers unless the operation always produces a float */
+fn arithmetic_type(left: Type, right: Type, float: bool) -> Type {
+    match (left, right) {
+        (Type::Integer, Type::Integer) if !float => Type::Integer,
+        (Type::Integer | Type::Number, Type::Integer | Type::Number) => Type::Number,
+        _ => Type::None,
+    }
+}
+
+fn bitwise_type(left: Type, right: Type) -> Type {
+    match (left, right) {
+        (Type::Integer | Type::Number, Type::Integer | Type::Number) => Type::Integer,
+        _ => Type::None,
+    }
+}
+
+/** right associative and binds tighter than unary on its left, so `-2^2` is -(2^2) while `2^-2` still parses */
+fn exponent<'c>(
+    this: &mut Compiler,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    it: &mut Peekable<Lexer>,
+    _can_assign: bool,
+) -> Catch {
+    devnote!(this it "exponent");
+    let l = this.current_location;
+    let left = this.expression_type;
+    let left_inferred = this.expression_inferred;
+    this.parse_precedence(mc, f, it, Precedence::Unary, false)?;
+    let right = this.expression_type;
+    operand_warnings(this, &Operator::Exponent, left, right, l);
+    this.expression_type = operator_type(&Operator::Exponent, left, right);
+    this.expression_inferred |= left_inferred;
+    this.expression_signature = None;
+    this.emit(f, OpCode::EXPONENT, l);
+    Ok(())
+}
+
+fn concat<'c>(
+    this: &mut Compiler,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    it: &mut Peekable<Lexer>,
+    _can_assign: bool,
+) -> Catch {
     devnote!(this it "concat_binary");
     let t = this.copy_store()?;
     let l = this.current_location;
     let rule = Compiler::get_rule(&t);
-    this.parse_precedence(f, it, rule.precedence.next(), false)?;
+    let left = this.expression_type;
+    let left_inferred = this.expression_inferred;
+    this.parse_precedence(mc, f, it, rule.precedence.next(), false)?;
+    let right = this.expression_type;
+    operand_warnings(this, &Operator::Concat, left, right, l);
+    this.expression_type = operator_type(&Operator::Concat, left, right);
+    this.expression_inferred |= left_inferred;
+    this.expression_signature = None;
 
     if let Token::Op(op) = t {
         match op {
@@ -2511,16 +4159,32 @@ fn concat(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>, _can_assign:
     Ok(())
 }
 
-fn and(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>, _can_assign: bool) -> Catch {
+fn and<'c>(
+    this: &mut Compiler,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    it: &mut Peekable<Lexer>,
+    _can_assign: bool,
+) -> Catch {
     devnote!(this it "and");
     let index = this.emit_index(f, OpCode::GOTO_IF_FALSE(0));
     this.emit_at(f, OpCode::POP);
-    this.parse_precedence(f, it, Precedence::And, false)?;
+    this.parse_precedence(mc, f, it, Precedence::And, false)?;
     this.patch(f, index)?;
+    this.closed_at = this.get_chunk_size(f);
+    // either side may be the result
+    this.expression_type = Type::None;
+    this.expression_signature = None;
     Ok(())
 }
 
-fn or(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>, _can_assign: bool) -> Catch {
+fn or<'c>(
+    this: &mut Compiler,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    it: &mut Peekable<Lexer>,
+    _can_assign: bool,
+) -> Catch {
     devnote!(this it "or");
 
     // the goofy way
@@ -2533,13 +4197,22 @@ fn or(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>, _can_assign: bool
 
     let index = this.emit_index(f, OpCode::GOTO_IF_TRUE(0));
     this.emit_at(f, OpCode::POP);
-    this.parse_precedence(f, it, Precedence::Or, false)?;
+    this.parse_precedence(mc, f, it, Precedence::Or, false)?;
     this.patch(f, index)?;
+    this.closed_at = this.get_chunk_size(f);
+    this.expression_type = Type::None;
+    this.expression_signature = None;
     Ok(())
 }
 
-fn integer(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>, _can_assign: bool) -> Catch {
-    devnote!(this it "integer");
+fn integer<'c>(
+    this: &mut Compiler,
+    _mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    _it: &mut Peekable<Lexer>,
+    _can_assign: bool,
+) -> Catch {
+    devnote!(this _it "integer");
     let t = this.copy_store()?;
     let value = if let Token::Integer(i) = t {
         Value::Integer(i)
@@ -2547,11 +4220,18 @@ fn integer(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>, _can_assign:
         unreachable!()
     };
     this.constant_at(f, value);
+    this.expression_type = Type::Integer;
     Ok(())
 }
 
-fn number(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>, _can_assign: bool) -> Catch {
-    devnote!(this it "number");
+fn number<'c>(
+    this: &mut Compiler,
+    _mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    _it: &mut Peekable<Lexer>,
+    _can_assign: bool,
+) -> Catch {
+    devnote!(this _it "number");
     let t = this.copy_store()?;
     let value = if let Token::Number(n) = t {
         Value::Number(n)
@@ -2559,11 +4239,18 @@ fn number(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>, _can_assign:
         unreachable!()
     };
     this.constant_at(f, value);
+    this.expression_type = Type::Number;
     Ok(())
 }
 
-fn string(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>, _can_assign: bool) -> Catch {
-    devnote!(this it "string");
+fn string<'c>(
+    this: &mut Compiler,
+    _mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    _it: &mut Peekable<Lexer>,
+    _can_assign: bool,
+) -> Catch {
+    devnote!(this _it "string");
     let t = this.copy_store()?;
     let value = if let Token::StringLiteral(s) = t {
         Value::String(s.into_string())
@@ -2571,11 +4258,66 @@ fn string(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>, _can_assign:
         unreachable!()
     };
     this.constant_at(f, value);
+    this.expression_type = Type::String;
     Ok(())
 }
 
-fn literal(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>, _can_assign: bool) -> Catch {
-    devnote!(this it "literal");
+/** backtick template, each `{expr}` is concatenated onto the text before it so it takes on CONCAT's tostring conversion */
+fn template<'c>(
+    this: &mut Compiler,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    it: &mut Peekable<Lexer>,
+    _can_assign: bool,
+) -> Catch {
+    devnote!(this it "template");
+    if !(cfg!(feature = "interpolation") && this.language_flags.interpolation) {
+        return Err(this.error_at(SiltError::InterpolationDisabled));
+    }
+    let head = match this.copy_store()? {
+        Token::TemplateString(s) => {
+            this.constant_at(f, Value::String(s.into_string()));
+            this.expression_type = Type::String;
+            return Ok(());
+        }
+        Token::TemplateHead(s) => s,
+        _ => unreachable!(),
+    };
+    // even an empty head is kept so a lone `{expr}` still becomes a string
+    this.constant_at(f, Value::String(head.into_string()));
+    loop {
+        let l = this.current_location;
+        expression_single(this, mc, f, it, false)?;
+        this.emit(f, OpCode::CONCAT, l);
+        let (res, location) = this.pop(it);
+        this.current_location = location;
+        let (text, last) = match res? {
+            Token::TemplateMiddle(s) => (s, false),
+            Token::TemplateTail(s) => (s, true),
+            _ => return Err(this.error_at(SiltError::ExpectedToken(Token::CloseBrace))),
+        };
+        if !text.is_empty() {
+            this.constant_at(f, Value::String(text.into_string()));
+            this.emit_at(f, OpCode::CONCAT);
+        }
+        if last {
+            break;
+        }
+    }
+    this.expression_type = Type::String;
+    this.expression_inferred = false;
+    this.expression_signature = None;
+    Ok(())
+}
+
+fn literal<'c>(
+    this: &mut Compiler,
+    _mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    _it: &mut Peekable<Lexer>,
+    _can_assign: bool,
+) -> Catch {
+    devnote!(this _it "literal");
     let t = this.copy_store()?;
     match t {
         Token::Nil => this.emit_at(f, OpCode::NIL),
@@ -2583,10 +4325,21 @@ fn literal(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>, _can_assign:
         Token::False => this.emit_at(f, OpCode::FALSE),
         _ => unreachable!(),
     }
+    this.expression_type = if let Token::Nil = t {
+        Type::Nil
+    } else {
+        Type::Boolean
+    };
     Ok(())
 }
 
-fn call(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>, _can_assign: bool) -> Catch {
+fn call<'c>(
+    this: &mut Compiler,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    it: &mut Peekable<Lexer>,
+    _can_assign: bool,
+) -> Catch {
     devnote!(this it "call");
     // let t = this.take_store()?;
     // let l = this.current_location;
@@ -2597,34 +4350,167 @@ fn call(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>, _can_assign: bo
     //     this.emit(OpCode::CALL { arg_count }, l);
     // }
     let start = this.current_location;
+    let signature = this.expression_signature.take();
+    callee_warning(this, start);
 
     // println!("{} ", "TIME TO COUNT".on_cyan());
-    let arg_count = arguments(this, f, it, start)?;
+    let arg_count = arguments(this, mc, f, it, start, signature.as_ref())?;
     devout!("{} {}", "ARG COUNT".on_cyan(), arg_count);
-    this.emit(f, OpCode::CALL(arg_count, 0), start);
+    if arg_count > 0 && open_last_values(this, f) {
+        this.emit(f, OpCode::CALL_OPEN(arg_count - 1, 1), start);
+    } else {
+        this.emit(f, OpCode::CALL(arg_count, 1), start);
+    }
+    call_result_type(this, signature);
     Ok(())
 }
 
-fn call_table(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>, _can_assign: bool) -> Catch {
-    todo!();
+/** `.name` or `[key]` after a call or a parenthesized expression like `f().x`, the value being indexed is already on the stack. The path may be assigned to like any other */
+fn call_index<'c>(
+    this: &mut Compiler,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    it: &mut Peekable<Lexer>,
+    can_assign: bool,
+) -> Catch {
+    devnote!(this it "call_index");
+    let start = this.current_location;
+    if let Token::Dot = this.get_current()? {
+        let t = this.pop(it);
+        this.current_location = t.1;
+        if let Token::Identifier(ident) = t.0? {
+            this.emit_identifer_constant_at(f, ident);
+        } else {
+            return Err(this.error_at(SiltError::ExpectedFieldIdentifier));
+        }
+    } else {
+        expression_single(this, mc, f, it, false)?;
+        expect_token!(
+            this,
+            it,
+            CloseBracket,
+            this.error_at(SiltError::UnterminatedBracket(start.0, start.1))
+        );
+    }
+    let count = table_indexer(this, mc, f, it)? as u8 + 1;
+    this.expression_type = Type::None;
+    this.expression_inferred = false;
+    this.expression_signature = None;
+    if can_assign && this.can_multivar_set && matches!(this.peek(it)?, Token::Comma) {
+        let reads = vec![this.emit_index(f, OpCode::TABLE_GET { depth: count })];
+        let list = vec![AssignTarget::Field(count)];
+        return field_targets(this, mc, f, it, can_assign, list, reads);
+    }
+    if can_assign && matches!(this.peek(it)?, Token::Assign) {
+        this.eat(it);
+        expression(this, mc, f, it, false)?;
+        this.emit_at(f, OpCode::TABLE_SET { depth: count });
+        this.override_pop = true;
+    } else if compound_operator(this.peek(it)?).is_some() {
+        if !can_assign {
+            let t = this.peek(it)?.clone();
+            return Err(this.error_at(SiltError::InvalidAssignment(t)));
+        }
+        this.emit_at(f, OpCode::DUPLICATE(count + 1));
+        this.emit_at(f, OpCode::TABLE_GET { depth: count });
+        compound_assignment(this, mc, f, it)?;
+        this.emit_at(f, OpCode::TABLE_SET { depth: count });
+        this.override_pop = true;
+    } else {
+        this.emit_at(f, OpCode::TABLE_GET { depth: count });
+    }
     Ok(())
 }
 
-fn call_string(
+/** `obj:name(...)`, obj is already on the stack so it's only evaluated once and handed to the call as self */
+fn method_call<'c>(
     this: &mut Compiler,
-    f: FnRef,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
     it: &mut Peekable<Lexer>,
     _can_assign: bool,
 ) -> Catch {
-    todo!();
-    Ok(())
+    devnote!(this it "method_call");
+    let t = this.pop(it);
+    this.current_location = t.1;
+    if let Token::Identifier(ident) = t.0? {
+        this.emit_identifer_constant_at(f, ident);
+    } else {
+        return Err(this.error_at(SiltError::ExpectedFieldIdentifier));
+    }
+    this.emit_at(f, OpCode::GET_METHOD);
+    this.self_arg = true;
+    // methods are looked up at runtime, what we know is about the object
+    this.expression_type = Type::None;
+    this.expression_signature = None;
+    this.store(it);
+    match this.get_current()? {
+        Token::OpenParen => call(this, mc, f, it, false),
+        Token::OpenBrace => call_table(this, mc, f, it, false),
+        Token::StringLiteral(_) => call_string(this, mc, f, it, false),
+        _ => Err(this.error_at(SiltError::ExpectedToken(Token::OpenParen))),
+    }
 }
 
-fn arguments(
+/** `f{...}` sugar, the table constructor is the only argument */
+fn call_table<'c>(
+    this: &mut Compiler,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    it: &mut Peekable<Lexer>,
+    _can_assign: bool,
+) -> Catch {
+    devnote!(this it "call_table");
+    let start = this.current_location;
+    let signature = this.expression_signature.take();
+    callee_warning(this, start);
+    tabulate(this, mc, f, it, false)?;
+    single_argument_call(this, f, start, signature)
+}
+
+/** `f"str"` and `f[[str]]` sugar, the string is the only argument */
+fn call_string<'c>(
+    this: &mut Compiler,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    it: &mut Peekable<Lexer>,
+    _can_assign: bool,
+) -> Catch {
+    devnote!(this it "call_string");
+    let start = this.current_location;
+    let signature = this.expression_signature.take();
+    callee_warning(this, start);
+    string(this, mc, f, it, false)?;
+    single_argument_call(this, f, start, signature)
+}
+
+/** the table or string argument is on the stack, check it against the first parameter if we know the signature */
+fn single_argument_call(
     this: &mut Compiler,
     f: FnRef,
+    start: TokenCell,
+    signature: Option<FunctionType>,
+) -> Catch {
+    let arg_count = if std::mem::take(&mut this.self_arg) {
+        2
+    } else {
+        1
+    };
+    if let Some(expected) = signature.as_ref().and_then(|s| s.params.first()) {
+        check_type(this, *expected)?;
+    }
+    this.emit(f, OpCode::CALL(arg_count, 1), start);
+    call_result_type(this, signature);
+    Ok(())
+}
+
+fn arguments<'c>(
+    this: &mut Compiler,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
     it: &mut Peekable<Lexer>,
     start: TokenCell,
+    signature: Option<&FunctionType>,
 ) -> Result<u8, ErrorTuple> {
     devnote!(this it "arguments");
     this.can_multivar_set = false;
@@ -2637,8 +4523,13 @@ fn arguments(
     };
     devout!("{} {}", "start with ".red(), args);
     if !matches!(this.peek(it)?, &Token::CloseParen) {
+        let mut index = 0;
         while {
-            expression_single(this, f, it, false)?;
+            expression_single(this, mc, f, it, false)?;
+            if let Some(expected) = signature.and_then(|s| s.params.get(index)) {
+                check_type(this, *expected)?;
+            }
+            index += 1;
             devout!("{}", "yeah ADD 1".red());
             args += 1;
             if let &Token::Comma = this.peek(it)? {
@@ -2667,16 +4558,41 @@ fn arguments(
     Ok(args)
 }
 
-fn print(this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>) -> Catch {
+fn print<'c>(
+    this: &mut Compiler,
+    mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    it: &mut Peekable<Lexer>,
+) -> Catch {
     devnote!(this it "print");
     this.eat(it);
-    expression(this, f, it, false)?;
+    expression(this, mc, f, it, false)?;
     this.emit_at(f, OpCode::PRINT);
     Ok(())
 }
 
-pub fn void(_this: &mut Compiler, f: FnRef, it: &mut Peekable<Lexer>, _can_assign: bool) -> Catch {
-    devnote!(_this it "void");
+fn varargs<'c>(
+    this: &mut Compiler,
+    _mc: &Mutation<'c>,
+    f: FnRef<'_, 'c>,
+    _it: &mut Peekable<Lexer>,
+    _can_assign: bool,
+) -> Catch {
+    if !this.variadic {
+        return Err(this.error_at(SiltError::VarArgsOutsideFunction));
+    }
+    this.emit_at(f, OpCode::VARARGS(1));
+    Ok(())
+}
+
+pub fn void<'c>(
+    _this: &mut Compiler,
+    _mc: &Mutation<'c>,
+    _f: FnRef<'_, 'c>,
+    _it: &mut Peekable<Lexer>,
+    _can_assign: bool,
+) -> Catch {
+    devnote!(_this _it "void");
     Ok(())
 }
 
@@ -1,4 +1,4 @@
-use crate::{token::Token, userdata::MetaMethod};
+use crate::{token::Token, types::Type, userdata::MetaMethod};
 
 #[derive(Clone, PartialEq, Debug)]
 pub enum SiltError {
@@ -7,6 +7,13 @@ pub enum SiltError {
     NotANumber(String),
     UnexpectedCharacter(char),
     UnterminatedString,
+    InvalidEscapeSequence(String),
+    EscapeTooLarge(String),
+    /** a `\u{...}` escape in the utf16 surrogate range, it has no utf8 encoding */
+    EscapeSurrogate(String),
+    InvalidUtf8String,
+    InvalidLongStringDelimiter,
+    UnterminatedComment,
     UnterminatedParenthesis(usize, usize),
     UnterminatedBracket(usize, usize),
     InvalidTokenPlacement(Token),
@@ -17,10 +24,12 @@ pub enum SiltError {
     ExpectedFieldIdentifier,
     TableExpectedCommaOrCloseBrace,
     UndefinedLabel(String),
+    GotoIntoScope(String),
     InvalidAssignment(Token),
     UnterminatedBlock,
     ExpectedThen,
     ExpectedDo,
+    BreakOutsideLoop,
     ExpectedToken(Token),
     TooManyLocals,
     TooManyOperations,
@@ -33,9 +42,25 @@ pub enum SiltError {
     ExpInvalidLength(ValueTypes),
     ExpOpValueWithValue(ValueTypes, MetaMethod, ValueTypes),
     ExpInvalidNegation(ValueTypes),
+    ExpIntegerDivideByZero(MetaMethod),
+    ExpNoIntegerRepresentation,
     EarlyEndOfFile,
     ExpInvalid,
     ExpectedAssign,
+    VarArgsOutsideFunction,
+    ArrowFunctionsDisabled,
+    InterpolationDisabled,
+    UnknownType(String),
+    /** expected, found */
+    TypeMismatch(Type, Type),
+    UnknownAttribute(String),
+    AssignToConst(String),
+    MultipleToBeClosed,
+
+    // compile warnings, the chunk is still produced
+    AttemptArithmetic(Type),
+    AttemptConcat(Type),
+    AttemptCall(Type),
 
     // resolver errors
     // ResReadInOwnInit,
@@ -45,6 +70,8 @@ pub enum SiltError {
     //interpreted errors
     EvalNoInteger(ValueTypes),
     NotCallable(String),
+    /** raised by `error`, the thrown value itself waits in the VM until it's caught */
+    Thrown(String),
     // Return(Value),
     MetaMethodMissing(MetaMethod),
     MetaMethodNotCallable(MetaMethod),
@@ -67,6 +94,21 @@ pub enum SiltError {
     VmNonTableOperations(ValueTypes),
     VmValBadConvert(ValueTypes),
     VmNativeParameterMismatch,
+    /** argument position, function name, reason */
+    VmBadArgument(usize, String, String),
+    /** table.unpack asked for more values than the stack could ever hold */
+    VmUnpackTooMany,
+    VmNestedProtectedCall,
+    VmResumeDead,
+    VmResumeNotSuspended,
+    VmYieldOutsideCoroutine,
+    /** a metamethod or native is running the call that yielded to completion, it can't be left halfway */
+    VmYieldAcrossNative,
+    /** the stack or call depth went past the VM's limits */
+    StackOverflow,
+    /** what failed, the type it failed on and the variable the value was read from */
+    VmAttempt(Attempt, ValueTypes, VariableName),
+    TableInvalidNextKey,
 
     Unknown,
 
@@ -90,12 +132,54 @@ pub enum ValueTypes {
     Closure,
     Table,
     UserData,
+    Thread,
     #[cfg(feature = "vectors")]
     Vec3,
     #[cfg(feature = "vectors")]
     Vec2,
 }
 
+/** an operation a runtime error can name the variable for */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Attempt {
+    Index,
+    Arithmetic,
+    Call,
+}
+
+impl std::fmt::Display for Attempt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Attempt::Index => write!(f, "index"),
+            Attempt::Arithmetic => write!(f, "perform arithmetic on"),
+            Attempt::Call => write!(f, "call"),
+        }
+    }
+}
+
+/** where the value an operation failed on was read from */
+#[derive(Debug, Clone, PartialEq)]
+pub enum VariableName {
+    Global(String),
+    Local(String),
+    Upvalue(String),
+    /** a table field, `?` when the key wasn't a string */
+    Field(String),
+    Method(String),
+}
+
+impl std::fmt::Display for VariableName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VariableName::Global(s) => write!(f, "global '{}'", s),
+            VariableName::Local(s) => write!(f, "local '{}'", s),
+            VariableName::Upvalue(s) => write!(f, "upvalue '{}'", s),
+            VariableName::Field(s) => write!(f, "field '{}'", s),
+            VariableName::Method(s) => write!(f, "method '{}'", s),
+        }
+    }
+}
+
 pub struct TokenTriple {
     pub line: usize,
     pub col: usize,
@@ -156,6 +240,7 @@ impl std::fmt::Display for SiltError {
                 )
             }
             Self::UndefinedLabel(s) => write!(f, "No matching goto label for '{}'", s),
+            Self::GotoIntoScope(s) => write!(f, "Goto '{}' jumps into the scope of a local", s),
             Self::ExpectedGotoIdentifier => write!(f, "Expected identifier following goto keyword"),
             Self::ExpectedFieldIdentifier => {
                 write!(f, "Expected identifier following field accessor '.'")
@@ -171,6 +256,14 @@ impl std::fmt::Display for SiltError {
             Self::NotANumber(s) => write!(f, "Not a number: {}", s),
             Self::UnexpectedCharacter(c) => write!(f, "Unexpected character: {}", c),
             Self::UnterminatedString => write!(f, "Unterminated string"),
+            Self::InvalidEscapeSequence(s) => write!(f, "Invalid escape sequence '{}'", s),
+            Self::EscapeTooLarge(s) => write!(f, "Escape sequence '{}' is too large", s),
+            Self::EscapeSurrogate(s) => {
+                write!(f, "Escape sequence '{}' is a surrogate, not valid UTF-8", s)
+            }
+            Self::InvalidUtf8String => write!(f, "String is not valid UTF-8"),
+            Self::InvalidLongStringDelimiter => write!(f, "Invalid long string delimiter"),
+            Self::UnterminatedComment => write!(f, "Unterminated comment"),
             Self::UnterminatedParenthesis(x, y) => {
                 write!(
                     f,
@@ -198,11 +291,38 @@ impl std::fmt::Display for SiltError {
                 )
             }
             SiltError::ExpInvalidNegation(v) => write!(f, "Cannot negate '{}'", v),
+            SiltError::ExpIntegerDivideByZero(op) => {
+                write!(f, "Attempt to perform 'n{}0'", op)
+            }
             SiltError::InvalidTokenPlacement(t) => write!(f, "Invalid token placement: {}", t),
             SiltError::InvalidColonPlacement => {
                 write!(f, "Colon must be followed by type and assigned or a call")
             }
             SiltError::ExpInvalidBitwise(v) => write!(f, "Cannot bitwise on '{}'", v),
+            SiltError::ArrowFunctionsDisabled => write!(f, "Arrow functions are not enabled"),
+            SiltError::InterpolationDisabled => write!(f, "String interpolation is not enabled"),
+            SiltError::UnknownType(s) => write!(f, "Unknown type '{}'", s),
+            SiltError::TypeMismatch(expected, found) => {
+                write!(f, "Expected type '{}' but found '{}'", expected, found)
+            }
+            SiltError::UnknownAttribute(s) => write!(f, "Unknown attribute '{}'", s),
+            SiltError::AssignToConst(s) => {
+                write!(f, "Attempt to assign to const variable '{}'", s)
+            }
+            SiltError::MultipleToBeClosed => {
+                write!(f, "Multiple to-be-closed variables in local list")
+            }
+            SiltError::AttemptArithmetic(t) => {
+                write!(f, "Attempt to perform arithmetic on a {}", t)
+            }
+            SiltError::AttemptConcat(t) => write!(f, "Attempt to concatenate a {}", t),
+            SiltError::AttemptCall(t) => write!(f, "Attempt to call a {}", t),
+            SiltError::VarArgsOutsideFunction => {
+                write!(f, "Cannot use '...' outside a vararg function")
+            }
+            SiltError::ExpNoIntegerRepresentation => {
+                write!(f, "Number has no integer representation")
+            }
             Self::ExpInvalidLength(v) => write!(f, "Cannot get length of '{}'", v),
             SiltError::EvalNoInteger(v) => {
                 write!(f, "{} has no direct integer conversion for operation", v)
@@ -219,14 +339,44 @@ impl std::fmt::Display for SiltError {
             SiltError::UnterminatedBlock => write!(f, "Unterminated block"),
             SiltError::ExpectedThen => write!(f, "Expected 'then' after if condition"),
             SiltError::ExpectedDo => write!(f, "Expected 'do' after while condition"),
+            Self::BreakOutsideLoop => write!(f, "Cannot break outside of a loop"),
             Self::ExpectedToken(t) => write!(f, "Expected token: {}", t),
             Self::NotCallable(s) => write!(f, "Value '{}' is not callable", s),
+            Self::Thrown(s) => write!(f, "{}", s),
             Self::ExpInvalid => write!(f, "Invalid expression"),
             Self::VmCompileError => write!(f, "Error compiling chunk"),
             Self::VmRuntimeError => write!(f, "Runtime error for chunk"),
             Self::VmCorruptConstant => write!(f, "Constant store corrupted"),
             Self::VmValBadConvert(t)=> write!(f, "Impossible to convert from \"{}\"",t),
             Self::VmNativeParameterMismatch=>write!(f, "Cannot call native function with available parameters"), 
+            Self::TableInvalidNextKey => write!(f, "Invalid key to 'next'"),
+            Self::VmBadArgument(i, name, reason) => {
+                write!(f, "Bad argument #{} to '{}' ({})", i, name, reason)
+            }
+            Self::VmUnpackTooMany => write!(f, "Too many results to unpack"),
+            Self::VmNestedProtectedCall => {
+                write!(f, "pcall and xpcall cannot directly call each other")
+            }
+            Self::VmResumeDead => write!(f, "Cannot resume dead coroutine"),
+            Self::VmResumeNotSuspended => write!(f, "Cannot resume non-suspended coroutine"),
+            Self::VmYieldOutsideCoroutine => write!(f, "Attempt to yield from outside a coroutine"),
+            Self::StackOverflow => write!(f, "Stack overflow"),
+            Self::VmYieldAcrossNative => {
+                write!(f, "Attempt to yield across a metamethod or native call")
+            }
+            Self::VmAttempt(attempt, v, name) => {
+                let v = v.to_string();
+                let article = if v.starts_with(['a', 'e', 'i', 'o', 'u']) {
+                    "an"
+                } else {
+                    "a"
+                };
+                write!(
+                    f,
+                    "Attempt to {} {} {} value ({})",
+                    attempt, article, v, name
+                )
+            }
 
             Self::Unknown => write!(f, "Unknown error"),
             SiltError::MetaMethodMissing(meta_method) => {
@@ -266,6 +416,7 @@ impl std::fmt::Display for ValueTypes {
             ValueTypes::Function => write!(f, "function"),
             ValueTypes::Closure => write!(f, "(function)"),
             ValueTypes::Table => write!(f, "table"),
+            ValueTypes::Thread => write!(f, "thread"),
             ValueTypes::UserData => write!(f, "userdata"),
             #[cfg(feature = "vectors")]
             ValueTypes::Vec3 => write!(f, "vec3"),
@@ -286,3 +437,56 @@ impl std::fmt::Display for ErrorTuple {
         write!(f, "{}@{}:{}", self.code, self.location.0, self.location.1)
     }
 }
+
+/** one call that was active when a runtime error was raised */
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceFrame {
+    /** None for the main chunk */
+    pub function: Option<String>,
+    pub chunk: Option<String>,
+    pub location: TokenCell,
+}
+
+impl TraceFrame {
+    /** `chunk:line` as lua prefixes error messages, an unnamed chunk is `?` */
+    pub fn position(&self) -> String {
+        format!(
+            "{}:{}",
+            self.chunk.as_deref().unwrap_or("?"),
+            self.location.0
+        )
+    }
+}
+
+impl std::fmt::Display for TraceFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.function {
+            Some(name) => write!(f, "{}: in function '{}'", self.position(), name),
+            None => write!(f, "{}: in main chunk", self.position()),
+        }
+    }
+}
+
+/** a runtime error with where it was raised and the calls active at the time, innermost first */
+#[derive(Clone, Debug)]
+pub struct RuntimeError {
+    pub code: SiltError,
+    pub location: TokenCell,
+    pub traceback: Vec<TraceFrame>,
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.traceback.first() {
+            Some(frame) => write!(f, "{}: {}", frame.position(), self.code)?,
+            None => write!(f, "{}", self.code)?,
+        }
+        write!(f, "\nstack traceback:")?;
+        for frame in &self.traceback {
+            write!(f, "\n\t{}", frame)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RuntimeError {}
@@ -9,9 +9,9 @@ use gc_arena::{lock::RefLock, Collect, Gc, Mutation};
 use crate::{
     chunk::Chunk,
     code::OpCode,
-    error::SiltError,
+    error::{SiltError, TokenCell, TraceFrame},
     lua::{Ephemeral, VM},
-    userdata::{InnerResult, ToInnerResult},
+    userdata::{MultiResult, ToInnerResult},
     value::{FromLuaMulti, ToLua, ToLuaMulti, Value},
 };
 
@@ -21,28 +21,45 @@ pub struct CallFrame<'gc> {
     pub function: Gc<'gc, Closure<'gc>>, // pointer
     // ip: *const OpCode
     // pub base: usize,
-    // pointer point sinto VM values stack
+    /** index of the frame's base on the VM's stack, the callee's slot with it's locals above it. An index rather than a pointer so it stays put when the stack grows */
     pub stack_snapshot: usize,
-    pub local_stack: *mut Value<'gc>,
     pub ip: *const OpCode,
     // pub need: u8,
     pub multi_return: u8,
+    /** extra arguments passed to a variadic function, read back by `...` */
+    pub varargs: Vec<Value<'gc>>,
+    /** set on a frame started by pcall or xpcall, errors unwind to here. Holds the message handler, nil for pcall. A `true` waits in the slot just below the frame for a clean return */
+    pub protected: Option<Value<'gc>>,
+    /** bottom frame of a coroutine, it's results are left on the stack for resume to hand back */
+    pub coroutine: bool,
     // pub mark: usize
 }
 
+unsafe impl<'gc> Collect for CallFrame<'gc> {
+    fn trace(&self, cc: &gc_arena::Collection) {
+        // ip points into a chunk kept alive elsewhere
+        self.function.trace(cc);
+        self.varargs.trace(cc);
+        self.protected.trace(cc);
+    }
+}
+
 impl<'frame> CallFrame<'frame> {
     pub fn new<'a>(
         function: Gc<'frame, Closure<'frame>>,
         stack_snapshot: usize,
         multi_return: u8,
     ) -> Self {
-        let ip = function.function.chunk.code.as_ptr();
+        // start one before the first instruction, the process loop iterates past the call that made us
+        let ip = function.function.chunk.code.as_ptr().wrapping_sub(1);
         Self {
             function,
             ip,
-            local_stack: std::ptr::null_mut(),
             stack_snapshot,
             multi_return,
+            varargs: vec![],
+            protected: None,
+            coroutine: false,
         }
     }
 
@@ -51,10 +68,36 @@ impl<'frame> CallFrame<'frame> {
         unsafe { &*self.ip }
     }
 
+    /** index of the instruction being run, or the call a caller is waiting on */
+    pub fn pc(&self) -> usize {
+        let chunk = &self.function.function.chunk;
+        let index = unsafe { self.ip.offset_from(chunk.code.as_ptr()) };
+        index.max(0) as usize
+    }
+
+    /** source location of the instruction being run */
+    pub fn location(&self) -> TokenCell {
+        self.function.function.chunk.get_location(self.pc())
+    }
+
+    /** this frame as a line of a traceback */
+    pub fn trace(&self) -> TraceFrame {
+        let function = &self.function.function;
+        TraceFrame {
+            function: if function.is_script {
+                None
+            } else {
+                function.name.clone()
+            },
+            chunk: function.chunk_name.clone(),
+            location: self.location(),
+        }
+    }
+
     /** shift ip by 1 instruction */
     pub fn iterate(&mut self) {
         // self.ip += 1;
-        self.ip = unsafe { self.ip.add(1) };
+        self.ip = self.ip.wrapping_add(1);
     }
 
     /** DANGER: does not shift ip, only returns instruction set in range past ip */
@@ -70,28 +113,6 @@ impl<'frame> CallFrame<'frame> {
         self.ip = unsafe { self.ip.add(n) };
     }
 
-    pub fn set_val(&mut self, index: u8, value: Value<'frame>) {
-        // self.stack[index as usize] = value;
-        unsafe { *self.local_stack.add(index as usize) = value };
-    }
-
-    pub fn get_val(&self, index: u8) -> &Value<'frame> {
-        // &self.stack[index as usize]
-        // println!("get_val: {}", index);
-        // println!("top: {}", unsafe { &*self.local_stack });
-        unsafe { &*self.local_stack.add(index as usize) }
-    }
-
-    pub fn get_val_mut(&mut self, index: u8) -> &mut Value<'frame> {
-        unsafe { &mut *self.local_stack.add(index as usize) }
-    }
-
-    pub fn print_local_stack(&self) {
-        println!("local stack: {:?}", unsafe {
-            std::slice::from_raw_parts(self.local_stack, 10)
-        });
-    }
-
     // pub fn push(&mut self, value: Value) {
     //     // TODO can we push to the stack by pointer? Or should we just push on a Vec?
     //     // *self.stack_top= value;
@@ -131,15 +152,6 @@ impl<'frame> CallFrame<'frame> {
     //     self.stack = unsafe { self.stack.sub(n as usize) };
     // }
 
-    /** take and replace with a Nil */
-    pub fn take<'a>(&'frame mut self) -> &'a Value<'frame> {
-        // self.stack_top = unsafe { self.stack_top.sub(1) };
-        // unsafe { *self.stack_top }
-        let v = unsafe { &*self.local_stack };
-        unsafe { *self.local_stack = Value::Nil };
-        v
-    }
-
     // TODO validate safety of this, compiler has to be solid af!
     pub fn forward(&mut self, offset: u16) {
         // self.ip += offset as usize;
@@ -160,7 +172,12 @@ pub struct FunctionObject<'chnk> {
     pub chunk: Chunk<'chnk>,
     pub upvalue_count: u8,
     pub need: u8,
-    // pub arity: usize,
+    /** count of named parameters, arguments are adjusted to this on call */
+    pub arity: u8,
+    /** declared with a trailing `...`, the main chunk always is */
+    pub is_variadic: bool,
+    /** name given to the script this function was compiled from */
+    pub chunk_name: Option<String>,
 }
 
 impl<'chnk> FunctionObject<'chnk> {
@@ -171,6 +188,9 @@ impl<'chnk> FunctionObject<'chnk> {
             chunk: Chunk::new(),
             upvalue_count: 0,
             need: 1,
+            arity: 0,
+            is_variadic: is_script,
+            chunk_name: None,
         }
     }
 
@@ -211,7 +231,7 @@ impl<'chnk> FunctionObject<'chnk> {
             }
         }
 
-        vm.push(ep, Value::Closure(Gc::new(ep.mc, closure)));
+        vm.push(ep, Value::Closure(Gc::new(ep.mc, closure)))?;
         // }
         // else {
         //     return Err(SiltError::VmRuntimeError);
@@ -220,21 +240,6 @@ impl<'chnk> FunctionObject<'chnk> {
         Ok(())
     }
 
-    pub(crate) fn call_closure<'gc, 'a: 'gc>(
-        clos: &'a Gc<Closure<'gc>>,
-        frames: &'a mut Vec<CallFrame<'gc>>,
-        stack_count: usize,
-        arity: usize,
-        mut frame: &'a mut CallFrame<'gc>,
-        ep: &mut Ephemeral<'_, 'a>,
-    ) {
-        let frame_top = unsafe { ep.ip.sub(arity + 1) };
-        let new_frame = CallFrame::new(clos.clone(), stack_count - arity - 1, 0);
-        frames.push(new_frame);
-        frame = frames.last_mut().unwrap();
-        frame.local_stack = frame_top;
-    }
-
     pub fn print(&self) {
         self.chunk.print_chunk(&self.name);
     }
@@ -271,10 +276,13 @@ impl Display for FunctionObject<'_> {
 
 pub type NativeFunctionRef<'a> = &'a NativeFunctionRaw<'a>;
 pub type NativeFunctionRc<'a> = Rc<NativeFunctionRaw<'a>>;
+/** what every native is boxed as once its arguments and results are converted */
+pub type NativeCallback<'a> =
+    Box<dyn Fn(&mut VM<'a>, &Mutation<'a>, &[Value<'a>]) -> MultiResult<'a> + 'a>;
 // pub trait NativeFunction<'a> =  Fn(&mut VM<'a>, &Mutation<'a>, Vec<Value<'a>>) -> Value<'a>;
 
 pub struct NativeFunctionRaw<'a> {
-    pub func: Box<dyn Fn(&mut VM<'a>, &Mutation<'a>, &[Value<'a>]) -> InnerResult<'a> + 'a>,
+    pub func: NativeCallback<'a>,
 }
 
 impl<'gc> NativeFunctionRaw<'gc> {
@@ -293,7 +301,22 @@ impl<'gc> NativeFunctionRaw<'gc> {
         Self {
             func: Box::new(move |vm, mc, raw_args| {
                 let args = A::from_lua_multi(raw_args, vm, mc)?;
-                R::to_lua(f(vm, mc, args), vm, mc)
+                Ok(vec![R::to_lua(f(vm, mc, args), vm, mc)?])
+            }),
+        }
+    }
+
+    /** same as new but the function may return any number of values, like `next` or `pairs` */
+    pub fn new_multi<A, F, R>(f: F) -> Self
+    where
+        A: FromLuaMulti<'gc>,
+        R: ToLuaMulti<'gc>,
+        F: Fn(&mut VM<'gc>, &Mutation<'gc>, A) -> Result<R, SiltError> + 'gc,
+    {
+        Self {
+            func: Box::new(move |vm, mc, raw_args| {
+                let args = A::from_lua_multi(raw_args, vm, mc)?;
+                f(vm, mc, args)?.to_lua_multi(vm, mc)
             }),
         }
     }
@@ -313,7 +336,7 @@ impl<'gc> NativeFunctionRaw<'gc> {
         vm: &mut VM<'gc>,
         mutation: &Mutation<'gc>,
         args: &[Value<'gc>],
-    ) -> InnerResult<'gc> {
+    ) -> MultiResult<'gc> {
         (self.func)(vm, mutation, args)
     }
 }
@@ -326,6 +349,8 @@ impl<'gc> NativeFunctionRaw<'gc> {
 pub struct WrappedFn<'gc> {
     // pub f: Box<dyn Fn(&mut VM<'gc>, &Mutation<'gc>, Vec<Value<'gc>>) -> InnerResult<'gc>>, // used exclusively by userdata, a bit of a hack
     pub f: NativeFunctionRc<'gc>,
+    /** a value the function holds on to, like the coroutine behind `coroutine.wrap`, traced so it lives as long as the function */
+    pub bound: Value<'gc>,
     // pub meta: u8
 }
 
@@ -334,7 +359,10 @@ impl<'gc> WrappedFn<'gc> {
         // callback: Rc<dyn Fn(&mut VM<'gc>, &Mutation<'gc>, T) -> InnerResult<'gc> + 'gc>,
         callback: NativeFunctionRc<'gc>,
     ) -> Self {
-        Self { f: callback }
+        Self {
+            f: callback,
+            bound: Value::Nil,
+        }
     }
 
     pub fn call(
@@ -342,7 +370,7 @@ impl<'gc> WrappedFn<'gc> {
         vm: &mut VM<'gc>,
         mc: &Mutation<'gc>,
         args: &[Value<'gc>],
-    ) -> InnerResult<'gc> {
+    ) -> MultiResult<'gc> {
         (self.f.func)(vm, mc, args)
     }
 }
@@ -369,11 +397,8 @@ impl<'gc> WrappedFn<'gc> {
 // }
 
 unsafe impl<'gc> Collect for WrappedFn<'gc> {
-    fn needs_trace() -> bool
-    where
-        Self: Sized,
-    {
-        false
+    fn trace(&self, cc: &gc_arena::Collection) {
+        self.bound.trace(cc);
     }
 }
 
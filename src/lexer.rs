@@ -25,6 +25,8 @@ pub struct Lexer<'c> {
     /// tracks start character column position of a token, for error pos
     pub column_start: usize,
     mode: Mode,
+    /// brace depth of each backtick template we're inside of, a `}` at depth 0 resumes the template text
+    templates: Vec<usize>,
     // ahead_buffer: Vec<TokenOption>,
 }
 
@@ -78,6 +80,7 @@ impl<'c> Lexer<'c> {
             line_number: 1,
             iterator: chars,
             mode: Mode::Normal,
+            templates: vec![],
             // ahead_buffer: Vec::new(),
         }
     }
@@ -159,71 +162,150 @@ impl<'c> Lexer<'c> {
         self.column = 0;
     }
 
-    fn get_sofar(&self) -> String {
-        self.source[self.start_token..self.current].to_string()
-    }
-
     fn number(&mut self, prefix_dot: bool) -> TokenOption {
+        // built from the chars we eat, underscores are simply never pushed
+        let mut cc = String::new();
         if prefix_dot {
             self.start_token = self.current - 1;
             self.column_start = self.column - 1;
+            cc.push('.');
         } else {
             self.set_start();
         }
-        self.eat();
+        let first = self.eat_out()?;
+        cc.push(first);
+        if !prefix_dot && first == '0' {
+            if let Some('x' | 'X') = self.peek() {
+                cc.push(self.eat_out()?);
+                return self.hex_number(cc);
+            }
+        }
         let mut is_float = prefix_dot;
-        let mut strip = false;
-        while self.current < self.end {
-            match self.peek() {
-                Some(c) => match c {
-                    '0'..='9' => {
-                        self.eat();
+        let mut exponent = false;
+        while let Some(&c) = self.peek() {
+            match c {
+                '0'..='9' => {
+                    self.eat();
+                    cc.push(c);
+                }
+                #[cfg(feature = "under-number")]
+                '_' => {
+                    self.eat();
+                }
+                '.' => {
+                    if is_float || exponent {
+                        return self.error(SiltError::InvalidNumber(cc));
                     }
-                    #[cfg(feature = "under-number")]
-                    '_' => {
+                    is_float = true;
+                    self.eat();
+                    cc.push(c);
+                }
+                'e' | 'E' if !exponent => {
+                    exponent = true;
+                    self.eat();
+                    cc.push(c);
+                    if let Some(&sign @ ('+' | '-')) = self.peek() {
                         self.eat();
-                        strip = true;
+                        cc.push(sign);
+                    }
+                    if !matches!(self.peek(), Some('0'..='9')) {
+                        return self.error(SiltError::InvalidNumber(cc));
+                    }
+                }
+                'a'..='z' | 'A'..='Z' => {
+                    return self.error(SiltError::InvalidNumber(cc));
+                }
+                _ => break,
+            }
+        }
+        if !is_float && !exponent {
+            if let Ok(n) = cc.parse::<i64>() {
+                return self.send(Token::Integer(n));
+            }
+            // too large for an integer, lua promotes these to a float
+        }
+        match cc.parse::<f64>() {
+            Ok(n) => self.send(Token::Number(n)),
+            Err(_) => self.error(SiltError::NotANumber(cc)),
+        }
+    }
+
+    /** Hex literal after the 0x prefix, integers wrap around on overflow and a fraction or p exponent makes a float */
+    fn hex_number(&mut self, mut cc: String) -> TokenOption {
+        let mut integer: u64 = 0;
+        let mut float = 0.;
+        let mut exp: i64 = 0;
+        let mut digits = 0;
+        let mut is_float = false;
+        while let Some(&c) = self.peek() {
+            match c {
+                '0'..='9' | 'a'..='f' | 'A'..='F' => {
+                    let d = c.to_digit(16).unwrap();
+                    self.eat();
+                    cc.push(c);
+                    integer = integer.wrapping_mul(16).wrapping_add(d as u64);
+                    float = float * 16. + d as f64;
+                    if is_float {
+                        exp -= 4;
+                    }
+                    digits += 1;
+                }
+                #[cfg(feature = "under-number")]
+                '_' => {
+                    self.eat();
+                }
+                '.' => {
+                    if is_float {
+                        return self.error(SiltError::InvalidNumber(cc));
+                    }
+                    is_float = true;
+                    self.eat();
+                    cc.push(c);
+                }
+                'p' | 'P' => {
+                    if digits == 0 {
+                        return self.error(SiltError::InvalidNumber(cc));
                     }
-                    '.' => {
-                        if is_float {
-                            return self.error(SiltError::InvalidNumber(self.get_sofar()));
+                    is_float = true;
+                    self.eat();
+                    cc.push(c);
+                    let negative = match self.peek() {
+                        Some(&sign @ ('+' | '-')) => {
+                            self.eat();
+                            cc.push(sign);
+                            sign == '-'
                         }
-                        is_float = true;
-                        self.eat();
+                        _ => false,
+                    };
+                    let mut power: i64 = 0;
+                    let mut power_digits = 0;
+                    while let Some(d) = self.peek().and_then(|c| c.to_digit(10)) {
+                        cc.push(self.eat_out()?);
+                        power = power.saturating_mul(10).saturating_add(d as i64);
+                        power_digits += 1;
                     }
-                    'a'..='z' | 'A'..='Z'  => {
-                        return self.error(SiltError::InvalidNumber(self.get_sofar()));
+                    if power_digits == 0 {
+                        return self.error(SiltError::InvalidNumber(cc));
                     }
-                    _ => break,
-                },
-                None => break,
+                    exp += if negative { -power } else { power };
+                    if let Some('0'..='9' | 'a'..='z' | 'A'..='Z' | '.') = self.peek() {
+                        return self.error(SiltError::InvalidNumber(cc));
+                    }
+                    break;
+                }
+                'g'..='z' | 'G'..='Z' => {
+                    return self.error(SiltError::InvalidNumber(cc));
+                }
+                _ => break,
             }
         }
-        let cc = &self.source[self.start_token..self.current];
+        if digits == 0 {
+            return self.error(SiltError::InvalidNumber(cc));
+        }
         if is_float {
-            let n = match if strip {
-                cc.replace("_", "").parse::<f64>()
-            } else {
-                cc.parse::<f64>()
-            } {
-                Ok(n) => n,
-                Err(_) => {
-                    return self.error(SiltError::NotANumber(cc.to_string()));
-                }
-            };
-            self.send(Token::Number(n))
+            self.send(Token::Number(float * 2f64.powf(exp as f64)))
         } else {
-            let n = match if strip {
-                cc.replace("_", "").parse::<i64>()
-            } else {
-                cc.parse::<i64>()
-            } {
-                Ok(n) => n,
-                Err(_) => {
-                    return self.error(SiltError::NotANumber(cc.to_string()));
-                }
-            };
-            return self.send(Token::Integer(n));
+            self.send(Token::Integer(integer as i64))
         }
     }
 
@@ -232,67 +314,267 @@ impl<'c> Lexer<'c> {
         self.column_start = self.column-1;
         self.eat();
         self.start_token = self.current;
-        while self.current < self.end {
+        let quote = if apos { '\'' } else { '"' };
+        // escapes can produce raw bytes, so we only check for valid utf8 once the string is done
+        let mut bytes = Vec::new();
+        loop {
             match self.peek() {
-                Some(c) => match c {
-                    '\n' => {
-                        return self.error(SiltError::UnterminatedString);
+                Some('\n') | None => {
+                    return self.error(SiltError::UnterminatedString);
+                }
+                Some(&c) => {
+                    self.eat();
+                    if c == quote {
+                        break;
+                    } else if c == '\\' {
+                        if let Err(e) = self.escape(&mut bytes) {
+                            self.skip_string(quote);
+                            return Some(Err(e));
+                        }
+                    } else {
+                        push_char(&mut bytes, c);
                     }
-                    '\'' => {
-                        self.eat();
-                        if apos {
-                            break;
+                }
+            }
+        }
+        match String::from_utf8(bytes) {
+            Ok(cc) => self.send(Token::StringLiteral(cc.into_boxed_str())),
+            Err(_) => self.error(SiltError::InvalidUtf8String),
+        }
+    }
+
+    /** Pass over the rest of a string after a bad escape so it isn't lexed as code, a line break ends it like an unterminated string would */
+    fn skip_string(&mut self, quote: char) {
+        while let Some(&c) = self.peek() {
+            if c == '\n' {
+                break;
+            }
+            self.eat();
+            if c == quote {
+                break;
+            }
+            if c == '\\' && self.peek().is_some_and(|&c| c != '\n') {
+                self.eat();
+            }
+        }
+    }
+
+    /** Decode the escape sequence following a backslash into the string buffer, errors point at the backslash */
+    fn escape(&mut self, bytes: &mut Vec<u8>) -> Result<(), ErrorTuple> {
+        let location = (self.line_number, self.column);
+        let error = |code| ErrorTuple { code, location };
+        let c = match self.eat_out() {
+            Some(c) => c,
+            None => return Err(error(SiltError::UnterminatedString)),
+        };
+        match c {
+            'a' => bytes.push(0x07),
+            'b' => bytes.push(0x08),
+            'f' => bytes.push(0x0c),
+            'n' => bytes.push(b'\n'),
+            'r' => bytes.push(b'\r'),
+            't' => bytes.push(b'\t'),
+            'v' => bytes.push(0x0b),
+            '\\' | '"' | '\'' => bytes.push(c as u8),
+            '\n' | '\r' => {
+                // an escaped line break is kept as a single newline
+                let pair = if c == '\n' { '\r' } else { '\n' };
+                if self.peek() == Some(&pair) {
+                    self.eat();
+                }
+                self.new_line();
+                bytes.push(b'\n');
+            }
+            'z' => {
+                while let Some(&c) = self.peek() {
+                    match c {
+                        '\n' => {
+                            self.eat();
+                            self.new_line();
                         }
+                        ' ' | '\t' | '\r' | '\x0b' | '\x0c' => self.eat(),
+                        _ => break,
                     }
-                    '"' => {
-                        self.eat();
-                        if !apos {
-                            break;
+                }
+            }
+            'x' => {
+                let mut seq = String::from("\\x");
+                let mut value = 0;
+                for _ in 0..2 {
+                    match self.peek().and_then(|c| c.to_digit(16)) {
+                        Some(d) => {
+                            seq.push(self.eat_out().unwrap());
+                            value = value * 16 + d;
                         }
+                        None => return Err(error(SiltError::InvalidEscapeSequence(seq))),
                     }
-                    _ => {
-                        self.eat();
+                }
+                bytes.push(value as u8);
+            }
+            '0'..='9' => {
+                let mut seq = format!("\\{}", c);
+                let mut value = c.to_digit(10).unwrap();
+                for _ in 0..2 {
+                    match self.peek().and_then(|c| c.to_digit(10)) {
+                        Some(d) => {
+                            seq.push(self.eat_out().unwrap());
+                            value = value * 10 + d;
+                        }
+                        None => break,
                     }
-                },
-                None => {
-                    return self.error(SiltError::UnterminatedString);
+                }
+                if value > 255 {
+                    return Err(error(SiltError::EscapeTooLarge(seq)));
+                }
+                bytes.push(value as u8);
+            }
+            'u' => {
+                let mut seq = String::from("\\u");
+                if self.peek() != Some(&'{') {
+                    return Err(error(SiltError::InvalidEscapeSequence(seq)));
+                }
+                seq.push(self.eat_out().unwrap());
+                let mut value: u32 = 0;
+                let mut digits = 0;
+                while let Some(d) = self.peek().and_then(|c| c.to_digit(16)) {
+                    seq.push(self.eat_out().unwrap());
+                    digits += 1;
+                    value = match value.checked_mul(16) {
+                        // strings are utf8 so we stop at the last codepoint, not lua's 0x7FFFFFFF
+                        Some(v) if v + d <= 0x10_FFFF => v + d,
+                        _ => return Err(error(SiltError::EscapeTooLarge(seq))),
+                    };
+                }
+                if digits == 0 || self.peek() != Some(&'}') {
+                    return Err(error(SiltError::InvalidEscapeSequence(seq)));
+                }
+                seq.push(self.eat_out().unwrap());
+                match char::from_u32(value) {
+                    Some(c) => push_char(bytes, c),
+                    // anything past 0x10FFFF was turned away above, only surrogates are left
+                    None => return Err(error(SiltError::EscapeSurrogate(seq))),
                 }
             }
+            _ => {
+                return Err(error(SiltError::InvalidEscapeSequence(format!("\\{}", c))));
+            }
         }
-        let cc = self.source[self.start_token..self.current - 1].to_string();
-        self.send(Token::StringLiteral(cc.into_boxed_str()))
+        Ok(())
     }
 
-    fn multi_line_string(&mut self) -> TokenOption {
-        self.column_start = self.column;
+    /** Template text after a backtick or a closing `}` up to the next `{` or the closing backtick. Braces and backticks can be escaped, everything else escapes like a regular string */
+    fn template(&mut self, head: bool) -> TokenOption {
         self.eat();
-        self.start_token = self.current;
-        while self.current < self.end {
-            let char = self.peek();
-            match char {
-                Some(c) => match c {
-                    '\n' => {
-                        self.new_line();
+        let mut bytes = Vec::new();
+        let open = loop {
+            match self.peek() {
+                None => return self.error(SiltError::UnterminatedString),
+                Some(&c) => {
+                    self.eat();
+                    match c {
+                        '`' => break false,
+                        '{' => break true,
+                        '\\' => match self.peek() {
+                            Some(&c @ ('{' | '}' | '`')) => {
+                                self.eat();
+                                bytes.push(c as u8);
+                            }
+                            _ => {
+                                if let Err(e) = self.escape(&mut bytes) {
+                                    return Some(Err(e));
+                                }
+                            }
+                        },
+                        '\n' => {
+                            self.new_line();
+                            bytes.push(b'\n');
+                        }
+                        c => push_char(&mut bytes, c),
+                    }
+                }
+            }
+        };
+        let cc = match String::from_utf8(bytes) {
+            Ok(cc) => cc.into_boxed_str(),
+            Err(_) => return self.error(SiltError::InvalidUtf8String),
+        };
+        match (head, open) {
+            (true, true) => {
+                self.templates.push(0);
+                self.send(Token::TemplateHead(cc))
+            }
+            (true, false) => self.send(Token::TemplateString(cc)),
+            (false, true) => self.send(Token::TemplateMiddle(cc)),
+            (false, false) => {
+                self.templates.pop();
+                self.send(Token::TemplateTail(cc))
+            }
+        }
+    }
+
+    fn multi_line_string(&mut self, level: usize) -> TokenOption {
+        match self.long_bracket(level) {
+            Some(cc) => self.send(Token::StringLiteral(cc.into_boxed_str())),
+            None => self.error(SiltError::UnterminatedString),
+        }
+    }
+
+    /** After an opening '[' count the '=' level, true long brackets must be followed by another '[' */
+    fn long_bracket_level(&mut self) -> Option<usize> {
+        let mut level = 0;
+        while let Some('=') = self.peek() {
+            self.eat();
+            level += 1;
+        }
+        if let Some('[') = self.peek() {
+            self.eat();
+            Some(level)
+        } else {
+            None
+        }
+    }
+
+    /** Read long bracket content up to the matching close of the same level, None if we hit the end first. A newline directly after the opening bracket is skipped */
+    fn long_bracket(&mut self, level: usize) -> Option<String> {
+        let mut content = String::new();
+        let mut first = true;
+        loop {
+            let c = *self.peek()?;
+            match c {
+                '\n' | '\r' => {
+                    self.eat();
+                    // \r\n and \n\r count as a single line break
+                    let pair = if c == '\n' { '\r' } else { '\n' };
+                    if self.peek() == Some(&pair) {
                         self.eat();
                     }
-                    ']' => {
+                    self.new_line();
+                    if !first {
+                        content.push('\n');
+                    }
+                }
+                ']' => {
+                    self.eat();
+                    let mut closing = 0;
+                    while let Some('=') = self.peek() {
                         self.eat();
-                        if self.peek() == Some(&']') {
-                            self.eat();
-                            break;
-                        }
+                        closing += 1;
                     }
-                    _ => {
+                    if closing == level && self.peek() == Some(&']') {
                         self.eat();
+                        return Some(content);
                     }
-                },
-                None => {
-                    return self.error(SiltError::UnterminatedString);
+                    // not our close, whatever we ate is content and a following ']' gets another try
+                    content.push(']');
+                    content.extend(std::iter::repeat_n('=', closing));
+                }
+                _ => {
+                    self.eat();
+                    content.push(c);
                 }
             }
+            first = false;
         }
-        let cc = self.source[self.start_token..self.current - 2].to_string();
-        self.send(Token::StringLiteral(cc.into_boxed_str()))
     }
 
     fn word_eater(&mut self) {
@@ -433,7 +715,13 @@ impl<'c> Lexer<'c> {
                     self.eat();
                     match self.peek() {
                         Some('0'..='9') => self.number(true),
-                        Some('.') => self.eat_send(Token::Op(Operator::Concat)),
+                        Some('.') => {
+                            self.eat();
+                            match self.peek() {
+                                Some('.') => self.eat_send(Token::VarArgs),
+                                _ => self.send(Token::Op(Operator::Concat)),
+                            }
+                        }
                         _ => self.send(Token::Dot),
                     }
                 }
@@ -470,6 +758,16 @@ impl<'c> Lexer<'c> {
                                 self.eat();
                                 self.get_flag()
                             } else {
+                                if let Some('[') = self.peek() {
+                                    self.eat();
+                                    if let Some(level) = self.long_bracket_level() {
+                                        return match self.long_bracket(level) {
+                                            Some(_) => self.send(Token::Comment),
+                                            None => self.error(SiltError::UnterminatedComment),
+                                        };
+                                    }
+                                    // not a long bracket, the rest of the line is a regular comment
+                                }
                                 while self.current < self.end {
                                     if let Some('\n') = self.eat_out() {
                                         self.new_line();
@@ -501,6 +799,10 @@ impl<'c> Lexer<'c> {
                             self.eat();
                             self.send(Token::DivideAssign)
                         }
+                        Some('/') => {
+                            self.eat();
+                            self.send(Token::Op(Operator::FloorDivide))
+                        }
                         _ => self.send(Token::Op(Operator::Divide)),
                     }
                 }
@@ -524,6 +826,18 @@ impl<'c> Lexer<'c> {
                         _ => self.send(Token::Op(Operator::Modulus)),
                     }
                 }
+                '&' => {
+                    self.eat();
+                    self.send(Token::Op(Operator::BitAnd))
+                }
+                '|' => {
+                    self.eat();
+                    self.send(Token::Op(Operator::BitOr))
+                }
+                '^' => {
+                    self.eat();
+                    self.send(Token::Op(Operator::Exponent))
+                }
                 '(' => {
                     self.eat();
                     self.send(Token::OpenParen)
@@ -549,6 +863,10 @@ impl<'c> Lexer<'c> {
                             self.eat();
                             self.send(Token::Op(Operator::LessEqual))
                         }
+                        Some('<') => {
+                            self.eat();
+                            self.send(Token::Op(Operator::ShiftLeft))
+                        }
                         _ => self.send(Token::Op(Operator::Less)),
                     }
                 }
@@ -559,21 +877,21 @@ impl<'c> Lexer<'c> {
                             self.eat();
                             self.send(Token::Op(Operator::GreaterEqual))
                         }
+                        Some('>') => {
+                            self.eat();
+                            self.send(Token::Op(Operator::ShiftRight))
+                        }
                         _ => self.send(Token::Op(Operator::Greater)),
                     }
                 }
                 '[' => {
                     self.eat();
                     match self.peek() {
-                        Some(c) => match c {
-                            '[' => self.multi_line_string(),
-                            // '=' => {
-                            //     self.eat();
-                            //     return Some(Token::OpenBracketAssign);
-                            // }
-                            _ => self.send(Token::OpenBracket),
+                        Some('[' | '=') => match self.long_bracket_level() {
+                            Some(level) => self.multi_line_string(level),
+                            None => self.error(SiltError::InvalidLongStringDelimiter),
                         },
-                        None => self.send(Token::OpenBracket),
+                        _ => self.send(Token::OpenBracket),
                     }
                 }
                 ']' => {
@@ -581,20 +899,31 @@ impl<'c> Lexer<'c> {
                     self.send(Token::CloseBracket)
                 }
                 '{' => {
+                    if let Some(depth) = self.templates.last_mut() {
+                        *depth += 1;
+                    }
                     self.eat();
                     self.send(Token::OpenBrace)
                 }
-                '}' => {
-                    self.eat();
-                    self.send(Token::CloseBrace)
-                }
+                '}' => match self.templates.last_mut() {
+                    Some(0) => self.template(false),
+                    depth => {
+                        if let Some(depth) = depth {
+                            *depth -= 1;
+                        }
+                        self.eat();
+                        self.send(Token::CloseBrace)
+                    }
+                },
+                #[cfg(feature = "interpolation")]
+                '`' => self.template(true),
                 ' ' | '\r' | '\t' => {
                     self.eat();
                     None
                 }
                 '\n' => {
-                    self.new_line();
                     self.eat();
+                    self.new_line();
                     None
                 }
                 '#' => {
@@ -654,3 +983,8 @@ impl<'c> Lexer<'c> {
         None
     }
 }
+
+fn push_char(bytes: &mut Vec<u8>, c: char) {
+    let mut buf = [0; 4];
+    bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+}
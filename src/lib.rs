@@ -11,7 +11,9 @@ pub mod lua;
 pub mod prelude;
 pub mod standard;
 pub mod table;
+mod thread;
 mod token;
+pub mod types;
 pub mod userdata;
 pub mod value;
 pub extern crate gc_arena;
@@ -55,6 +57,36 @@ fn complex(source: &str) -> Result<ExVal, ErrorTuple> {
     // }
 }
 
+/** result of a script, or the message of its first error without the location */
+#[cfg(test)]
+fn outcome(source: &str) -> ExVal {
+    outcome_with_flags(source, compiler::LanguageFlags::default())
+}
+
+/** result of a script compiled with language flags, or the message of its first error without the location */
+#[cfg(test)]
+fn outcome_with_flags(source: &str, flags: compiler::LanguageFlags) -> ExVal {
+    let mut compiler = Compiler::with_language_flags(flags);
+    let mut lua = Lua::new_with_standard();
+    match lua.run(source, &mut compiler) {
+        Ok(v) => v,
+        Err(e) => ExVal::String(e[0].code.to_string()),
+    }
+}
+
+/** warnings the compiler raised for a script, it's run too so the warnings are final */
+#[cfg(test)]
+fn warnings_of(source: &str) -> Vec<LuaError> {
+    let mut compiler = Compiler::new();
+    let mut lua = Lua::new_with_standard();
+    let _ = lua.run(source, &mut compiler);
+    compiler
+        .get_warnings()
+        .iter()
+        .map(|w| w.code.clone())
+        .collect()
+}
+
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen]
 extern "C" {
@@ -115,13 +147,20 @@ mod tests {
     use crate::{
         chunk::Chunk,
         code::OpCode,
+        compiler::LanguageFlags,
         complex,
-        error::SiltError,
+        error::{RuntimeError, SiltError},
         function::FunctionObject,
+        lua::Limits,
+        outcome, outcome_with_flags,
         prelude::ValueTypes,
         simple,
+        table::Table,
         token::Token,
+        types::Type,
+        userdata::MetaMethod,
         value::{ExVal, Value},
+        warnings_of, Compiler, Lua,
     };
     use std::{mem::size_of, println};
 
@@ -499,4 +538,1904 @@ mod tests {
         "#;
         assert_eq!(simple(source_in), ExVal::String("hello".to_string()));
     }
+
+    #[test]
+    fn if_chains() {
+        valeq!(
+            r#"
+            function grade(n)
+                g = "f"
+                if n > 90 then
+                    g = "a"
+                elseif n > 80 then
+                    g = "b"
+                elseif n > 70 then
+                    g = "c"
+                end
+                return g
+            end
+            return grade(95) .. grade(85) .. grade(75) .. grade(10)
+            "#,
+            vstr!("abcf")
+        );
+        valeq!(
+            r#"
+            s = ""
+            if true then s = s .. "x" end
+            if false then s = s .. "y" elseif true then s = s .. "z" else s = s .. "w" end
+            s = s .. "!"
+            return s
+            "#,
+            vstr!("xz!")
+        );
+    }
+
+    #[test]
+    fn generic_for() {
+        valeq!(
+            r#"
+            t = {10, 20, 30, x = 5}
+            sum = 0
+            for k, v in pairs(t) do
+                sum = sum + v
+            end
+            return sum
+            "#,
+            ExVal::Integer(65)
+        );
+        valeq!(
+            r#"
+            s = ""
+            for i, v in ipairs({"a", "b", nil, "d"}) do
+                s = s .. i .. v
+            end
+            return s
+            "#,
+            vstr!("1a2b")
+        );
+        valeq!(
+            r#"
+            -- iterator function with state and control values
+            function iter(s, c)
+                if c < s then
+                    return c + 1, c * 2
+                end
+            end
+            total = 0
+            for i, d in iter, 3, 0 do
+                total = total + i + d
+            end
+            return total -- 1+0 + 2+2 + 3+4 = 12
+            "#,
+            ExVal::Integer(12)
+        );
+        valeq!(
+            r#"
+            t = {1, 2, 3, 4}
+            t[2] = nil
+            n = 0
+            for k in next, t do
+                n = n + k
+            end
+            return n -- 1+3+4 = 8
+            "#,
+            ExVal::Integer(8)
+        );
+        // pairs hands out the same next as the global, even once that's replaced
+        valeq!(
+            "n = 0 next = nil for k in pairs({5, 6}) do n = n + k end return n",
+            ExVal::Integer(3)
+        );
+        valeq!(
+            r#"
+            do
+                local function walk(t, i)
+                    if i < 3 then
+                        return i + 1, (i + 1) * 10
+                    end
+                end
+                local function custom(t)
+                    return walk, t, 0
+                end
+                local t = {x = 1}
+                setmetatable(t, { __pairs = custom })
+                local sum = 0
+                for k, v in pairs(t) do
+                    sum = sum + k + v
+                end
+                local ok, f, s, c = pcall(pairs, t)
+                return sum + c -- 1+10 + 2+20 + 3+30 = 66
+            end
+            "#,
+            ExVal::Integer(66)
+        );
+    }
+
+    #[test]
+    fn generic_for_closures() {
+        valeq!(
+            r#"
+            -- each iteration gets fresh locals so closures capture that iteration's value
+            do
+                local a = {}
+                for i, v in ipairs({7, 8, 9}) do
+                    local function t()
+                        return v
+                    end
+                    a[i] = t
+                end
+                return a[1]() + a[2]() + a[3]() -- 7+8+9 = 24
+            end
+            "#,
+            ExVal::Integer(24)
+        );
+    }
+
+    #[test]
+    fn next_table() {
+        valeq!(
+            r#"
+            t = {x = 5}
+            k, v = next(t)
+            return k .. v
+            "#,
+            vstr!("x5")
+        );
+        valeq!("return next({})", ExVal::Nil);
+        fails!(
+            "for k in pairs(5) do end",
+            SiltError::VmNonTableOperations(ValueTypes::Integer)
+        );
+        fails!("next({1}, 'nope')", SiltError::TableInvalidNextKey);
+    }
+
+    #[test]
+    fn table_removed_keys() {
+        // used as a queue the removed keys get dropped instead of piling up
+        let mut t = Table::new(0);
+        for i in 0..1000 {
+            t.insert(Value::Integer(i), Value::Integer(i));
+            if i >= 4 {
+                t.insert(Value::Integer(i - 4), Value::Nil);
+            }
+        }
+        assert_eq!(t.len(), 4);
+        assert!(t.slots() <= 16);
+        // clearing fields while walking them is still allowed
+        valeq!(
+            r#"
+            t = {}
+            for i = 1, 50 do t[i] = i end
+            for i = 1, 40 do t[i] = nil end
+            t.x = 1
+            n = 0
+            for k, v in pairs(t) do
+                t[k] = nil
+                n = n + 1
+            end
+            return n .. (next(t) or "")
+            "#,
+            vstr!("11")
+        );
+    }
+
+    #[test]
+    fn repeat_until() {
+        valeq!(
+            r#"
+            do
+                local r = 0
+                repeat
+                    local y = r + 1
+                    r = y
+                until y >= 3 -- until can see the block's locals
+                local after = 10
+                return r + after
+            end
+            "#,
+            ExVal::Integer(13)
+        );
+        valeq!(
+            r#"
+            n = 0
+            repeat
+                n = n + 1
+            until true
+            return n
+            "#,
+            ExVal::Integer(1)
+        );
+    }
+
+    #[test]
+    fn break_loops() {
+        valeq!(
+            r#"
+            do
+                local n = 0
+                while true do
+                    local a = n
+                    n = n + 1
+                    if n > 4 then break end
+                end
+                local s = 0
+                for i = 1, 10 do
+                    local x = i * 2
+                    if i == 4 then break end
+                    s = s + x
+                end
+                local g = 0
+                for k, v in ipairs({5, 6, 7, 8}) do
+                    if v == 7 then
+                        local q = 1
+                        break
+                    end
+                    g = g + v
+                end
+                local r = 0
+                repeat
+                    r = r + 1
+                    if r == 3 then break end
+                until false
+                local after = 100
+                return n + s + g + r + after -- 5+12+11+3+100
+            end
+            "#,
+            ExVal::Integer(131)
+        );
+        fails!("break", SiltError::BreakOutsideLoop);
+        fails!(
+            r#"
+            while true do
+                function g()
+                    break
+                end
+            end
+            "#,
+            SiltError::BreakOutsideLoop
+        );
+    }
+
+    #[test]
+    fn goto_statements() {
+        valeq!(
+            r#"
+            function f()
+                local s = 0
+                for i = 1, 5 do
+                    if i == 2 then goto continue end
+                    local x = i
+                    s = s + x
+                    ::continue::
+                end
+                local n = 0
+                ::top::
+                do
+                    local y = n + 1
+                    n = y
+                    if n < 3 then goto top end
+                end
+                for i = 1, 3 do
+                    for j = 1, 3 do
+                        if j == 2 then goto out end
+                        s = s + 100
+                    end
+                    ::out::
+                end
+                return s + n -- 13+300+3
+            end
+            return f()
+            "#,
+            ExVal::Integer(316)
+        );
+        valeq!(
+            r#"
+            function f()
+                local t = {}
+                local n = 0
+                ::again::
+                local x = n
+                n = n + 1
+                function g() return x end
+                t[n] = g
+                if n < 3 then goto again end
+                return t[1]() + t[2]() + t[3]()
+            end
+            return f()
+            "#,
+            ExVal::Integer(3)
+        );
+        fails!(
+            "goto nowhere",
+            SiltError::UndefinedLabel("nowhere".to_string())
+        );
+        fails!(
+            "do goto a end do ::a:: end",
+            SiltError::UndefinedLabel("a".to_string())
+        );
+        fails!(
+            "function f() goto skip local x = 1 ::skip:: x = 2 end",
+            SiltError::GotoIntoScope("skip".to_string())
+        );
+    }
+
+    #[test]
+    fn break_closes_upvalues() {
+        valeq!(
+            r#"
+            do
+                local fs = {}
+                local c = 0
+                while true do
+                    c = c + 1
+                    local z = c * 10
+                    local function get()
+                        return z
+                    end
+                    fs[c] = get
+                    if c == 3 then break end
+                end
+                local after = 1
+                return fs[1]() + fs[2]() + fs[3]() + after -- 10+20+30+1
+            end
+            "#,
+            ExVal::Integer(61)
+        );
+    }
+
+    #[test]
+    fn arithmetic_operators() {
+        valeq!("return 7 % 3", ExVal::Integer(1));
+        valeq!("return -7 % 3", ExVal::Integer(2));
+        valeq!("return 7 % -3", ExVal::Integer(-2));
+        valeq!("return 5.5 % 2", ExVal::Number(1.5));
+        valeq!("return -5.5 % 2", ExVal::Number(0.5));
+        valeq!("return 7 // 2", ExVal::Integer(3));
+        valeq!("return -7 // 2", ExVal::Integer(-4));
+        valeq!("return 7.0 // 2", ExVal::Number(3.0));
+        valeq!("return \"9\" // 2", ExVal::Integer(4));
+        valeq!("return 2 ^ 3", ExVal::Number(8.0));
+        valeq!("return 2 ^ 3 ^ 2", ExVal::Number(512.0));
+        valeq!("return -2 ^ 2", ExVal::Number(-4.0));
+        valeq!("return 2 ^ -1", ExVal::Number(0.5));
+        valeq!("return 1 + 2 * 3 % 4", ExVal::Integer(3));
+        valeq!("return (1 + 2) * 3", ExVal::Integer(9));
+        valeq!("return (1 + 2) .. \"x\"", vstr!("3x"));
+        fails!("return 1 % 0", SiltError::ExpIntegerDivideByZero(MetaMethod::Mod));
+        fails!("return 1 // 0", SiltError::ExpIntegerDivideByZero(MetaMethod::IDiv));
+        valeq!("return 1 // 0.0", ExVal::Number(f64::INFINITY));
+        valeq!("return (-9223372036854775807 - 1) // -1", ExVal::Integer(i64::MIN));
+        valeq!("return (-9223372036854775807 - 1) % -1", ExVal::Integer(0));
+    }
+
+    #[test]
+    fn arithmetic_metamethods() {
+        valeq!(
+            r#"
+            do
+                local function modder(a, b)
+                    return b + 1
+                end
+                local function power(a, b)
+                    return b * 2
+                end
+                local t = {}
+                setmetatable(t, { __mod = modder, __pow = power })
+                return t % 4 + t ^ 10
+            end
+            "#,
+            ExVal::Integer(25)
+        );
+        valeq!(
+            r#"
+            do
+                local function modder(a, b)
+                    return a
+                end
+                local function power(a, b)
+                    return a * 2
+                end
+                local function idiv(a, b)
+                    return a * 3
+                end
+                local t = {}
+                setmetatable(t, { __mod = modder, __pow = power, __idiv = idiv })
+                return 5 % t + 10 ^ t + 100 // t
+            end
+            "#,
+            ExVal::Integer(325)
+        );
+    }
+
+    #[test]
+    fn bitwise_operators() {
+        valeq!("return 6 & 3", ExVal::Integer(2));
+        valeq!("return 6 | 3", ExVal::Integer(7));
+        valeq!("return 6 ~ 3", ExVal::Integer(5));
+        valeq!("return ~0", ExVal::Integer(-1));
+        valeq!("return 1 << 4", ExVal::Integer(16));
+        valeq!("return 256 >> 4", ExVal::Integer(16));
+        valeq!("return -1 >> 60", ExVal::Integer(15));
+        valeq!("return 1 << 64", ExVal::Integer(0));
+        valeq!("return 1 << -1", ExVal::Integer(0));
+        valeq!("return 3.0 | 0", ExVal::Integer(3));
+        valeq!("return 1 | 2 ~ 3 & 4", ExVal::Integer(3));
+        valeq!("return 1 << 1 + 1", ExVal::Integer(4));
+        valeq!("return 5 & 3 == 1", ExVal::Bool(true));
+        fails!("return 1.5 | 0", SiltError::ExpNoIntegerRepresentation);
+        fails!(
+            "return true & 1",
+            SiltError::ExpInvalidBitwise(ValueTypes::Bool)
+        );
+    }
+
+    #[test]
+    fn bitwise_metamethods() {
+        valeq!(
+            r#"
+            do
+                local function band(a, b)
+                    return 10
+                end
+                local function bnot(a, b)
+                    return 20
+                end
+                local t = {}
+                setmetatable(t, { __band = band, __bnot = bnot })
+                return (t & 1) + ~t
+            end
+            "#,
+            ExVal::Integer(30)
+        );
+        valeq!(
+            r#"
+            do
+                local function band(a, b)
+                    return a
+                end
+                local t = {}
+                setmetatable(t, { __band = band })
+                return 5 & t
+            end
+            "#,
+            ExVal::Integer(5)
+        );
+        fails!(
+            "return 5 << test_ent()",
+            SiltError::ExpOpValueWithValue(
+                ValueTypes::Integer,
+                MetaMethod::Shl,
+                ValueTypes::UserData
+            )
+        );
+    }
+
+    #[test]
+    fn varargs() {
+        valeq!(
+            r#"
+            function f(a, ...)
+                local b, c = ...
+                local t = {...}
+                return a + b * 10 + c * 100 + #t * 1000
+            end
+            return f(1, 2, 3)
+            "#,
+            ExVal::Integer(2321)
+        );
+        valeq!(
+            r#"
+            function count(...)
+                return select('#', ...)
+            end
+            function pass(...)
+                return count(0, ...)
+            end
+            return pass(nil, nil, nil)
+            "#,
+            ExVal::Integer(4)
+        );
+        valeq!(
+            r#"
+            function f(a, b)
+                local c = 3
+                return a + c + (b or 0)
+            end
+            return f(1) + f(1, 2, 3, 4)
+            "#,
+            ExVal::Integer(10)
+        );
+        valeq!(
+            r#"
+            function last(...)
+                return ...
+            end
+            return last(7)
+            "#,
+            ExVal::Integer(7)
+        );
+        fails!(
+            r#"
+            function f(a)
+                return ...
+            end
+            "#,
+            SiltError::VarArgsOutsideFunction
+        );
+    }
+
+    #[test]
+    fn select_pack_unpack() {
+        valeq!("return select(2, 'a', 'b', 'c')", vstr!("b"));
+        valeq!("return select(-1, 'a', 'b', 'c')", vstr!("c"));
+        valeq!("return select('#')", ExVal::Integer(0));
+        fails!(
+            "return select(0, 'a')",
+            SiltError::VmBadArgument(1, "select".to_string(), "index out of range".to_string())
+        );
+        valeq!(
+            r#"
+            local t = table.pack(1, nil, 3)
+            return t.n
+            "#,
+            ExVal::Integer(3)
+        );
+        valeq!(
+            r#"
+            do
+                local a, b, c = table.unpack({4, 5, 6})
+                local d, e = table.unpack({4, 5, 6}, 2)
+                return a + b + c + d * 10 + e * 100
+            end
+            "#,
+            ExVal::Integer(665)
+        );
+        // only the array part counts, up to the first gap
+        valeq!(
+            "return select('#', table.unpack({1, 2, x = 5}))",
+            ExVal::Integer(2)
+        );
+        valeq!(
+            "return select('#', table.unpack({1, 2, nil, 4, y = 1}))",
+            ExVal::Integer(2)
+        );
+        fails!(
+            "return table.unpack({}, 1, 100000000)",
+            SiltError::VmUnpackTooMany
+        );
+    }
+
+    #[test]
+    fn function_declarations() {
+        valeq!(
+            r#"
+            local M = {}
+            function M.double(a) return M.add(a, a) end
+            function M.add(a, b) return a + b end
+            return M.double(4)
+            "#,
+            ExVal::Integer(8)
+        );
+        valeq!(
+            r#"
+            do
+                local M = { sub = {}, obj = { v = 3 } }
+                function M.sub.fn(a) return a + 1 end
+                function M.obj:method(b) return self.v + b end
+                return M.sub.fn(1) + M.obj:method(10)
+            end
+            "#,
+            ExVal::Integer(15)
+        );
+        // a local function can see itself, even from a nested function
+        valeq!(
+            r#"
+            do
+                local function fact(n)
+                    if n <= 1 then return 1 end
+                    return n * fact(n - 1)
+                end
+                local function walk(n)
+                    local function step() return walk(n - 1) end
+                    if n == 0 then return 0 end
+                    return step() + 1
+                end
+                return fact(5) + walk(3)
+            end
+            "#,
+            ExVal::Integer(123)
+        );
+    }
+
+    #[test]
+    fn protected_calls() {
+        let prelude = r#"
+            function add(a, b) return a + b, "sum" end
+            function boom() error("boom", 0) end
+            function throw_table() error({code = 7}) end
+            function broken() local t = nil return t.x end
+            function wrap(e) return "caught: " .. e end
+        "#;
+        let run = |source: &str| outcome(&format!("{}{}", prelude, source));
+        assert_eq!(
+            run("local ok, a, b = pcall(add, 1, 2) return (ok and 'yes' or 'no') .. a .. b"),
+            vstr!("yes3sum")
+        );
+        assert_eq!(
+            run("local ok, e = pcall(boom) return ok == false and e"),
+            vstr!("boom")
+        );
+        assert_eq!(
+            run("local ok, e = pcall(throw_table) return e.code"),
+            ExVal::Integer(7)
+        );
+        assert_eq!(
+            run("local ok, e = pcall(broken) return ok == false and e ~= nil"),
+            ExVal::Bool(true)
+        );
+        assert_eq!(
+            run("local ok, e = xpcall(boom, wrap) return e"),
+            vstr!("caught: boom")
+        );
+        assert_eq!(
+            run("local ok, e = pcall(nil) return ok"),
+            ExVal::Bool(false)
+        );
+        assert_eq!(
+            run("local ok, e = pcall(error, 'native') return e"),
+            vstr!("native")
+        );
+        // the stack is back where it was, locals around the pcall are untouched
+        assert_eq!(
+            run(r#"
+                local before = 10
+                function deep(n) if n == 0 then error("deep") end return deep(n - 1) + 1 end
+                function outer() local x = 5 local ok = pcall(deep, 20) return x + before end
+                local after = 2
+                return outer() + after
+                "#),
+            ExVal::Integer(17)
+        );
+        // nested pcalls only catch what's inside them
+        assert_eq!(
+            run(r#"
+                function inner() local ok, e = pcall(boom) error("again " .. e, 0) end
+                local ok, e = pcall(inner)
+                return e
+                "#),
+            vstr!("again boom")
+        );
+        // captured locals of unwound frames are closed, not left pointing at the stack
+        assert_eq!(
+            run(r#"
+                saved = nil
+                function capture() local n = 3 function get() return n end saved = get error("x") end
+                pcall(capture)
+                local a, b, c = 100, 200, 300
+                return saved()
+                "#),
+            ExVal::Integer(3)
+        );
+        // to-be-closed variables see the error on the way out
+        assert_eq!(
+            run(r#"
+                seen = nil
+                mt = {}
+                function mt.__close(v, e) seen = e end
+                function closable() local t = {} setmetatable(t, mt) return t end
+                function guarded() local c <close> = closable() error("closing", 0) end
+                pcall(guarded)
+                return seen
+                "#),
+            vstr!("closing")
+        );
+        assert_eq!(run("boom()"), vstr!("boom"));
+        assert_eq!(run("error({})"), vstr!("(error object is a table value)"));
+        // a function returning in tail position keeps the frame protected
+        assert_eq!(
+            run("function tail() return boom() end local ok, e = pcall(tail) return e"),
+            vstr!("boom")
+        );
+        assert_eq!(
+            run("function f() return pcall(boom) end local ok, e = f() return e"),
+            vstr!("boom")
+        );
+        // a native failing with it's own error is caught as the message
+        let mut compiler = Compiler::new();
+        let mut lua = Lua::new_with_standard();
+        lua.enter(|vm, mc| {
+            vm.register_native_function_multi(mc, "fail", |_, _, _: Vec<Value>| {
+                Err::<Vec<Value>, _>(SiltError::Custom("custom failure".to_string()))
+            });
+            Ok(ExVal::Nil)
+        })
+        .unwrap();
+        assert_eq!(
+            lua.run("local ok, e = pcall(fail) return e", &mut compiler)
+                .ok(),
+            Some(vstr!("custom failure"))
+        );
+    }
+
+    #[test]
+    fn runtime_error_locations() {
+        let source = r#"
+            function inner()
+                local t = nil
+                return t.x
+            end
+            function outer()
+                return inner() + 1
+            end
+            outer()
+        "#;
+        let mut compiler = Compiler::new();
+        let mut lua = Lua::new_with_standard();
+        let e = lua.run(source, &mut compiler).unwrap_err();
+        assert_eq!(e[0].location.0, 4);
+
+        // the structured error keeps every call that was active, innermost first
+        let trace = lua
+            .enter(|vm, mc| {
+                let f = Compiler::new().try_compile(mc, Some("trace.lua".to_string()), source);
+                let f = gc_arena::Gc::new(mc, f.ok().unwrap());
+                Ok(vm.execute(mc, f)?)
+            })
+            .unwrap_err();
+        assert_eq!(
+            trace.to_string(),
+            String::from(
+                "trace.lua:4: Attempt to index a nil value (local 't')\nstack traceback:\n\ttrace.lua:4: in function 'inner'\n\ttrace.lua:7: in function 'outer'\n\ttrace.lua:9: in main chunk"
+            )
+        );
+
+        // error's level picks whose position prefixes the message
+        assert_eq!(
+            outcome("function f()\n error('here')\nend\nlocal ok, e = pcall(f) return e"),
+            vstr!("?:2: here")
+        );
+        assert_eq!(
+            outcome("function f()\n error('there', 2)\nend\nfunction g()\n f()\nend\nlocal ok, e = pcall(g) return e"),
+            vstr!("?:5: there")
+        );
+        assert_eq!(
+            outcome("function f() error('plain', 0) end local ok, e = pcall(f) return e"),
+            vstr!("plain")
+        );
+        assert_eq!(outcome("error('top')"), vstr!("?:1: top"));
+        // debug.traceback as a message handler still sees the frames that failed
+        assert_eq!(
+            outcome("function f()\n error('deep', 0)\nend\nlocal ok, e = xpcall(f, debug.traceback) return e"),
+            vstr!("deep\nstack traceback:\n\t?:2: in function 'f'\n\t?:4: in main chunk")
+        );
+        assert_eq!(
+            outcome("function f()\n return debug.traceback('here')\nend\nlocal s = f() return s"),
+            vstr!("here\nstack traceback:\n\t?:2: in function 'f'\n\t?:4: in main chunk")
+        );
+        assert_eq!(
+            outcome(
+                "function f()\n return debug.traceback('skip', 2)\nend\nlocal s = f() return s"
+            ),
+            vstr!("skip\nstack traceback:\n\t?:4: in main chunk")
+        );
+        assert_eq!(
+            outcome("local r = debug.traceback({n = 5}) return r.n"),
+            ExVal::Integer(5)
+        );
+    }
+
+    #[test]
+    fn variable_error_names() {
+        assert_eq!(
+            outcome("return player.hp"),
+            vstr!("Attempt to index a nil value (global 'player')")
+        );
+        assert_eq!(
+            outcome("do local cfg = 5 return cfg.size end"),
+            vstr!("Attempt to index an integer value (local 'cfg')")
+        );
+        assert_eq!(
+            outcome("do local n = 5 n() end"),
+            vstr!("Attempt to call an integer value (local 'n')")
+        );
+        assert_eq!(
+            outcome("player = {} return player.pos.x"),
+            vstr!("Attempt to index a nil value (field 'pos')")
+        );
+        assert_eq!(
+            outcome("player = {} player.pos.x = 1"),
+            vstr!("Attempt to index a nil value (field 'pos')")
+        );
+        assert_eq!(
+            outcome("player = {} do local p = player.pos return p.x end"),
+            vstr!("Attempt to index a nil value (local 'p')")
+        );
+        assert_eq!(
+            outcome("local n = 1 return n + score"),
+            vstr!("Attempt to perform arithmetic on a nil value (global 'score')")
+        );
+        assert_eq!(
+            outcome("local t = {} return t.count * 2"),
+            vstr!("Attempt to perform arithmetic on a nil value (field 'count')")
+        );
+        assert_eq!(
+            outcome("return -missing"),
+            vstr!("Attempt to perform arithmetic on a nil value (global 'missing')")
+        );
+        assert_eq!(
+            outcome(
+                r#"
+                function counter()
+                    local count = nil
+                    function bump() count = count + 1 end
+                    return bump
+                end
+                local b = counter()
+                b()
+                "#
+            ),
+            vstr!("Attempt to perform arithmetic on a nil value (upvalue 'count')")
+        );
+        assert_eq!(
+            outcome("nope(1, 2)"),
+            vstr!("Attempt to call a nil value (global 'nope')")
+        );
+        assert_eq!(
+            outcome("local t = {} t.run()"),
+            vstr!("Attempt to call a nil value (field 'run')")
+        );
+        assert_eq!(
+            outcome("local t = {} t:run(1)"),
+            vstr!("Attempt to call a nil value (method 'run')")
+        );
+        // slots are reused once a local leaves scope, the name is the one live at the time
+        assert_eq!(
+            outcome("do local a = {} end do local b = nil return b.x end"),
+            vstr!("Attempt to index a nil value (local 'b')")
+        );
+        // values we can't follow back to a variable keep the plain message
+        assert_eq!(
+            outcome("return (x or y) + 1"),
+            vstr!("Cannot + 'nil' and 'integer'")
+        );
+        assert_eq!(
+            outcome("local t = {} return t[1][2]"),
+            vstr!("Attempt to index a nil value (field '?')")
+        );
+    }
+
+    #[test]
+    fn coroutines() {
+        // values pass both ways, yield returns what the next resume was given
+        assert_eq!(
+            outcome(
+                r#"
+                function body(a, b)
+                    local c, d = coroutine.yield(a + b, a - b)
+                    return c * d, "done"
+                end
+                co = coroutine.create(body)
+                local ok1, s, d = coroutine.resume(co, 5, 3)
+                local ok2, p, msg = coroutine.resume(co, 4, 6)
+                return (ok1 and ok2) and s .. d .. p .. msg
+                "#
+            ),
+            vstr!("8224done")
+        );
+        // yields from deeper lua calls and picks back up in them
+        assert_eq!(
+            outcome(
+                r#"
+                total = 0
+                function step(i) total = total + i coroutine.yield(total) end
+                function count(n) for i = 1, n do step(i) end return "end" end
+                co = coroutine.create(count)
+                out = ""
+                for i = 1, 5 do
+                    local ok, v = coroutine.resume(co, 3)
+                    out = out .. v .. ","
+                end
+                return out
+                "#
+            ),
+            vstr!("1,3,6,end,Cannot resume dead coroutine,")
+        );
+        // an upvalue left open on the coroutine's stack is still shared while it's suspended
+        assert_eq!(
+            outcome(
+                r#"
+                function body()
+                    local count = 0
+                    function bump() count = count + 1 return count end
+                    coroutine.yield(bump)
+                    coroutine.yield(count)
+                end
+                co = coroutine.create(body)
+                local _, bump = coroutine.resume(co)
+                bump()
+                bump()
+                local _, seen = coroutine.resume(co)
+                return seen
+                "#
+            ),
+            ExVal::Integer(2)
+        );
+        assert_eq!(
+            outcome(
+                r#"
+                function body() inside = coroutine.status(co) coroutine.yield() end
+                co = coroutine.create(body)
+                local a = coroutine.status(co)
+                coroutine.resume(co)
+                local b = coroutine.status(co)
+                coroutine.resume(co)
+                return a .. inside .. b .. coroutine.status(co)
+                "#
+            ),
+            vstr!("suspendedrunningsuspendeddead")
+        );
+        assert_eq!(
+            outcome(
+                r#"
+                function peek() seen = coroutine.status(outer) end
+                function resume_inner() coroutine.resume(coroutine.create(peek)) end
+                outer = coroutine.create(resume_inner)
+                coroutine.resume(outer)
+                return seen
+                "#
+            ),
+            vstr!("normal")
+        );
+        assert_eq!(
+            outcome(
+                r#"
+                function upto(n) for i = 1, n do coroutine.yield(i) end end
+                local sum = 0
+                for v in coroutine.wrap(upto), 4 do
+                    sum = sum + v
+                end
+                return sum
+                "#
+            ),
+            ExVal::Integer(10)
+        );
+        assert_eq!(
+            outcome(
+                r#"
+                function plus_one() return coroutine.yield(1) + 1 end
+                function body() local ok, v = pcall(plus_one) return v end
+                co = coroutine.create(body)
+                coroutine.resume(co)
+                local _, v = coroutine.resume(co, 41)
+                return v
+                "#
+            ),
+            ExVal::Integer(42)
+        );
+        assert_eq!(
+            outcome(
+                r#"
+                function check() inside = coroutine.isyieldable() end
+                coroutine.wrap(check)()
+                return coroutine.isyieldable() == false and inside
+                "#
+            ),
+            ExVal::Bool(true)
+        );
+        // errors end the coroutine, resume returns them and a wrapped one raises them
+        assert_eq!(
+            outcome(
+                r#"
+                function bad() error("bad", 0) end
+                co = coroutine.create(bad)
+                local ok, e = coroutine.resume(co)
+                return ok == false and e .. coroutine.status(co)
+                "#
+            ),
+            vstr!("baddead")
+        );
+        assert_eq!(
+            outcome(
+                r#"
+                function oops() error("oops", 0) end
+                local ok, e = pcall(coroutine.wrap(oops))
+                return e
+                "#
+            ),
+            vstr!("oops")
+        );
+        assert_eq!(
+            outcome("coroutine.yield(1)"),
+            vstr!("Attempt to yield from outside a coroutine")
+        );
+        // natives can't be suspended so they aren't taken as a body
+        assert_eq!(
+            outcome("return coroutine.create(print)"),
+            vstr!("Bad argument #1 to 'create' (silt can't run a native function as a coroutine, wrap it in a lua function)")
+        );
+        assert_eq!(
+            outcome("return coroutine.wrap(1)"),
+            vstr!("Bad argument #1 to 'wrap' (function expected, got integer)")
+        );
+        // a body ending in a tail call still finishes the coroutine with its results
+        assert_eq!(
+            outcome(
+                r#"
+                function two() return 7 end
+                function body() return two() end
+                local co = coroutine.create(body)
+                local ok, v = coroutine.resume(co)
+                return v .. coroutine.status(co)
+                "#
+            ),
+            vstr!("7dead")
+        );
+    }
+
+    #[test]
+    fn stack_overflow() {
+        let run = |source: &str, limits: Limits| {
+            let mut compiler = Compiler::new();
+            let mut lua = Lua::new_with_standard();
+            lua.set_limits(limits);
+            match lua.run(source, &mut compiler) {
+                Ok(v) => v,
+                Err(e) => ExVal::String(e[0].code.to_string()),
+            }
+        };
+        // well past the starting stack, it grows as the calls pile up
+        assert_eq!(
+            run(
+                r#"
+                function sum(n) if n == 0 then return 0 end return n + sum(n - 1) end
+                return sum(5000)
+                "#,
+                Limits::default()
+            ),
+            ExVal::Integer(12502500)
+        );
+        // upvalues left open while the stack moves still point at their locals
+        assert_eq!(
+            run(
+                r#"
+                function deep(n, get)
+                    if n == 0 then return get() end
+                    return deep(n - 1, get) + 1
+                end
+                function outer()
+                    local x = 7
+                    function get() return x end
+                    local r = deep(1000, get)
+                    x = x + 1
+                    return r + get()
+                end
+                return outer()
+                "#,
+                Limits::default()
+            ),
+            ExVal::Integer(1015)
+        );
+        // runaway recursion is caught instead of taking the host down
+        assert_eq!(
+            run(
+                r#"
+                function forever(n) return 1 + forever(n + 1) end
+                local ok, e = pcall(forever, 0)
+                return not ok and e
+                "#,
+                Limits::default()
+            ),
+            vstr!("Stack overflow")
+        );
+        // each limit is reported on its own
+        let forever = r#"
+            function forever(n) return 1 + forever(n + 1) end
+            return forever(0)
+            "#;
+        assert_eq!(
+            run(
+                forever,
+                Limits {
+                    stack: 1_000_000,
+                    calls: 100
+                }
+            ),
+            vstr!("Stack overflow")
+        );
+        assert_eq!(
+            run(
+                forever,
+                Limits {
+                    stack: 300,
+                    calls: 200_000
+                }
+            ),
+            vstr!("Stack overflow")
+        );
+        // values pushed past the limit overflow the same as calls do
+        let run = |source: &str| {
+            let mut lua = Lua::new_with_standard();
+            lua.set_limits(Limits {
+                stack: 100,
+                calls: 200_000,
+            });
+            lua.enter(|vm, mc| {
+                vm.register_native_function_multi(mc, "many", |_, _, n: Vec<Value>| match n[..] {
+                    [Value::Integer(n)] => Ok(vec![Value::Integer(1); n as usize]),
+                    _ => Err(SiltError::Unknown),
+                });
+                Ok(ExVal::Nil)
+            })
+            .unwrap();
+            match lua.run(source, &mut Compiler::new()) {
+                Ok(v) => v,
+                Err(e) => ExVal::String(e[0].code.to_string()),
+            }
+        };
+        assert_eq!(run("return select('#', many(50))"), ExVal::Integer(50));
+        assert_eq!(
+            run("return select('#', many(150))"),
+            vstr!("Stack overflow")
+        );
+        assert_eq!(
+            run("return select('#', table.unpack({}, 1, 99))"),
+            vstr!("Stack overflow")
+        );
+        assert_eq!(
+            run("function f() return select('#', many(150)) end local ok, e = pcall(f) return e"),
+            vstr!("Stack overflow")
+        );
+        // the traceback holds every call up to the limit, innermost first
+        let mut lua = Lua::new_with_standard();
+        lua.set_limits(Limits {
+            stack: 1_000_000,
+            calls: 50,
+        });
+        let e = lua
+            .enter(|vm, mc| {
+                let f = Compiler::new().try_compile(mc, None, forever);
+                let f = gc_arena::Gc::new(mc, f.ok().unwrap());
+                Ok(vm.execute(mc, f)?)
+            })
+            .unwrap_err()
+            .downcast::<RuntimeError>()
+            .unwrap();
+        assert!(matches!(e.code, SiltError::StackOverflow));
+        assert_eq!(e.traceback.len(), 50);
+        assert_eq!(e.traceback[0].function.as_deref(), Some("forever"));
+    }
+
+    #[test]
+    fn multi_value_propagation() {
+        let prelude = r#"
+            function f() return 1, 2, 3 end
+            function none() end
+            function count(...) return select('#', ...) end
+        "#;
+        let run = |source: &str| {
+            let mut compiler = Compiler::new();
+            let mut lua = Lua::new_with_standard();
+            lua.run(&format!("{}{}", prelude, source), &mut compiler)
+                .ok()
+        };
+        // only the last expression in a list expands
+        assert_eq!(run("return count(f())"), Some(ExVal::Integer(3)));
+        assert_eq!(run("return count(f(), f())"), Some(ExVal::Integer(4)));
+        assert_eq!(run("return count(none())"), Some(ExVal::Integer(0)));
+        assert_eq!(run("return count(1, none())"), Some(ExVal::Integer(1)));
+        assert_eq!(
+            run("local t = {f(), f()} return #t"),
+            Some(ExVal::Integer(4))
+        );
+        assert_eq!(run("local t = {f(), x = 1} return t[2]"), Some(ExVal::Nil));
+        assert_eq!(
+            run("function g(a, b, c, d) return a * 1000 + b * 100 + c * 10 + d end return g(9, f())"),
+            Some(ExVal::Integer(9123))
+        );
+        assert_eq!(
+            run("function lead() return 0, f() end local a, b, c, d = lead() return a + b * 10 + c * 100 + d * 1000"),
+            Some(ExVal::Integer(3210))
+        );
+        // parentheses and `and`/`or` cut a call down to one value
+        assert_eq!(run("return count((f()))"), Some(ExVal::Integer(1)));
+        assert_eq!(
+            run("function paren() return (f()) end local a, b = paren() return b"),
+            Some(ExVal::Nil)
+        );
+        assert_eq!(
+            run("function either(x) return x and f() end local a, b = either(true) return b"),
+            Some(ExVal::Nil)
+        );
+        assert_eq!(run("return count(true and f())"), Some(ExVal::Integer(1)));
+    }
+
+    #[test]
+    fn tail_calls() {
+        // deeper than the stack could hold if each call kept its frame
+        valeq!(
+            r#"
+            function count(n, acc)
+                if n == 0 then return acc end
+                return count(n - 1, acc + 1)
+            end
+            return count(200000, 0)
+            "#,
+            ExVal::Integer(200000)
+        );
+        valeq!(
+            r#"
+            function state_a(n) if n <= 0 then return "a" end return state_b(n - 1) end
+            function state_b(n) if n <= 0 then return "b" end return state_a(n - 1) end
+            return state_a(100001)
+            "#,
+            vstr!("b")
+        );
+        // every value reaches the original caller, native functions included
+        valeq!(
+            r#"
+            function pair() return 1, 2 end
+            function relay() return pair() end
+            function rest(...) return select(2, ...) end
+            local a, b = relay()
+            local c, d = rest(7, 8, 9)
+            return a * 1000 + b * 100 + c * 10 + d
+            "#,
+            ExVal::Integer(1289)
+        );
+        // upvalues of the replaced frame are closed
+        valeq!(
+            r#"
+            function up(n, keep)
+                local k = n * 2
+                local function get() return k end
+                if n == 0 then return keep end
+                return up(n - 1, keep or get)
+            end
+            return up(3)()
+            "#,
+            ExVal::Integer(6)
+        );
+        valeq!(
+            r#"
+            function nine() return 9 end
+            function guard(v) return v and nine() end
+            local a = guard(false)
+            return a
+            "#,
+            ExVal::Bool(false)
+        );
+    }
+
+    #[test]
+    fn method_calls() {
+        valeq!(
+            r#"
+            Account = { balance = 10 }
+            function Account:deposit(v)
+                self.balance = self.balance + v
+                return self.balance
+            end
+            Account:deposit(5)
+            return Account:deposit(2)
+            "#,
+            ExVal::Integer(17)
+        );
+        valeq!(
+            r#"
+            shapes = { square = { side = 3 } }
+            function shapes.square:area(scale)
+                return self.side * self.side * scale
+            end
+            return shapes.square:area(2)
+            "#,
+            ExVal::Integer(18)
+        );
+        valeq!(
+            r#"
+            calls = 0
+            counter = { n = 4 }
+            function counter.get(self) return self.n end
+            function fetch()
+                calls = calls + 1
+                return counter
+            end
+            return fetch():get() + calls * 10
+            "#,
+            ExVal::Integer(14)
+        );
+        valeq!(
+            r#"
+            local e = test_ent()
+            return e:test()
+            "#,
+            ExVal::Integer(3)
+        );
+    }
+
+    #[test]
+    fn call_sugar() {
+        valeq!(
+            r#"
+            function node(t) return t.name end
+            return node{ name = "x" }
+            "#,
+            vstr!("x")
+        );
+        valeq!(
+            r#"
+            function join(a)
+                function rest(b) return a .. b end
+                return rest
+            end
+            return join"a""b"
+            "#,
+            vstr!("ab")
+        );
+        valeq!(
+            r#"
+            function id(s) return s end
+            return id[[long
+string]]
+            "#,
+            vstr!("long\nstring")
+        );
+        valeq!(
+            r#"
+            builder = { count = 1 }
+            function builder:add(t) return self.count + t[1] end
+            function builder:greet(s) return s .. self.count end
+            return builder:add{ 4 } .. builder:greet"hi"
+            "#,
+            vstr!("5hi1")
+        );
+    }
+
+    #[test]
+    fn string_escapes() {
+        valeq!(r#"return "a\nb\tc""#, vstr!("a\nb\tc"));
+        valeq!(r#"return "\"q\" \\ \'""#, vstr!("\"q\" \\ '"));
+        valeq!(
+            r#"return '\'' .. "\a\b\f\v\r""#,
+            vstr!("'\x07\x08\x0c\x0b\r")
+        );
+        valeq!(r#"return "\65\066\0671""#, vstr!("ABC1"));
+        valeq!(r#"return "\x41\x62""#, vstr!("Ab"));
+        valeq!(r#"return "\xE2\x82\xAC\u{20AC}\u{1F600}""#, vstr!("€€😀"));
+        valeq!(r#"return "\u{10FFFF}""#, vstr!("\u{10FFFF}"));
+        valeq!("return \"a\\z\n      b\"", vstr!("ab"));
+        valeq!("return \"a\\\nb\"", vstr!("a\nb"));
+        valeq!(r#"return #"\0\n""#, ExVal::Integer(2));
+    }
+
+    #[test]
+    fn string_escape_errors() {
+        fails!(
+            r#"return "\q""#,
+            SiltError::InvalidEscapeSequence("\\q".to_string())
+        );
+        fails!(
+            r#"return "\x4g""#,
+            SiltError::InvalidEscapeSequence("\\x4".to_string())
+        );
+        fails!(
+            r#"return "\256""#,
+            SiltError::EscapeTooLarge("\\256".to_string())
+        );
+        fails!(
+            r#"return "\u{80000000}""#,
+            SiltError::EscapeTooLarge("\\u{800000".to_string())
+        );
+        fails!(
+            r#"return "\u{110000}""#,
+            SiltError::EscapeTooLarge("\\u{110000".to_string())
+        );
+        fails!(
+            r#"return "\u{D800}""#,
+            SiltError::EscapeSurrogate("\\u{D800}".to_string())
+        );
+        fails!(
+            r#"return "\u{12""#,
+            SiltError::InvalidEscapeSequence("\\u{12".to_string())
+        );
+        // strings are utf8 so a byte escape has to be part of a whole character
+        fails!(r#"return "\xff""#, SiltError::InvalidUtf8String);
+        fails!(r#"return "\255""#, SiltError::InvalidUtf8String);
+        // the rest of the string is passed over so only the escape is reported
+        let mut compiler = Compiler::new();
+        match Lua::new_with_standard().run(r#"a = "x\qy" b = 'z\x'"#, &mut compiler) {
+            Ok(_) => panic!("Expected error"),
+            Err(e) => assert_eq!(
+                e.iter().map(|e| e.code.clone()).collect::<Vec<_>>(),
+                vec![
+                    SiltError::InvalidEscapeSequence("\\q".to_string()),
+                    SiltError::InvalidEscapeSequence("\\x".to_string())
+                ]
+            ),
+        }
+        match complex("a = 1\nb = \"ok\\k\"") {
+            Ok(_) => panic!("Expected error"),
+            Err(e) => assert_eq!(e.location, (2, 8)),
+        }
+    }
+
+    #[test]
+    fn long_brackets() {
+        valeq!("return [[\nfirst\nsecond]]", vstr!("first\nsecond"));
+        valeq!("return [==[a]]b]=]c]==]", vstr!("a]]b]=]c"));
+        valeq!("return [=[\r\n[[x]]]=]", vstr!("[[x]]"));
+        valeq!("return [[]]", vstr!(""));
+        valeq!(
+            r#"
+            --[==[ a long comment
+            with ]] inside
+            ]==] x = 1 --[[ inline ]] y = 2
+            --[ just a line comment
+            return x + y
+            "#,
+            ExVal::Integer(3)
+        );
+        fails!("return [==x", SiltError::InvalidLongStringDelimiter);
+        fails!("return [=[ abc ]]", SiltError::UnterminatedString);
+        fails!("--[[ abc", SiltError::UnterminatedComment);
+        match complex("s = [[a\nb\n]] z = 1\n  \"open") {
+            Ok(_) => panic!("Expected error"),
+            Err(e) => assert_eq!(e.location.0, 4),
+        }
+    }
+
+    #[test]
+    fn numeric_literals() {
+        valeq!("return 0xFF", ExVal::Integer(255));
+        valeq!("return 0Xa0 + 0x1", ExVal::Integer(161));
+        valeq!("return 0xffffffffffffffff", ExVal::Integer(-1));
+        valeq!("return 0x10000000000000001", ExVal::Integer(1));
+        valeq!("return 0x1p4", ExVal::Number(16.));
+        valeq!("return 0x.8P1", ExVal::Number(1.));
+        valeq!("return 0xA.8p-1", ExVal::Number(5.25));
+        valeq!("return 1e3", ExVal::Number(1000.));
+        valeq!("return 2.5E-1", ExVal::Number(0.25));
+        valeq!("return .5e+1", ExVal::Number(5.));
+        valeq!("return 6.02E23", ExVal::Number(6.02e23));
+        valeq!(
+            "return 9223372036854775808",
+            ExVal::Number(9223372036854775808.)
+        );
+        valeq!("return 9223372036854775807", ExVal::Integer(i64::MAX));
+        fails!("return 0x", SiltError::InvalidNumber("0x".to_string()));
+        fails!("return 0xfg", SiltError::InvalidNumber("0xf".to_string()));
+        fails!("return 1e", SiltError::InvalidNumber("1e".to_string()));
+        fails!("return 1e2.5", SiltError::InvalidNumber("1e2".to_string()));
+        fails!("return 0x1p", SiltError::InvalidNumber("0x1p".to_string()));
+    }
+
+    #[test]
+    fn arrow_functions() {
+        let flags = LanguageFlags {
+            arrow_functions: true,
+            ..LanguageFlags::default()
+        };
+        let arrow = |source: &str| outcome_with_flags(source, flags);
+        assert_eq!(arrow("f = a -> a + 1 return f(2)"), ExVal::Integer(3));
+        assert_eq!(
+            arrow("mul = (a, b) -> a * b return mul(3, 4)"),
+            ExVal::Integer(12)
+        );
+        assert_eq!(
+            arrow("g = () -> do local x = 2 x * 5 end return g()"),
+            ExVal::Integer(10)
+        );
+        assert_eq!(
+            arrow(
+                r#"
+                function counter()
+                    local n = 0
+                    return () -> do
+                        n = n + 1
+                        return n
+                    end
+                end
+                c = counter()
+                c()
+                return c()
+                "#
+            ),
+            ExVal::Integer(2)
+        );
+        assert_eq!(
+            arrow(
+                r#"
+                function apply(fn, v, w) return fn(v) + w end
+                return apply(x -> x * 2, 20, 2)
+                "#
+            ),
+            ExVal::Integer(42)
+        );
+        assert_eq!(arrow("a = 3 return (a) * 2 + (a + 1)"), ExVal::Integer(10));
+        fails!("f = a -> a", SiltError::ArrowFunctionsDisabled);
+        fails!("f = (a, b) -> a", SiltError::ArrowFunctionsDisabled);
+    }
+
+    #[test]
+    fn string_interpolation() {
+        let flags = LanguageFlags {
+            interpolation: true,
+            ..LanguageFlags::default()
+        };
+        let template = |source: &str| outcome_with_flags(source, flags);
+        assert_eq!(
+            template("player = {hp = 7} max = 10 return `hp: {player.hp}/{max}`"),
+            vstr!("hp: 7/10")
+        );
+        assert_eq!(template("return `plain text`"), vstr!("plain text"));
+        assert_eq!(template("a = 4 return `{a}`"), vstr!("4"));
+        assert_eq!(
+            template("return `{nil} {true} {1 + 1.5}`"),
+            vstr!("nil true 2.5")
+        );
+        assert_eq!(
+            template(r#"t = {n = "}"} return `{t["n"]} {"`"} {#{1, 2}}`"#),
+            vstr!("} ` 2")
+        );
+        assert_eq!(
+            template("x = 2 return `outer {`inner {x * 3}`} end`"),
+            vstr!("outer inner 6 end")
+        );
+        assert_eq!(template(r"return `\{a\} \` \t.`"), vstr!("{a} ` \t."));
+        fails!("return `a{1}`", SiltError::InterpolationDisabled);
+    }
+
+    #[test]
+    fn type_annotations() {
+        valeq!("local x: number = 5 return x", ExVal::Integer(5));
+        valeq!(
+            "do local s: string = 'a' s = s .. 'b' return s end",
+            vstr!("ab")
+        );
+        valeq!(
+            "function add(a: integer, b: number): number return a + b end return add(1, 2.5)",
+            ExVal::Number(3.5)
+        );
+        valeq!("local t: table = {} local n: nil return n", ExVal::Nil);
+        valeq!("local a: any = 1 a = 'b' return a", vstr!("b"));
+        // a declaration may start out nil either way
+        valeq!("local x: number return x", ExVal::Nil);
+        valeq!("local x: number = nil return x", ExVal::Nil);
+        valeq!("local x: number, y: string = 1, 'a' return y", vstr!("a"));
+        valeq!(
+            "do local x: number, y: string = nil, 'a' return x end",
+            ExVal::Nil
+        );
+        fails!(
+            "local x: number, y = 'str', 2",
+            SiltError::TypeMismatch(Type::Number, Type::String)
+        );
+        fails!(
+            "do local x, y: string = 1, 2 end",
+            SiltError::TypeMismatch(Type::String, Type::Integer)
+        );
+        fails!(
+            "do local x: integer, y = 1, 2 x = 'a' end",
+            SiltError::TypeMismatch(Type::Integer, Type::String)
+        );
+        fails!(
+            "local x: number = 'a'",
+            SiltError::TypeMismatch(Type::Number, Type::String)
+        );
+        fails!(
+            "local x: integer = 1.5",
+            SiltError::TypeMismatch(Type::Integer, Type::Number)
+        );
+        fails!(
+            "do local b: boolean = true b = 1 end",
+            SiltError::TypeMismatch(Type::Boolean, Type::Integer)
+        );
+        fails!(
+            "global g: string = 'a' g = 1 + 2",
+            SiltError::TypeMismatch(Type::String, Type::Integer)
+        );
+        fails!(
+            "function f(a: string) return a end f(2)",
+            SiltError::TypeMismatch(Type::String, Type::Integer)
+        );
+        fails!(
+            "function f(): string return 1 end",
+            SiltError::TypeMismatch(Type::String, Type::Integer)
+        );
+        fails!(
+            "function f(): number return 1 end local s: string = f()",
+            SiltError::TypeMismatch(Type::String, Type::Number)
+        );
+        fails!(
+            "function f(a) a = 'b' end local x: number = {}",
+            SiltError::TypeMismatch(Type::Number, Type::Table)
+        );
+        fails!(
+            "local x: strang = 1",
+            SiltError::UnknownType("strang".to_string())
+        );
+        match complex("a = 1\nlocal b: boolean = 'no'") {
+            Ok(_) => panic!("Expected error"),
+            Err(e) => assert_eq!(e.location.0, 2),
+        }
+    }
+
+    #[test]
+    fn type_inference() {
+        assert_eq!(
+            warnings_of("do local t = {} local s = 'a' .. t end"),
+            vec![SiltError::AttemptConcat(Type::Table)]
+        );
+        assert_eq!(
+            warnings_of("do local n = 5 n() end"),
+            vec![SiltError::AttemptCall(Type::Integer)]
+        );
+        assert_eq!(
+            warnings_of("do local b = true local c = b c = c + 1 end"),
+            vec![SiltError::AttemptArithmetic(Type::Boolean)]
+        );
+        assert_eq!(
+            warnings_of("do local function f() return {} end local x = f() .. 'a' end"),
+            vec![SiltError::AttemptConcat(Type::Table)]
+        );
+        assert_eq!(
+            warnings_of("do local function f() return 1 end local s: string = f() end"),
+            vec![SiltError::TypeMismatch(Type::String, Type::Integer)]
+        );
+        // reassigned or only maybe assigned values aren't held against them
+        assert!(warnings_of("do local t = {} t = 'a' local s = t .. 'b' end").is_empty());
+        assert!(
+            warnings_of("do local t = 'a' if true then t = {} end local s = t .. 'b' end")
+                .is_empty()
+        );
+        assert!(warnings_of("do local n = 1 local function f() n = f end f() n() end").is_empty());
+        assert!(warnings_of("local s = ('a'):upper()").is_empty());
+        // a warning still runs
+        valeq!(
+            "do local function f() return 1 end local s: string = f() return s end",
+            ExVal::Integer(1)
+        );
+        let mut compiler = Compiler::new();
+        let out = compiler.lsp("do local t = {} return t .. 'a' end", false);
+        assert!(out.to_string().contains("Attempt to concatenate a table"));
+    }
+
+    #[test]
+    fn compound_assignment() {
+        valeq!("local a = 1 a += 2 return a", ExVal::Integer(3));
+        valeq!(
+            "do local a = 10 a -= 4 a *= 3 a %= 7 return a end",
+            ExVal::Integer(4)
+        );
+        valeq!("do local a = 3 a /= 2 return a end", ExVal::Number(1.5));
+        valeq!(
+            "do local n = 1 local function f() n += 5 end f() f() return n end",
+            ExVal::Integer(11)
+        );
+        valeq!(
+            "t = {a = {5, {b = 2}}} t.a[2].b += 40 return t.a[2].b",
+            ExVal::Integer(42)
+        );
+        // the index expression only runs once
+        valeq!(
+            r#"
+            do
+                local calls = 0
+                local function key() calls += 1 return 1 end
+                local t = {10}
+                t[key()] *= 3
+                return t[1] + calls
+            end
+            "#,
+            ExVal::Integer(31)
+        );
+        valeq!(
+            r#"
+            do
+                local function adder(a, b) return 100 + b end
+                local t = {}
+                setmetatable(t, { __add = adder })
+                local s = { v = t }
+                s.v += 1
+                return s.v
+            end
+            "#,
+            ExVal::Integer(101)
+        );
+        valeq!(
+            r#"
+            do
+                local function adder(a, b) return a + 100 end
+                local t = {}
+                setmetatable(t, { __add = adder })
+                local x = 1
+                x += t
+                return x
+            end
+            "#,
+            ExVal::Integer(101)
+        );
+        // a path off a call result is still a path
+        valeq!(
+            r#"
+            t = {x = 1, y = {2}}
+            function f() return t end
+            f().x += 10
+            f().y[1] *= 3
+            f()["z"] = 4
+            return t.x + t.y[1] + t.z
+            "#,
+            ExVal::Integer(21)
+        );
+        fails!(
+            "function f() return {x = 1} end return 1 + f().x += 1",
+            SiltError::InvalidAssignment(Token::AddAssign)
+        );
+        fails!(
+            "local a += 1",
+            SiltError::InvalidAssignment(Token::AddAssign)
+        );
+        fails!(
+            "do local n: integer = 1 n /= 2 end",
+            SiltError::TypeMismatch(Type::Integer, Type::Number)
+        );
+    }
+
+    #[test]
+    fn table_multi_assignment() {
+        valeq!(
+            "t = {x = 1, y = 2} t.x, t.y = t.y, t.x return t.x * 10 + t.y",
+            ExVal::Integer(21)
+        );
+        valeq!(
+            r#"
+            do
+                local a = {5, 6, 7}
+                local i, j = 1, 3
+                a[i], a[j] = a[j], a[i]
+                return a[1] * 100 + a[2] * 10 + a[3]
+            end
+            "#,
+            ExVal::Integer(765)
+        );
+        // a trailing call fills the rest of the targets
+        valeq!(
+            r#"
+            do
+                local function f() return 1, 2, 3 end
+                local t = {}
+                local a, c
+                a, t.b, c = f()
+                return a * 100 + t.b * 10 + c
+            end
+            "#,
+            ExVal::Integer(123)
+        );
+        // keys are evaluated before any store
+        valeq!(
+            r#"
+            do
+                local i = 1
+                local a = {}
+                i, a[i] = i + 1, 20
+                return a[1] + i
+            end
+            "#,
+            ExVal::Integer(22)
+        );
+        valeq!(
+            "t = {a = {b = {}}} t.a.b.c, x = 4, 5 return t.a.b.c + x",
+            ExVal::Integer(9)
+        );
+        valeq!("t = {x = 3} t.x, y = 1 return y", ExVal::Nil);
+        // still just a list of values when there is no assignment
+        valeq!(
+            "do local t = {x = 1, y = 2} local function f(a, b) return a - b end return f(t.x, t.y) end",
+            ExVal::Integer(-1)
+        );
+        valeq!(
+            "do local a, t = 7, {y = 2} local function f(a, b, c) return a - b - c end return f(a, t.y, 1) end",
+            ExVal::Integer(4)
+        );
+        fails!(
+            "t = {} t.x, 5 = 1, 2",
+            SiltError::InvalidAssignment(Token::Assign)
+        );
+    }
+
+    #[test]
+    fn attributes() {
+        valeq!("do local x <const> = 5 return x + 1 end", ExVal::Integer(6));
+        valeq!(
+            "do local s <const> = 'hi' local function f() return s end return f() end",
+            vstr!("hi")
+        );
+        valeq!("local g <const> = 2 return g * 3", ExVal::Integer(6));
+        fails!(
+            "do local x <const> = 1 x = 2 end",
+            SiltError::AssignToConst("x".to_string())
+        );
+        fails!(
+            "do local x <const> = 1 x += 2 end",
+            SiltError::AssignToConst("x".to_string())
+        );
+        fails!(
+            "do local a, b <close> = 1, nil b = 2 end",
+            SiltError::AssignToConst("b".to_string())
+        );
+        fails!(
+            "local x <cons> = 1",
+            SiltError::UnknownAttribute("cons".to_string())
+        );
+        fails!(
+            "do local a <close>, b <close> = nil, nil end",
+            SiltError::MultipleToBeClosed
+        );
+        fails!(
+            "do local a <close> = {} end",
+            SiltError::MetaMethodMissing(MetaMethod::Close)
+        );
+    }
+
+    #[test]
+    fn to_be_closed() {
+        // closable(name) gives a table whose __close appends it's name and any error to log
+        let prelude = r#"
+            log = ''
+            function closer(v, e)
+                log = log .. v.name
+                if e then log = log .. '!' end
+            end
+            mt = { __close = closer }
+            function closable(name)
+                local t = { name = name }
+                setmetatable(t, mt)
+                return t
+            end
+        "#;
+        let run = |source: &str| {
+            let mut compiler = Compiler::new();
+            let mut lua = Lua::new_with_standard();
+            let result = lua
+                .run(&format!("{}{}", prelude, source), &mut compiler)
+                .ok();
+            let log = lua.enter(|vm, _| {
+                let log = vm.globals.borrow().get("log").cloned();
+                Ok(log.unwrap_or_default().into())
+            });
+            (result, log.ok().unwrap())
+        };
+        let (_, log) = run(
+            "do local a <close> = closable('a') local b <close> = closable('b') log = log .. '-' end",
+        );
+        assert_eq!(log, vstr!("-ba"));
+        let (_, log) = run(
+            "for i = 1, 3 do local a <close> = closable(i) if i == 2 then break end end log = log .. '-'",
+        );
+        assert_eq!(log, vstr!("12-"));
+        let (_, log) =
+            run("do local a <close> = closable('a') goto out end ::out:: log = log .. '-'");
+        assert_eq!(log, vstr!("a-"));
+        let (_, log) = run(
+            "for i = 1, 3 do local a <close> = closable(i) if i == 2 then goto continue end log = log .. '-' ::continue:: end",
+        );
+        assert_eq!(log, vstr!("-12-3"));
+        let (result, log) = run(r#"
+            function f()
+                local a <close> = closable('a')
+                do
+                    local b <close> = closable('b')
+                    return log .. 'r'
+                end
+            end
+            return f()
+            "#);
+        assert_eq!(result, Some(vstr!("r")));
+        assert_eq!(log, vstr!("ba"));
+        let (result, log) = run("do local a <close> = closable('a') local n = nil + 1 end");
+        assert_eq!(result, None);
+        assert_eq!(log, vstr!("a!"));
+        let (_, log) =
+            run("local a <close> = closable('a') local b <close> = false log = log .. '-'");
+        assert_eq!(log, vstr!("-a"));
+    }
 }
@@ -5,11 +5,14 @@ use gc_arena::{lock::RefLock, Arena, Collect, Gc, Mutation, Rootable};
 use crate::{
     code::OpCode,
     compiler::Compiler,
-    error::{ErrorTuple, SiltError, ValueTypes},
+    error::{Attempt, ErrorTuple, RuntimeError, SiltError, TraceFrame, ValueTypes, VariableName},
     function::{CallFrame, Closure, FunctionObject, NativeFunctionRaw, UpValue, WrappedFn},
     prelude::UserData,
     table::{ExTable, Table},
-    userdata::{InnerResult, MetaMethod, UserDataRegistry, UserDataWrapper, WeakWrapper},
+    thread::{Thread, ThreadStatus},
+    userdata::{
+        InnerResult, MetaMethod, MultiResult, UserDataRegistry, UserDataWrapper, WeakWrapper,
+    },
     value::{ExVal, FromLuaMulti, ToLua, ToLuaMulti, Value},
 };
 
@@ -144,7 +147,7 @@ macro_rules! binary_op_push {
         let l = $src.pop($ep);
         let res = binary_op!($src, $ep, $frame, $frames, $frame_count, l, $op, r, $opp);
 
-        $src.push($ep, res);
+        $src.push($ep, res)?;
     }};
 }
 
@@ -174,13 +177,88 @@ macro_rules! binary_op  {
                     ))
                 }
             },
+            (ll, Value::Table(right)) => {
+                table_meta_op!($lua, $ep, $frame, $frames, $frame_count, right, ll, Value::Table(right), $opp)
+            },
+            (left, Value::UserData(right)) => {
+                let el = left.to_error();
+                match $lua.handle_userdata_binary_op($ep, right, MetaMethod::$opp, left) {
+                    Ok(result) => result,
+                    Err(_) => return Err(SiltError::ExpOpValueWithValue(
+                        el,
+                        MetaMethod::$opp,
+                        ValueTypes::UserData
+                    ))
+                }
+            },
             (ll,rr) => return Err(SiltError::ExpOpValueWithValue(ll.to_error(), MetaMethod::$opp, rr.to_error()))
         }
     };
 }
 
+/** Like binary_op_push but the numeric work is handed to a function, `arith` for mod, pow and idiv or `bitwise` for the integer ops */
+macro_rules! arith_op_push {
+    ($src:ident, $ep:ident, $frame:ident, $frames:ident, $frame_count:ident, $opp:tt) => {
+        arith_op_push!($src, $ep, $frame, $frames, $frame_count, $opp, arith)
+    };
+    ($src:ident, $ep:ident, $frame:ident, $frames:ident, $frame_count:ident, $opp:tt, $func:ident) => {{
+        let r = $src.pop($ep);
+        let l = $src.pop($ep);
+        let res = match (l, r) {
+            (Value::Table(left), rr) => {
+                table_meta_op!($src, $ep, $frame, $frames, $frame_count, left, rr, $opp)
+            }
+            (Value::UserData(left), right) => {
+                let er = right.to_error();
+                match $src.handle_userdata_binary_op($ep, left, MetaMethod::$opp, right) {
+                    Ok(result) => result,
+                    Err(_) => {
+                        return Err(SiltError::ExpOpValueWithValue(
+                            ValueTypes::UserData,
+                            MetaMethod::$opp,
+                            er,
+                        ))
+                    }
+                }
+            }
+            (ll, Value::Table(right)) => {
+                table_meta_op!(
+                    $src,
+                    $ep,
+                    $frame,
+                    $frames,
+                    $frame_count,
+                    right,
+                    ll,
+                    Value::Table(right),
+                    $opp
+                )
+            }
+            (left, Value::UserData(right)) => {
+                let el = left.to_error();
+                match $src.handle_userdata_binary_op($ep, right, MetaMethod::$opp, left) {
+                    Ok(result) => result,
+                    Err(_) => {
+                        return Err(SiltError::ExpOpValueWithValue(
+                            el,
+                            MetaMethod::$opp,
+                            ValueTypes::UserData,
+                        ))
+                    }
+                }
+            }
+            (ll, rr) => $func(&ll, &rr, MetaMethod::$opp)?,
+        };
+        $src.push($ep, res)?;
+    }};
+}
+
+/** Calls the metamethod found on `$table` with `$first` and `$second`, which default to the table and the right operand */
 macro_rules! table_meta_op {
-    ($lua:ident, $ep:ident, $frame:ident, $frames:ident, $frame_count:ident, $table:ident, $right:ident, $opp:tt) => {{
+    ($lua:ident, $ep:ident, $frame:ident, $frames:ident, $frame_count:ident, $table:ident, $right:ident, $opp:tt) => {
+        table_meta_op!($lua, $ep, $frame, $frames, $frame_count, $table, Value::Table($table), $right, $opp)
+    };
+    ($lua:ident, $ep:ident, $frame:ident, $frames:ident, $frame_count:ident, $table:ident, $first:expr, $second:expr, $opp:tt) => {{
         let a = $table.borrow().by_meta_method(MetaMethod::$opp);
         match a {
             Ok(f) => {
@@ -192,25 +270,24 @@ macro_rules! table_meta_op {
                  * This feels dirty, but I wrote this very sleep deprived and it works so this is a
                  * future me problem.
                  */
-                $lua.push($ep, f);
-                $lua.push($ep, Value::Table($table));
+                $lua.push($ep, f)?;
+                $lua.push($ep, $first)?;
                 // $lua.push($ep,$right);
-                let arity: u8 = 1;
-                let val = $lua.peekn($ep, arity);
+                let arity: usize = 1;
+                let val = $lua.peekn($ep, arity).clone();
                 // println!(" we attempt to call {}", val);
                 if let Value::Closure(c) = val {
+                    $lua.enter_frame()?;
                     const ARITY: usize = 2;
-                    let frame_top = unsafe { $ep.ip.sub(ARITY) };
-                    let new_frame = CallFrame::new(c.clone(), $lua.stack_count - ARITY, 0); // TODO using this opcode method means metamethods cant multireturn
+                    let new_frame = CallFrame::new(c.clone(), $lua.stack_count - ARITY, 1); // TODO using this opcode method means metamethods cant multireturn
                     $frames.push(new_frame);
                     $frame = $frames.last_mut().unwrap();
-                    $frame.local_stack = frame_top;
                     $frame_count += 1;
 
                     $lua.print_stack();
                 }
                 // Value::Nil
-                $right
+                $second
             }
             Err(e) => return Err(e),
         }
@@ -234,6 +311,24 @@ pub struct Lua {
     arena: Arena<Rootable![VM<'_>]>,
 }
 
+/** how far a VM may grow before a call fails with SiltError::StackOverflow */
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /** values the stack may hold, it starts small and grows on demand up to this */
+    pub stack: usize,
+    /** calls that may be running at once */
+    pub calls: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            stack: 1_000_000,
+            calls: 200_000,
+        }
+    }
+}
+
 impl<'gc> Lua {
     pub fn new() -> Self {
         let arena = Arena::<Rootable![VM<'_>]>::new(|mc| {
@@ -295,6 +390,11 @@ impl<'gc> Lua {
         self.arena.mutate_root(|mc, vm| vm.borrow_mut().cycle(mc))
     }
 
+    /// set how large the stack and how deep the calls may grow before a stack overflow
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.arena.mutate_root(|_, vm| vm.limits = limits)
+    }
+
     /// enter into the VM state to modify the VM directly
     pub fn enter<F>(&mut self, closure: F) -> Result<ExVal, Box<dyn std::error::Error>>
     where
@@ -385,9 +485,13 @@ pub struct VM<'gc> {
     /** Instruction to be run at start of loop  */
     // ip: *const OpCode, // TODO usize vs *const OpCode, will rust optimize the same?
     // stack: Vec<Value>, // TODO fixed size array vs Vec, how much less overhead is there?
-    stack: [Value<'gc>; 256],
+    /** on the heap so a coroutine can trade it for it's own without anything pointing into it moving.
+     * Grows on demand, everything pointing into it is moved along */
+    stack: Vec<Value<'gc>>,
     // stack_top: Gc<'lua,*mut Value<'lua>>,
     stack_count: usize,
+    /** how many values the last open instruction such as `VARARGS(0)` left on the stack, read by the next `*_OPEN` op */
+    open_count: usize,
     /** Next empty location */
     // stack_top: *mut Value,
     pub globals: Gc<'gc, RefLock<Table<'gc>>>, // TODO store strings as identifer usize and use that as key
@@ -396,6 +500,8 @@ pub struct VM<'gc> {
     // resizing a sequential array is faster then non sequential heap items, BUT since we'll USUALLY resolve the upvalue on the top of the list we're derefencing once to get our Upvalue vs an index lookup which is slightly slower.
     // TODO TLDR: benchmark this
     open_upvalues: Vec<Gc<'gc, RefLock<UpValue<'gc>>>>,
+    /** stack positions of live `<close>` variables, lowest first */
+    to_be_closed: Vec<usize>,
     // references: Vec<Reference>,
     // TODO should we store all strings in their own table/array for better equality checks? is this cheaper?
     // obj
@@ -412,6 +518,61 @@ pub struct VM<'gc> {
     userdata_stack: Option<UDVec>,
     /// Used to quickly run in-VM functions externally
     external_functions: Vec<Gc<'gc, FunctionObject<'gc>>>,
+    /** value passed to the last `error`, read back once a SiltError::Thrown is caught */
+    thrown: Value<'gc>,
+    /** level passed along with the thrown value, positions a string message once we know the frames */
+    thrown_level: usize,
+    /** work a native such as pcall asked us to do once it returns, it can't see the frames itself */
+    request: Option<NativeRequest<'gc>>,
+    /** calls active when the last error was raised, innermost first */
+    #[collect(require_static)]
+    traceback: Vec<TraceFrame>,
+    /** the coroutine running right now, none on the main thread */
+    thread: Option<Gc<'gc, RefLock<Thread<'gc>>>>,
+    /** calls being run to completion for a metamethod or native, a coroutine can't yield across them */
+    nested: usize,
+    /** values passed to yield on their way back to resume */
+    yielded: Option<Vec<Value<'gc>>>,
+    /** how large the stack and how deep the calls may grow before a stack overflow */
+    #[collect(require_static)]
+    pub limits: Limits,
+    /** calls running right now, counting those waiting on a nested call such as a metamethod or coroutine */
+    depth: usize,
+}
+
+/** something only the running frames can do, asked for by a native */
+#[derive(Collect)]
+#[collect(no_drop)]
+pub(crate) enum NativeRequest<'gc> {
+    Protect(ProtectedCall<'gc>),
+    /** debug.traceback's string, skipping the first `level` calls */
+    Traceback {
+        message: Option<String>,
+        level: usize,
+    },
+    /** coroutine.resume or a call to a wrapped coroutine, which raises the coroutine's error instead of returning it */
+    Resume {
+        thread: Gc<'gc, RefLock<Thread<'gc>>>,
+        args: Vec<Value<'gc>>,
+        wrap: bool,
+    },
+    /** coroutine.yield's values, the coroutine stops at the call until it's resumed */
+    Yield(Vec<Value<'gc>>),
+    /** a call made in the native's place, it's results are the native's. Like pairs handing over to `__pairs` */
+    Call {
+        callee: Value<'gc>,
+        args: Vec<Value<'gc>>,
+    },
+}
+
+/** a function to run under a protected frame on behalf of pcall or xpcall */
+#[derive(Collect)]
+#[collect(no_drop)]
+pub(crate) struct ProtectedCall<'gc> {
+    callee: Value<'gc>,
+    args: Vec<Value<'gc>>,
+    /** xpcall's message handler, nil for pcall */
+    handler: Value<'gc>,
 }
 
 type ObjectPtr<'gc, T> = Gc<'gc, RefLock<T>>;
@@ -427,6 +588,137 @@ impl<'a, 'g> Ephemeral<'a, 'g> {
     }
 }
 
+/** Coerce a value to an Integer or Number for arithmetic, strings are parsed the same as the other operators */
+fn arith_operand<'gc>(value: &Value<'gc>) -> Option<Value<'gc>> {
+    match value {
+        Value::Integer(i) => Some(Value::Integer(*i)),
+        Value::Number(n) => Some(Value::Number(*n)),
+        Value::String(s) => {
+            if let Ok(i) = s.parse::<i64>() {
+                Some(Value::Integer(i))
+            } else {
+                s.parse::<f64>().ok().map(Value::Number)
+            }
+        }
+        _ => None,
+    }
+}
+
+/** Modulo, exponent and floor division. Integers stay integers except for `^`, which always produces a float */
+fn arith<'gc>(
+    left: &Value<'gc>,
+    right: &Value<'gc>,
+    op: MetaMethod,
+) -> Result<Value<'gc>, SiltError> {
+    let (l, r) = match (arith_operand(left), arith_operand(right)) {
+        (Some(l), Some(r)) => (l, r),
+        _ => {
+            return Err(SiltError::ExpOpValueWithValue(
+                left.to_error(),
+                op,
+                right.to_error(),
+            ))
+        }
+    };
+    if let (Value::Integer(a), Value::Integer(b)) = (&l, &r) {
+        let (a, b) = (*a, *b);
+        match op {
+            MetaMethod::Mod | MetaMethod::IDiv if b == 0 => {
+                return Err(SiltError::ExpIntegerDivideByZero(op))
+            }
+            MetaMethod::Mod => {
+                let m = a.wrapping_rem(b);
+                let m = if m != 0 && (m ^ b) < 0 { m + b } else { m };
+                return Ok(Value::Integer(m));
+            }
+            MetaMethod::IDiv => {
+                let d = a.wrapping_div(b);
+                let d = if a.wrapping_rem(b) != 0 && (a ^ b) < 0 {
+                    d - 1
+                } else {
+                    d
+                };
+                return Ok(Value::Integer(d));
+            }
+            _ => {}
+        }
+    }
+    let a = match l {
+        Value::Integer(i) => i as f64,
+        Value::Number(n) => n,
+        _ => unreachable!(),
+    };
+    let b = match r {
+        Value::Integer(i) => i as f64,
+        Value::Number(n) => n,
+        _ => unreachable!(),
+    };
+    Ok(Value::Number(match op {
+        MetaMethod::Mod => {
+            let m = a % b;
+            if m != 0. && (m < 0.) != (b < 0.) {
+                m + b
+            } else {
+                m
+            }
+        }
+        MetaMethod::IDiv => (a / b).floor(),
+        _ => a.powf(b),
+    }))
+}
+
+/** Integer conversion for bitwise operations, floats are accepted only if they hold an exact integer */
+fn bitwise_operand(value: &Value) -> Result<i64, SiltError> {
+    match arith_operand(value) {
+        Some(Value::Integer(i)) => Ok(i),
+        Some(Value::Number(n)) => {
+            if n.fract() == 0. && (-9223372036854775808.0..9223372036854775808.0).contains(&n) {
+                Ok(n as i64)
+            } else {
+                Err(SiltError::ExpNoIntegerRepresentation)
+            }
+        }
+        _ => Err(SiltError::ExpInvalidBitwise(value.to_error())),
+    }
+}
+
+/** Lua shifts are logical and a negative displacement shifts the other direction */
+fn shift_left(value: i64, by: i64) -> i64 {
+    if by <= -64 || by >= 64 {
+        0
+    } else if by >= 0 {
+        ((value as u64) << by) as i64
+    } else {
+        ((value as u64) >> -by) as i64
+    }
+}
+
+fn bitwise<'gc>(
+    left: &Value<'gc>,
+    right: &Value<'gc>,
+    op: MetaMethod,
+) -> Result<Value<'gc>, SiltError> {
+    let a = bitwise_operand(left)?;
+    let b = bitwise_operand(right)?;
+    Ok(Value::Integer(match op {
+        MetaMethod::BAnd => a & b,
+        MetaMethod::BOr => a | b,
+        MetaMethod::BXor => a ^ b,
+        MetaMethod::Shl => shift_left(a, b),
+        MetaMethod::Shr => shift_left(a, b.saturating_neg()),
+        _ => !a,
+    }))
+}
+
+/** a path like `a.b.c` reached a field that isn't a table */
+fn field_error(key: &Value, found: ValueTypes) -> SiltError {
+    let name = match key {
+        Value::String(s) => s.clone(),
+        _ => "?".to_string(),
+    };
+    SiltError::VmAttempt(Attempt::Index, found, VariableName::Field(name))
+}
+
 fn wrap<'gc, T: Collect>(mc: &Mutation<'gc>, value: T) -> ObjectPtr<'gc, T> {
     Gc::new(mc, RefLock::new(value))
 }
@@ -472,7 +764,7 @@ impl<'gc> VM<'gc> {
         //     one
         // });
 
-        let stack = [(); 256].map(|_| Value::default());
+        let stack = vec![Value::default(); 256];
         // let stack_top = Gc::new(mc,RefLock::new( stack.as_mut_ptr() as *mut Value) );
         // let stack = vec![];
         // let stack_top = stack.as_ptr() as *mut Value;
@@ -486,14 +778,25 @@ impl<'gc> VM<'gc> {
             // ip: 0 as *const OpCode,
             // stack, //: unsafe { *stack },
             stack_count: 0,
+            open_count: 0,
             stack,
             // stack_top,
             globals: Gc::new(mc, RefLock::new(Table::new(0))), //Gc::new(mc, gtable),
             open_upvalues: vec![],
+            to_be_closed: vec![],
             table_counter: RefCell::new(1),
             userdata_registry: UserDataRegistry::new(),
             userdata_stack: Some(UDVec(vec![])),
             external_functions: vec![],
+            thrown: Value::Nil,
+            thrown_level: 0,
+            request: None,
+            traceback: vec![],
+            thread: None,
+            nested: 0,
+            yielded: None,
+            limits: Limits::default(),
+            depth: 0,
         }
     }
 
@@ -506,8 +809,8 @@ impl<'gc> VM<'gc> {
         match self.execute(mc, object) {
             Ok(v) => Ok(v),
             Err(e) => Err(vec![ErrorTuple {
-                code: e,
-                location: (0, 0),
+                code: e.code,
+                location: e.location,
             }]),
         }
 
@@ -539,8 +842,8 @@ impl<'gc> VM<'gc> {
         match self.execute(mc, self.root) {
             Ok(v) => Ok(v),
             Err(e) => Err(vec![ErrorTuple {
-                code: e,
-                location: (0, 0),
+                code: e.code,
+                location: e.location,
             }]),
         }
     }
@@ -550,7 +853,7 @@ impl<'gc> VM<'gc> {
         &mut self,
         mc: &Mutation<'gc>,
         object: Gc<'gc, FunctionObject<'gc>>,
-    ) -> Result<ExVal, SiltError> {
+    ) -> Result<ExVal, RuntimeError> {
         // TODO param is a reference of &'a
         // self.ip = object.chunk.code.as_ptr();
         // frame.ip = object.chunk.code.as_ptr();
@@ -565,11 +868,42 @@ impl<'gc> VM<'gc> {
 
         let mut frame = CallFrame::new(closure, 0, 0);
         frame.ip = object.chunk.code.as_ptr();
-        frame.local_stack = ep.ip;
         // frame.stack.resize(256, Value::Nil); // TODO
-        self.push(&mut ep, Value::Function(object)); // TODO this needs to store the function object itself somehow, RC?
-        let frames = vec![frame];
-        self.process(&mut ep, frames)
+        if let Err(code) = self.push(&mut ep, Value::Function(object)) {
+            // TODO this needs to store the function object itself somehow, RC?
+            return Err(RuntimeError {
+                code,
+                location: (0, 0),
+                traceback: vec![],
+            });
+        }
+        let mut frames = vec![frame];
+        self.traceback.clear();
+        self.depth = 1;
+        let result = self.process(&mut ep, &mut frames);
+        self.depth = 0;
+        match result {
+            Ok(v) => Ok(v.into()),
+            Err(e) => {
+                let location = self.traceback.first().map_or((0, 0), |f| f.location);
+                // unwinding is another way out of scope, anything left open is closed with the error
+                let error = self.error_value(e.clone());
+                let code = match self.close_to_be_closed(&mut ep, 0, error) {
+                    Ok(()) => e,
+                    Err(e) => e,
+                };
+                Err(RuntimeError {
+                    code,
+                    location,
+                    traceback: self.traceback.clone(),
+                })
+            }
+        }
+    }
+
+    /** calls that were active when the last uncaught error was raised, innermost first */
+    pub fn traceback(&self) -> &[TraceFrame] {
+        &self.traceback
     }
 
     /// dump all newest userdata as weak references but keep atomic strong references within the lua
@@ -611,9 +945,15 @@ impl<'gc> VM<'gc> {
     }
 
     /// push value to stack
-    pub(crate) fn push(&mut self, ep: &mut Ephemeral<'_, 'gc>, value: Value<'gc>) {
+    pub(crate) fn push(
+        &mut self,
+        ep: &mut Ephemeral<'_, 'gc>,
+        value: Value<'gc>,
+    ) -> Result<(), SiltError> {
+        self.fit(ep, 1)?;
         VM::push_raw(ep, value);
         self.stack_count += 1;
+        Ok(())
     }
 
     /// push value to stack without stack adjustment (convenience for mutable reference hiccups)
@@ -623,8 +963,13 @@ impl<'gc> VM<'gc> {
         ep.ip = unsafe { ep.ip.add(1) };
     }
 
-    pub(crate) fn push_nils(&mut self, ep: &mut Ephemeral<'_, 'gc>, amount: usize) {
+    pub(crate) fn push_nils(
+        &mut self,
+        ep: &mut Ephemeral<'_, 'gc>,
+        amount: usize,
+    ) -> Result<(), SiltError> {
         devout!(" | push nils x{}", amount);
+        self.fit(ep, amount)?;
         // const NIL: u8 = unsafe{ std::mem::transmute::<Value, u8>(Value::Nil)};
         // ep.ip = unsafe {
         //     ep.ip.write_bytes(NIL, amount);
@@ -635,6 +980,7 @@ impl<'gc> VM<'gc> {
             ep.ip = unsafe { ep.ip.add(1) };
         }
         self.stack_count += amount;
+        Ok(())
     }
 
     pub(crate) fn pushn(
@@ -642,8 +988,9 @@ impl<'gc> VM<'gc> {
         ep: &mut Ephemeral<'_, 'gc>,
         values: Vec<Value<'gc>>,
         need: usize,
-    ) {
+    ) -> Result<(), SiltError> {
         devout!(" | push_n: values x {}, need {}", values.len(), need);
+        self.fit(ep, need)?;
         // for v in values.iter() {
         //     println!("we have {}", v);
         // }
@@ -657,26 +1004,72 @@ impl<'gc> VM<'gc> {
             //     None=>Value::Nil
             // }
 
-            if let Some(v) = vv.next() {
-                devout!("pushn -> {}", v);
-                unsafe { ep.ip.write(v) };
-            };
+            let v = vv.next().unwrap_or(Value::Nil);
+            devout!("pushn -> {}", v);
+            unsafe { ep.ip.write(v) };
             ep.ip = unsafe { ep.ip.add(1) };
         }
         self.stack_count += need;
+        Ok(())
     }
 
-    fn reserve(&mut self, ep: &mut Ephemeral<'_, 'gc>) -> *mut Value<'gc> {
+    fn reserve(&mut self, ep: &mut Ephemeral<'_, 'gc>) -> Result<*mut Value<'gc>, SiltError> {
+        self.fit(ep, 1)?;
         self.stack_count += 1;
         let old = ep.ip;
         ep.ip = unsafe { ep.ip.add(1) };
-        old
+        Ok(old)
+    }
+
+    /** make room for n more values past the top of the stack, a stack overflow if that's past the limit */
+    fn fit(&mut self, ep: &mut Ephemeral<'_, 'gc>, n: usize) -> Result<(), SiltError> {
+        let base = self.stack.as_ptr() as usize;
+        let top = (ep.ip as usize - base) / std::mem::size_of::<Value>();
+        // the limit may be set below what the stack already holds
+        if top + n > self.stack.len().min(self.limits.stack) {
+            if top + n > self.limits.stack {
+                return Err(SiltError::StackOverflow);
+            }
+            self.grow(ep, top + n);
+        }
+        Ok(())
+    }
+
+    /** reallocate the stack to hold at least `need` values, doubling up to the limit. Anything
+     * pointing into the stack is moved to the same slot of the new one, frames only hold indices
+     * so they stay put. fit checks `need` against the limit first */
+    #[cold]
+    fn grow(&mut self, ep: &mut Ephemeral<'_, 'gc>, need: usize) {
+        let old = self.stack.as_ptr() as usize;
+        let size = (self.stack.len() * 2).max(need).min(self.limits.stack);
+        self.stack.resize(size, Value::Nil);
+        let new = self.stack.as_mut_ptr();
+        if new as usize == old {
+            return;
+        }
+        let moved = |slot: *mut Value<'gc>| unsafe {
+            new.add((slot as usize - old) / std::mem::size_of::<Value>())
+        };
+        ep.ip = moved(ep.ip);
+        for &upvalue in self.open_upvalues.iter() {
+            let mut upvalue = upvalue.borrow_mut(ep.mc);
+            upvalue.location = moved(upvalue.location);
+        }
+    }
+
+    /** count a call about to start, it fails with a stack overflow once either limit is reached */
+    fn enter_frame(&mut self) -> Result<(), SiltError> {
+        if self.depth >= self.limits.calls || self.stack_count >= self.limits.stack {
+            return Err(SiltError::StackOverflow);
+        }
+        self.depth += 1;
+        Ok(())
     }
 
     /** pop N number of values from stack */
-    fn popn_drop(&mut self, ep: &mut Ephemeral<'_, 'gc>, n: u8) {
-        unsafe { ep.ip = ep.ip.sub(n as usize) };
-        self.stack_count -= n as usize;
+    fn popn_drop(&mut self, ep: &mut Ephemeral<'_, 'gc>, n: usize) {
+        unsafe { ep.ip = ep.ip.sub(n) };
+        self.stack_count -= n;
     }
 
     fn print_upvalues(&self) {
@@ -689,37 +1082,652 @@ impl<'gc> VM<'gc> {
     fn close_n_upvalues(&mut self, ep: &mut Ephemeral<'_, 'gc>, n: u8) {
         #[cfg(feature = "dev-out")]
         self.print_upvalues();
-        // remove n from end of list
-        if n > 1 {
-            self.open_upvalues
-                .drain(self.open_upvalues.len() - n as usize..)
-                .rev()
-                .for_each(|mut up| {
-                    let mut upvalue = up.borrow_mut(ep.mc);
-                    upvalue.close_around(unsafe { ep.ip.replace(Value::Nil) });
+        // each of the top n values should have an open upvalue pointing at it, close them top down
+        for _ in 0..n {
+            let slot = unsafe { ep.ip.sub(1) };
+            if let Some(i) = self
+                .open_upvalues
+                .iter()
+                .rposition(|up| up.borrow().location == slot)
+            {
+                let upvalue = self.open_upvalues.remove(i);
+                upvalue.borrow_mut(ep.mc).close_around(self.pop(ep));
+            } else {
+                self.pop(ep);
+            }
+        }
+    }
+
+    /** pointer to a position of our stack, good until the stack next grows */
+    fn slot(&mut self, index: usize) -> *mut Value<'gc> {
+        unsafe { self.stack.as_mut_ptr().add(index) }
+    }
+
+    #[cfg(feature = "dev-out")]
+    fn print_local_stack(&self, frame: &CallFrame<'gc>) {
+        let end = (frame.stack_snapshot + 10).min(self.stack.len());
+        println!("local stack: {:?}", &self.stack[frame.stack_snapshot..end]);
+    }
+
+    /** position of a pointer into our stack */
+    fn stack_index(&self, slot: *const Value<'gc>) -> usize {
+        unsafe { slot.offset_from(self.stack.as_ptr()) as usize }
+    }
+
+    /** call `__close` on each to-be-closed variable at or above the stack position level, newest first. The error is nil unless we're unwinding from one */
+    fn close_to_be_closed(
+        &mut self,
+        ep: &mut Ephemeral<'_, 'gc>,
+        level: usize,
+        error: Value<'gc>,
+    ) -> Result<(), SiltError> {
+        while let Some(&i) = self.to_be_closed.last() {
+            if i < level {
+                break;
+            }
+            self.to_be_closed.pop();
+            match self.stack[i].clone() {
+                Value::Table(t) => {
+                    if let Value::Closure(c) = t.borrow().by_meta_method(MetaMethod::Close)? {
+                        self.call_closure(ep, c, vec![Value::Table(t), error.clone()])?;
+                    }
+                }
+                Value::UserData(u) => {
+                    self.handle_userdata_binary_op(ep, u, MetaMethod::Close, error.clone())?;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /** run a closure to completion on top of the stack and drop whatever it returns, for metamethods the current frame can't wait on */
+    fn call_closure(
+        &mut self,
+        ep: &mut Ephemeral<'_, 'gc>,
+        closure: Gc<'gc, Closure<'gc>>,
+        args: Vec<Value<'gc>>,
+    ) -> Result<(), SiltError> {
+        self.call_value(ep, Value::Closure(closure), args)
+            .map(|_| ())
+    }
+
+    /** run any callable to completion on top of the stack and keep only the first value it returns */
+    fn call_value(
+        &mut self,
+        ep: &mut Ephemeral<'_, 'gc>,
+        callee: Value<'gc>,
+        args: Vec<Value<'gc>>,
+    ) -> Result<Value<'gc>, SiltError> {
+        let values = self.call_values(ep, callee, args)?;
+        Ok(values.into_iter().next().unwrap_or_default())
+    }
+
+    /** run any callable to completion on top of the stack and keep every value it returns */
+    fn call_values(
+        &mut self,
+        ep: &mut Ephemeral<'_, 'gc>,
+        callee: Value<'gc>,
+        args: Vec<Value<'gc>>,
+    ) -> MultiResult<'gc> {
+        match callee {
+            Value::Closure(closure) => {
+                let depth = self.depth;
+                self.enter_frame()?;
+                let params = closure.function.arity as usize;
+                let mut frame = CallFrame::new(closure, self.stack_count, 0);
+                // returned values are left on the stack like a coroutine's
+                frame.coroutine = true;
+                self.push(ep, Value::Closure(closure))?;
+                self.pushn(ep, args, params)?;
+                let level = frame.stack_snapshot;
+                self.nested += 1;
+                let result = self.process(ep, &mut vec![frame]).map(|_| {
+                    let count = self.open_count;
+                    self.popn(ep, count)
                 });
-            unsafe { ep.ip = ep.ip.sub(n as usize) };
+                self.nested -= 1;
+                self.depth = depth;
+                let base = unsafe { self.stack.as_mut_ptr().add(level) };
+                self.close_upvalues_by_return(ep.mc, base);
+                while ep.ip > base {
+                    self.pop(ep);
+                }
+                result
+            }
+            Value::NativeFunction(f) => {
+                let values = f.f.call(self, ep.mc, &args)?;
+                match self.request.take() {
+                    Some(request) => self.answer(ep, request),
+                    None => Ok(values),
+                }
+            }
+            v => Err(SiltError::NotCallable(format!("Value: {}", v))),
+        }
+    }
+
+    /** raise any value as an error, a pcall further down hands it back untouched. A string is
+     * prefixed with the position of the call `level` frames up once the frames are known, 0 leaves
+     * it as is */
+    pub fn throw(&mut self, value: Value<'gc>, level: usize) -> SiltError {
+        let message = match &value {
+            Value::String(s) => s.to_string(),
+            Value::Integer(_) | Value::Number(_) | Value::Nil => value.to_string(),
+            v => format!("(error object is a {} value)", v.to_error()),
+        };
+        self.thrown = value;
+        self.thrown_level = level;
+        SiltError::Thrown(message)
+    }
+
+    /** the value lua sees for a caught error, whatever `error` threw or the message of anything else */
+    fn error_value(&mut self, e: SiltError) -> Value<'gc> {
+        match e {
+            SiltError::Thrown(_) => {
+                self.thrown_level = 0;
+                take(&mut self.thrown)
+            }
+            e => Value::String(e.to_string()),
+        }
+    }
+
+    /** name the variable behind the value the failing instruction choked on, like `(global 'player')`.
+     * Anything we can't trace back to a variable is left as it was */
+    fn name_operand(
+        &self,
+        ep: &Ephemeral<'_, 'gc>,
+        frames: &[CallFrame<'gc>],
+        e: SiltError,
+    ) -> SiltError {
+        let frame = match frames.last() {
+            Some(frame) => frame,
+            None => return e,
+        };
+        let numeric = |t: &ValueTypes| matches!(t, ValueTypes::Number | ValueTypes::Integer);
+        let coercible = |t: &ValueTypes| numeric(t) || *t == ValueTypes::String;
+        let (attempt, found, depth) = match (frame.current_instruction(), &e) {
+            (OpCode::TABLE_GET { depth }, SiltError::VmNonTableOperations(t)) => {
+                (Attempt::Index, t, *depth as usize)
+            }
+            (OpCode::TABLE_SET { depth }, SiltError::VmNonTableOperations(t)) => {
+                (Attempt::Index, t, *depth as usize + 1)
+            }
+            (OpCode::TABLE_ASSIGN { offset, .. }, SiltError::VmNonTableOperations(t)) => {
+                (Attempt::Index, t, *offset as usize)
+            }
+            (OpCode::GET_METHOD, SiltError::VmNonTableOperations(t)) => (Attempt::Index, t, 1),
+            (
+                OpCode::ADD
+                | OpCode::SUB
+                | OpCode::MULTIPLY
+                | OpCode::DIVIDE
+                | OpCode::MODULUS
+                | OpCode::EXPONENT
+                | OpCode::FLOOR_DIVIDE,
+                SiltError::ExpOpValueWithValue(l, _, r),
+            ) => {
+                // blame the left side unless it's only a string the right side could've been added to
+                if !coercible(l) || (!numeric(l) && coercible(r)) {
+                    (Attempt::Arithmetic, l, 1)
+                } else {
+                    (Attempt::Arithmetic, r, 0)
+                }
+            }
+            (OpCode::NEGATE, SiltError::ExpInvalidNegation(t)) => (Attempt::Arithmetic, t, 0),
+            (OpCode::CALL(arity, _), SiltError::NotCallable(_))
+            | (OpCode::TAIL_CALL(arity), SiltError::NotCallable(_)) => {
+                let arity = *arity as usize;
+                // the callee is still on the stack below it's arguments
+                let callee = unsafe { &*ep.ip.sub(arity + 1) };
+                return match Self::get_chunk(frame).operand_name(frame.pc(), arity) {
+                    Some(name) => SiltError::VmAttempt(Attempt::Call, callee.to_error(), name),
+                    None => e,
+                };
+            }
+            _ => return e,
+        };
+        match Self::get_chunk(frame).operand_name(frame.pc(), depth) {
+            Some(name) => SiltError::VmAttempt(attempt, found.clone(), name),
+            None => e,
+        }
+    }
+
+    /** add the frames an error is leaving to the traceback, the innermost call is reached first.
+     * This is also the first point a thrown string can be given it's position */
+    fn trace_error(&mut self, frames: &[CallFrame<'gc>], e: SiltError) -> SiltError {
+        self.traceback
+            .extend(frames.iter().rev().map(|f| f.trace()));
+        let level = take(&mut self.thrown_level);
+        match (e, &self.thrown) {
+            (SiltError::Thrown(message), Value::String(_)) if level > 0 => {
+                match self.traceback.get(level - 1) {
+                    Some(frame) => {
+                        let message = format!("{}: {}", frame.position(), message);
+                        self.thrown = Value::String(message.clone());
+                        SiltError::Thrown(message)
+                    }
+                    None => SiltError::Thrown(message),
+                }
+            }
+            (e, _) => e,
+        }
+    }
+
+    /** debug.traceback's output, the frames still running followed by those an error left if we're
+     * in xpcall's handler */
+    fn traceback_string(
+        &self,
+        frames: &[CallFrame<'gc>],
+        message: Option<String>,
+        level: usize,
+    ) -> String {
+        let mut out = match message {
+            Some(m) => format!("{}\nstack traceback:", m),
+            None => "stack traceback:".to_string(),
+        };
+        let running = frames.iter().rev().map(|f| f.trace());
+        for frame in running
+            .chain(self.traceback.iter().cloned())
+            .skip(level.saturating_sub(1))
+        {
+            out.push_str(&format!("\n\t{}", frame));
+        }
+        out
+    }
+
+    /** have debug.traceback's string returned as soon as the calling native returns */
+    pub(crate) fn request_traceback(&mut self, message: Option<String>, level: usize) {
+        self.request = Some(NativeRequest::Traceback { message, level });
+    }
+
+    /** have the callee called as soon as the calling native returns, what it returns is returned from the native */
+    pub(crate) fn request_call(&mut self, callee: Value<'gc>, args: Vec<Value<'gc>>) {
+        self.request = Some(NativeRequest::Call { callee, args });
+    }
+
+    /** have the callee run under a protected frame as soon as the calling native returns */
+    pub(crate) fn protect(
+        &mut self,
+        callee: Value<'gc>,
+        args: Vec<Value<'gc>>,
+        handler: Value<'gc>,
+    ) {
+        self.request = Some(NativeRequest::Protect(ProtectedCall {
+            callee,
+            args,
+            handler,
+        }));
+    }
+
+    /** do what a native asked for in the slot it was called from, a protected call may need a new
+     * frame. A yield leaves the slot empty until we're resumed */
+    fn fulfill(
+        &mut self,
+        ep: &mut Ephemeral<'_, 'gc>,
+        frames: &[CallFrame<'gc>],
+        request: NativeRequest<'gc>,
+        multi: u8,
+    ) -> Result<Option<CallFrame<'gc>>, SiltError> {
+        match request {
+            NativeRequest::Protect(call) => return self.start_protected(ep, call, multi),
+            NativeRequest::Traceback { message, level } => {
+                let trace = self.traceback_string(frames, message, level);
+                self.push_returns(ep, vec![Value::String(trace)], multi)?;
+            }
+            NativeRequest::Resume { thread, args, wrap } => {
+                let values = self.resume(ep.mc, thread, args, wrap)?;
+                self.push_returns(ep, values, multi)?;
+            }
+            NativeRequest::Yield(values) => {
+                if let Some(thread) = self.thread {
+                    thread.borrow_mut(ep.mc).multi = multi;
+                }
+                self.yielded = Some(values);
+            }
+            NativeRequest::Call { callee, args } => {
+                return self.start_call(ep, callee, args, multi)
+            }
+        }
+        Ok(None)
+    }
+
+    /** do what a native asked for when it was called from outside of any frame, like by pcall
+     * itself or for a metamethod. Anything needing a frame of it's own can't be done here */
+    fn answer(
+        &mut self,
+        ep: &mut Ephemeral<'_, 'gc>,
+        request: NativeRequest<'gc>,
+    ) -> MultiResult<'gc> {
+        match request {
+            // a native pcall asking for another protected call would need both of their `true`s
+            NativeRequest::Protect(_) => Err(SiltError::VmNestedProtectedCall),
+            NativeRequest::Traceback { message, level } => {
+                let trace = self.traceback_string(&[], message, level);
+                Ok(vec![Value::String(trace)])
+            }
+            NativeRequest::Resume { thread, args, wrap } => self.resume(ep.mc, thread, args, wrap),
+            NativeRequest::Yield(_) => Err(SiltError::VmYieldAcrossNative),
+            NativeRequest::Call { callee, args } => self.call_values(ep, callee, args),
+        }
+    }
+
+    /** have a coroutine resumed as soon as the calling native returns */
+    pub(crate) fn request_resume(
+        &mut self,
+        thread: Gc<'gc, RefLock<Thread<'gc>>>,
+        args: Vec<Value<'gc>>,
+        wrap: bool,
+    ) {
+        self.request = Some(NativeRequest::Resume { thread, args, wrap });
+    }
+
+    /** have the running coroutine suspended at the call to the native as soon as it returns */
+    pub(crate) fn request_yield(&mut self, values: Vec<Value<'gc>>) -> Result<(), SiltError> {
+        if self.thread.is_none() {
+            return Err(SiltError::VmYieldOutsideCoroutine);
+        }
+        if self.nested > 0 {
+            return Err(SiltError::VmYieldAcrossNative);
+        }
+        self.request = Some(NativeRequest::Yield(values));
+        Ok(())
+    }
+
+    /** is there a coroutine running that can yield from here */
+    pub fn is_yieldable(&self) -> bool {
+        self.thread.is_some() && self.nested == 0
+    }
+
+    /** run a coroutine until it yields, returns or fails. resume gets `true` and the values or
+     * `false` and the error, a wrapped coroutine hands back just the values and raises the error */
+    fn resume(
+        &mut self,
+        mc: &Mutation<'gc>,
+        thread: Gc<'gc, RefLock<Thread<'gc>>>,
+        args: Vec<Value<'gc>>,
+        wrap: bool,
+    ) -> MultiResult<'gc> {
+        let status = thread.borrow().status;
+        let result = match status {
+            ThreadStatus::Suspended => self.run_thread(mc, thread, args),
+            ThreadStatus::Dead => Err(SiltError::VmResumeDead),
+            _ => Err(SiltError::VmResumeNotSuspended),
+        };
+        match (result, wrap) {
+            (Ok(values), true) => Ok(values),
+            (Ok(mut values), false) => {
+                values.insert(0, Value::Bool(true));
+                Ok(values)
+            }
+            (Err(e), true) => Err(e),
+            (Err(e), false) => {
+                let error = self.error_value(e);
+                self.traceback.clear();
+                Ok(vec![Value::Bool(false), error])
+            }
+        }
+    }
+
+    /** swap in the coroutine's stack and carry on from where it left off, or call it's function
+     * if it hasn't started. It's closed up for good once it returns or fails */
+    fn run_thread(
+        &mut self,
+        mc: &Mutation<'gc>,
+        thread: Gc<'gc, RefLock<Thread<'gc>>>,
+        args: Vec<Value<'gc>>,
+    ) -> Result<Vec<Value<'gc>>, SiltError> {
+        let (mut frames, function, multi) = {
+            let mut t = thread.borrow_mut(mc);
+            t.status = ThreadStatus::Running;
+            self.swap_stack(&mut t);
+            (take(&mut t.frames), t.function, t.multi)
+        };
+        let caller = self.thread.replace(thread);
+        if let Some(caller) = caller {
+            caller.borrow_mut(mc).status = ThreadStatus::Normal;
+        }
+        let nested = take(&mut self.nested);
+        let depth = self.depth;
+        self.depth += frames.len();
+        let base = self.stack.as_mut_ptr();
+        let mut ep = Ephemeral::new(mc, unsafe { base.add(self.stack_count) });
+        // running out of stack for the arguments fails the coroutine like an error inside it would
+        let started = match frames.last_mut() {
+            // the call to yield we stopped at returns what we were resumed with
+            Some(frame) => {
+                frame.iterate();
+                self.push_returns(&mut ep, args, multi)
+            }
+            None => {
+                self.depth += 1;
+                let arity = args.len();
+                self.push(&mut ep, Value::Closure(function))
+                    .and_then(|_| self.pushn(&mut ep, args, arity))
+                    .and_then(|_| self.adjust_arguments(&mut ep, &function, arity))
+                    .map(|varargs| {
+                        let mut frame = CallFrame::new(function, 0, 0);
+                        frame.ip = function.function.chunk.code.as_ptr();
+                        frame.varargs = varargs;
+                        frame.coroutine = true;
+                        frames.push(frame);
+                    })
+            }
+        };
+        let run = started.and_then(|_| self.process(&mut ep, &mut frames));
+        let (result, status) = match (run, self.yielded.take()) {
+            (Ok(_), Some(values)) => (Ok(values), ThreadStatus::Suspended),
+            (Ok(_), None) => {
+                let count = self.open_count;
+                (Ok(self.popn(&mut ep, count)), ThreadStatus::Dead)
+            }
+            (Err(e), _) => {
+                let mut error = self.error_value(e);
+                while let Err(e) = self.close_to_be_closed(&mut ep, 0, error.clone()) {
+                    error = self.error_value(e);
+                }
+                (Err(self.throw(error, 0)), ThreadStatus::Dead)
+            }
+        };
+        self.nested = nested;
+        self.depth = depth;
+        if status == ThreadStatus::Dead {
+            let base = self.stack.as_mut_ptr();
+            self.close_upvalues_by_return(mc, base);
+            frames.clear();
+        }
+        {
+            let mut t = thread.borrow_mut(mc);
+            self.swap_stack(&mut t);
+            t.frames = frames;
+            t.status = status;
+        }
+        self.thread = caller;
+        if let Some(caller) = caller {
+            caller.borrow_mut(mc).status = ThreadStatus::Running;
+        }
+        result
+    }
+
+    /** trade the running stack for the one a coroutine keeps aside while it isn't running */
+    fn swap_stack(&mut self, thread: &mut Thread<'gc>) {
+        std::mem::swap(&mut self.stack, &mut thread.stack);
+        std::mem::swap(&mut self.stack_count, &mut thread.stack_count);
+        std::mem::swap(&mut self.open_upvalues, &mut thread.open_upvalues);
+        std::mem::swap(&mut self.to_be_closed, &mut thread.to_be_closed);
+    }
+
+    /** push a native's results the way the call asked for them, 0 leaves every one of them open */
+    fn push_returns(
+        &mut self,
+        ep: &mut Ephemeral<'_, 'gc>,
+        values: Vec<Value<'gc>>,
+        multi: u8,
+    ) -> Result<(), SiltError> {
+        if multi == 0 {
+            self.open_count = values.len();
+            self.pushn(ep, values, self.open_count)
         } else {
-            let upvalue = self.open_upvalues.pop().unwrap();
+            self.pushn(ep, values, multi as usize)
+        }
+    }
 
-            upvalue.borrow_mut(ep.mc).close_around(self.pop(ep));
+    /** start a call pcall or xpcall asked for in the slot pcall was called from. A closure gets a protected frame of it's own, anything else has nothing to unwind and is caught right here */
+    fn start_protected(
+        &mut self,
+        ep: &mut Ephemeral<'_, 'gc>,
+        call: ProtectedCall<'gc>,
+        multi: u8,
+    ) -> Result<Option<CallFrame<'gc>>, SiltError> {
+        let ProtectedCall {
+            callee,
+            args,
+            handler,
+        } = call;
+        let result = match callee {
+            Value::Closure(c) => {
+                let before = self.stack_count;
+                let arity = args.len();
+                let pushed = self
+                    .push(ep, Value::Bool(true))
+                    .and_then(|_| self.push(ep, callee))
+                    .and_then(|_| self.pushn(ep, args, arity))
+                    .and_then(|_| self.adjust_arguments(ep, &c, arity));
+                match pushed {
+                    Ok(varargs) => {
+                        let arity = c.function.arity as usize;
+                        let mut frame = CallFrame::new(c, self.stack_count - arity - 1, multi);
+                        frame.varargs = varargs;
+                        frame.protected = Some(handler);
+                        return Ok(Some(frame));
+                    }
+                    // no room to make the call is caught like an error inside of it
+                    Err(e) => {
+                        while self.stack_count > before {
+                            self.pop(ep);
+                        }
+                        Err(e)
+                    }
+                }
+            }
+            Value::NativeFunction(f) => match (f.f.call(self, ep.mc, &args), self.request.take()) {
+                (Ok(_), Some(request)) => self.answer(ep, request),
+                (r, _) => r,
+            },
+            v => Err(SiltError::NotCallable(format!("Value: {}", v))),
+        };
+        let values = match result {
+            Ok(mut values) => {
+                values.insert(0, Value::Bool(true));
+                values
+            }
+            Err(e) => vec![Value::Bool(false), self.caught(ep, e, handler)],
+        };
+        self.push_returns(ep, values, multi)?;
+        Ok(None)
+    }
+
+    /** call in place of the native that asked for it, a lua function gets a frame of it's own that
+     * returns straight to the native's caller */
+    fn start_call(
+        &mut self,
+        ep: &mut Ephemeral<'_, 'gc>,
+        callee: Value<'gc>,
+        args: Vec<Value<'gc>>,
+        multi: u8,
+    ) -> Result<Option<CallFrame<'gc>>, SiltError> {
+        let values = match callee {
+            Value::Closure(c) => {
+                let arity = args.len();
+                self.push(ep, callee)?;
+                self.pushn(ep, args, arity)?;
+                let varargs = self.adjust_arguments(ep, &c, arity)?;
+                let arity = c.function.arity as usize;
+                let mut frame = CallFrame::new(c, self.stack_count - arity - 1, multi);
+                frame.varargs = varargs;
+                return Ok(Some(frame));
+            }
+            Value::NativeFunction(f) => {
+                let values = f.f.call(self, ep.mc, &args)?;
+                match self.request.take() {
+                    Some(request) => self.answer(ep, request)?,
+                    None => values,
+                }
+            }
+            v => return Err(SiltError::NotCallable(format!("Value: {}", v))),
+        };
+        self.push_returns(ep, values, multi)?;
+        Ok(None)
+    }
+
+    /** the value a protected call fails with, run through xpcall's handler while the stack is still as the error left it */
+    fn caught(
+        &mut self,
+        ep: &mut Ephemeral<'_, 'gc>,
+        e: SiltError,
+        handler: Value<'gc>,
+    ) -> Value<'gc> {
+        let error = self.error_value(e);
+        if let Value::Nil = handler {
+            self.traceback.clear();
+            return error;
+        }
+        let out = match self.call_value(ep, handler, vec![error]) {
+            Ok(v) => v,
+            Err(e) => self.error_value(e),
+        };
+        self.traceback.clear();
+        out
+    }
+
+    /** drop the protected frame at i and everything above it, closing what they left open, then hand `false, error` to whoever called pcall */
+    fn unwind(
+        &mut self,
+        ep: &mut Ephemeral<'_, 'gc>,
+        frames: &mut Vec<CallFrame<'gc>>,
+        i: usize,
+        e: SiltError,
+    ) -> Result<(), SiltError> {
+        let handler = frames[i].protected.take().unwrap_or_default();
+        let mut error = self.caught(ep, e, handler);
+        let level = frames[i].stack_snapshot;
+        while let Err(e) = self.close_to_be_closed(ep, level, error.clone()) {
+            error = self.error_value(e);
+        }
+        self.traceback.clear();
+        // running the handler or a `__close` could have grown the stack out from under us
+        let base = self.slot(level);
+        self.close_upvalues_by_return(ep.mc, base);
+        // the error could have left the stack anywhere, clear down to the `true` pcall left below the frame
+        let slot = unsafe { base.sub(1) };
+        while ep.ip > slot {
+            unsafe {
+                ep.ip = ep.ip.sub(1);
+                ep.ip.replace(Value::Nil);
+            }
+        }
+        self.stack_count = frames[i].stack_snapshot - 1;
+        let multi = frames[i].multi_return;
+        self.depth -= frames.len() - i;
+        frames.truncate(i);
+        self.push_returns(ep, vec![Value::Bool(false), error], multi)?;
+        // the call to pcall is finished
+        if let Some(frame) = frames.last_mut() {
+            frame.iterate();
         }
+        Ok(())
     }
 
-    fn close_upvalues_by_return(&mut self, last: *mut Value<'gc>) {
+    fn close_upvalues_by_return(&mut self, mc: &Mutation<'gc>, last: *mut Value<'gc>) {
         // devout!("value: {}", unsafe { &*last });
         #[cfg(feature = "dev-out")]
         self.print_upvalues();
-        for upvalue in self.open_upvalues.iter().rev() {
-            let mut up = unsafe { upvalue.as_ptr().read() }; // TODO more bad practice
-                                                             // let upv = unsafe { &*up.get_location() };
-                                                             // let vv = unsafe { &*last };
-                                                             // let b = up.get_location() < last;
-                                                             // println!("upvalue {} less than {} is {} ", upv, vv, b);
-            if up.get_location() < last {
+        // open upvalues are ordered by stack location so we close from the top until we're below the frame
+        while let Some(&upvalue) = self.open_upvalues.last() {
+            if upvalue.borrow().location < last {
                 break;
             }
-            up.close();
+            upvalue.borrow_mut(mc).close();
+            self.open_upvalues.pop();
         }
     }
 
@@ -746,7 +1754,7 @@ impl<'gc> VM<'gc> {
     }
 
     // TODO can we make this faster with slices? can we slice a pointer? 🤔
-    fn popn(&mut self, ep: &mut Ephemeral<'_, 'gc>, n: u8) -> Vec<Value<'gc>> {
+    fn popn(&mut self, ep: &mut Ephemeral<'_, 'gc>, n: usize) -> Vec<Value<'gc>> {
         // println!("popn: {}", n);
         let mut values = vec![];
         for _ in 0..n {
@@ -809,29 +1817,51 @@ impl<'gc> VM<'gc> {
     // }
 
     /** Look down N amount of stack and return immutable reference */
-    fn peekn(&self, ep: &mut Ephemeral<'_, 'gc>, n: u8) -> &Value<'gc> {
+    fn peekn(&self, ep: &mut Ephemeral<'_, 'gc>, n: usize) -> &Value<'gc> {
         // unsafe { *ep.ip.sub(n as usize) }
         // &self.stack[self.stack.len() - n as usize]
-        unsafe { &*ep.ip.sub((n as usize) + 1) }
+        unsafe { &*ep.ip.sub(n + 1) }
     }
 
     // pub fn evaluate(&mut self, source: &str) -> FunctionObject<'lua> {
     //     self.compiler.compile(source.to_owned())
     // }
 
+    /// Run frames until the bottom one returns, an error is caught by the nearest protected frame
+    /// and running picks back up in the frame that called pcall
+    fn process(
+        &mut self,
+        ep: &mut Ephemeral<'_, 'gc>,
+        frames: &mut Vec<CallFrame<'gc>>,
+    ) -> Result<Value<'gc>, SiltError> {
+        loop {
+            match self.run_frames(ep, frames) {
+                Err(e) => {
+                    let e = self.name_operand(ep, frames, e);
+                    let e = self.trace_error(frames, e);
+                    match frames.iter().rposition(|f| f.protected.is_some()) {
+                        Some(i) => self.unwind(ep, frames, i, e)?,
+                        None => break Err(e),
+                    }
+                }
+                result => break result,
+            }
+        }
+    }
+
     /// The actual crawl through the entire root function object until it completes. This does not
     /// clear state on subsequent re-runs so variables could get redefined without any checks ( if
     /// x~=nil then x=1 end for instance )
-    fn process(
+    fn run_frames(
         &mut self,
         ep: &mut Ephemeral<'_, 'gc>,
-        mut frames: Vec<CallFrame<'gc>>,
-    ) -> Result<ExVal, SiltError> {
+        frames: &mut Vec<CallFrame<'gc>>,
+    ) -> Result<Value<'gc>, SiltError> {
         // let mut last = Value::Nil; // TODO temporary for testing
         // let stack_pointer = self.stack.as_mut_ptr();
         // let mut dummy_frame = CallFrame::new(Rc::new(FunctionObject::new(None, false)), 0);
+        let mut frame_count = frames.len();
         let mut frame = frames.last_mut().unwrap();
-        let mut frame_count = 1;
         // body.chunk.print_chunk(None);
         loop {
             let instruction = frame.current_instruction();
@@ -841,14 +1871,30 @@ impl<'gc> VM<'gc> {
 
             // TODO how much faster would it be to order these ops in order of usage, does match hash? probably.
             match instruction {
-                OpCode::RETURN(c) => {
-                    let count = *c;
+                OpCode::RETURN(_) | OpCode::RETURN_OPEN(_) => {
+                    let count = match instruction {
+                        OpCode::RETURN_OPEN(c) => *c as usize + self.open_count,
+                        OpCode::RETURN(c) => *c as usize,
+                        _ => unreachable!(),
+                    };
+                    let level = frame.stack_snapshot;
+                    self.close_to_be_closed(ep, level, Value::Nil)?;
                     frame_count -= 1;
-                    if frame_count <= 0 {
+                    if frame_count == 0 {
+                        if frame.coroutine {
+                            // resume hands back everything returned, straight off the coroutine's stack
+                            self.open_count = count;
+                            return Ok(Value::Nil);
+                        }
                         if self.stack_count <= 1 {
-                            return Ok(ExVal::Nil);
+                            return Ok(Value::Nil);
                         }
-                        let out: ExVal = self.safe_pop().into();
+                        // the end of a script returns whatever was left on top, otherwise the first returned value
+                        let out = match instruction {
+                            OpCode::RETURN(0) => self.safe_pop(),
+                            _ if count == 0 => Value::Nil,
+                            _ => take(&mut self.stack[self.stack_count - count]),
+                        };
                         return Ok(out);
                     }
 
@@ -857,63 +1903,39 @@ impl<'gc> VM<'gc> {
                         frame.multi_return,
                         count
                     );
-                    let multi_return = frame.multi_return;
-                    // if  || frame.need>1 {
-                    if multi_return > 1 && count > 1 {
-                        let vres = self.popn(ep, count);
-
-                        // TODO this paragraph is a dupe of the one below, i hate this whole logic
-                        // segment. Pushing stack values to a new vec, reversing it, then iterating
-                        // one by one back on to the stack but further down? Literally wasteful!
-                        // so we will rewrite eventually
-
-                        ep.ip = frame.local_stack;
-                        self.close_upvalues_by_return(ep.ip);
-                        devout!("stack top {}", unsafe { &*ep.ip });
-                        self.stack_count = frame.stack_snapshot;
-                        frames.pop();
-                        frame = frames.last_mut().unwrap();
-                        devout!("next instruction {}", frame.current_instruction());
-                        #[cfg(feature = "dev-out")]
-                        self.print_stack();
-
-                        self.pushn(ep, vres, multi_return as usize);
+                    // 0 asks for every value, left open for the instruction after the call
+                    let want = frame.multi_return as usize;
+                    // a protected frame's results follow the `true` pcall left just below it
+                    let lead = frame.protected.is_some() as usize;
+                    let kept = if want == 0 {
+                        count
                     } else {
-                        let res = if count > 1 {
-                            self.pop_offset(ep, count as usize)
-                        } else {
-                            self.pop(ep)
-                        };
-
-                        ep.ip = frame.local_stack;
-                        self.close_upvalues_by_return(ep.ip);
-                        devout!("stack top {}", unsafe { &*ep.ip });
-                        self.stack_count = frame.stack_snapshot;
-                        frames.pop();
-                        frame = frames.last_mut().unwrap();
-                        devout!("next instruction {}", frame.current_instruction());
-                        #[cfg(feature = "dev-out")]
-                        self.print_stack();
-
-                        self.push(ep, res);
-                        // if frame.need > 1 {
-                        //     for _ in 1..frame.need {
-                        //         self.push(ep, Value::Nil);
-                        //     }
-                        // }
+                        (want - lead).min(count)
+                    };
+                    let base = self.slot(frame.stack_snapshot);
+                    self.close_upvalues_by_return(ep.mc, base);
+                    // slide the results down over the callee, no need to gather them anywhere else
+                    let results = unsafe { ep.ip.sub(count) };
+                    for i in 0..kept {
+                        unsafe { base.add(i).write(results.add(i).replace(Value::Nil)) };
                     }
-
-                    // frame.need = 1;
-
-                    // println!("<< {}", self.pop());
-                    // match self.pop() {
-                    //     Some(v) => return Ok(v),
-                    //     None => return Ok(last),
-                    // }
+                    ep.ip = unsafe { base.add(kept) };
+                    self.stack_count = frame.stack_snapshot + kept;
+                    if want == 0 {
+                        self.open_count = count + lead;
+                    } else if want > kept + lead {
+                        self.push_nils(ep, want - kept - lead)?;
+                    }
+                    frames.pop();
+                    self.depth -= 1;
+                    frame = frames.last_mut().unwrap();
+                    devout!("next instruction {}", frame.current_instruction());
+                    #[cfg(feature = "dev-out")]
+                    self.print_stack();
                 }
                 OpCode::CONSTANT { constant } => {
                     let value = Self::get_chunk(frame).get_constant(*constant);
-                    self.push(ep, value.clone());
+                    self.push(ep, value.clone())?;
                     // match value {
                     //     Value::Number(f) => self.push(*f),
                     //     Value::Integer(i) => self.push(*i as f64),
@@ -927,7 +1949,7 @@ impl<'gc> VM<'gc> {
                         // DEV inline pop due to self lifetime nonsense
                         self.stack_count -= 1;
                         unsafe { ep.ip = ep.ip.sub(1) };
-                        let v = unsafe { ep.ip.read() };
+                        let v = unsafe { ep.ip.replace(Value::Nil) };
 
                         // let v = self.pop();
                         self.globals.borrow_mut(ep.mc).insert(s.into(), v);
@@ -968,9 +1990,9 @@ impl<'gc> VM<'gc> {
                     if let Value::String(s) = value {
                         devout!("\"{}\"", s);
                         if let Some(v) = self.globals.borrow_mut(ep.mc).get(s) {
-                            self.push(ep, v.clone());
+                            self.push(ep, v.clone())?;
                         } else {
-                            self.push(ep, Value::Nil);
+                            self.push(ep, Value::Nil)?;
                         }
                     } else {
                         return Err(SiltError::VmCorruptConstant);
@@ -978,11 +2000,11 @@ impl<'gc> VM<'gc> {
                 }
                 OpCode::SET_LOCAL { index } => {
                     let value = self.duplicate(ep);
-                    // frame.stack[*index as usize] = value;
-                    frame.set_val(*index, value)
+                    self.stack[frame.stack_snapshot + *index as usize] = value;
                 }
                 OpCode::GET_LOCAL { index } => {
-                    self.push(ep, frame.get_val(*index).clone());
+                    let value = self.stack[frame.stack_snapshot + *index as usize].clone();
+                    self.push(ep, value)?;
                     // self.push(frame.stack[*index as usize].clone());
                     // TODO ew cloning, is our cloning optimized yet?
                     // TODO also we should convert from stack to register based so we can use the index as a reference instead
@@ -998,16 +2020,16 @@ impl<'gc> VM<'gc> {
 
                     match (left, right) {
                         (Value::Number(left), Value::Number(right)) => {
-                            self.push(ep, Value::Number(left / right))
+                            self.push(ep, Value::Number(left / right))?
                         }
                         (Value::Integer(left), Value::Integer(right)) => {
-                            self.push(ep, Value::Number(left as f64 / right as f64))
+                            self.push(ep, Value::Number(left as f64 / right as f64))?
                         }
                         (Value::Number(left), Value::Integer(right)) => {
-                            self.push(ep, Value::Number(left / right as f64))
+                            self.push(ep, Value::Number(left / right as f64))?
                         }
                         (Value::Integer(left), Value::Number(right)) => {
-                            self.push(ep, Value::Number(left as f64 / right))
+                            self.push(ep, Value::Number(left as f64 / right))?
                         }
                         (Value::Table(table), rr) => {
                             let v = table_meta_op!(
@@ -1020,7 +2042,7 @@ impl<'gc> VM<'gc> {
                                 rr,
                                 Div
                             );
-                            self.push(ep, v);
+                            self.push(ep, v)?;
                         }
                         (l, r) => {
                             return Err(SiltError::ExpOpValueWithValue(
@@ -1032,75 +2054,101 @@ impl<'gc> VM<'gc> {
                     }
                 }
 
+                OpCode::MODULUS => arith_op_push!(self, ep, frame, frames, frame_count, Mod),
+                OpCode::EXPONENT => arith_op_push!(self, ep, frame, frames, frame_count, Pow),
+                OpCode::FLOOR_DIVIDE => arith_op_push!(self, ep, frame, frames, frame_count, IDiv),
+                OpCode::BIT_AND => {
+                    arith_op_push!(self, ep, frame, frames, frame_count, BAnd, bitwise)
+                }
+                OpCode::BIT_OR => {
+                    arith_op_push!(self, ep, frame, frames, frame_count, BOr, bitwise)
+                }
+                OpCode::BIT_XOR => {
+                    arith_op_push!(self, ep, frame, frames, frame_count, BXor, bitwise)
+                }
+                OpCode::SHIFT_LEFT => {
+                    arith_op_push!(self, ep, frame, frames, frame_count, Shl, bitwise)
+                }
+                OpCode::SHIFT_RIGHT => {
+                    arith_op_push!(self, ep, frame, frames, frame_count, Shr, bitwise)
+                }
+                OpCode::BIT_NOT => {
+                    // unary metamethods receive the operand twice, same as lua
+                    let v = self.peek(ep).clone();
+                    self.push(ep, v)?;
+                    arith_op_push!(self, ep, frame, frames, frame_count, BNot, bitwise)
+                }
                 OpCode::NEGATE => {
                     match self.peek(ep) {
                         Value::Number(n) => {
                             let f = -n;
                             self.pop(ep);
-                            self.push(ep, Value::Number(f))
+                            self.push(ep, Value::Number(f))?
                         }
                         Value::Integer(i) => {
                             let f = -i;
                             self.pop(ep);
-                            self.push(ep, Value::Integer(f))
+                            self.push(ep, Value::Integer(f))?
                         }
                         // None => Err(SiltError::EarlyEndOfFile)?,
                         c => Err(SiltError::ExpInvalidNegation(c.to_error()))?,
                     }
                     // TODO  test this vs below: unsafe { *ep.ip = -*ep.ip };
                 }
-                OpCode::NIL => self.push(ep, Value::Nil),
-                OpCode::NILS(u) => self.push_nils(ep, *u as usize),
-                OpCode::TRUE => self.push(ep, Value::Bool(true)),
-                OpCode::FALSE => self.push(ep, Value::Bool(false)),
+                OpCode::NIL => self.push(ep, Value::Nil)?,
+                OpCode::NILS(u) => self.push_nils(ep, *u as usize)?,
+                OpCode::TRUE => self.push(ep, Value::Bool(true))?,
+                OpCode::FALSE => self.push(ep, Value::Bool(false))?,
                 OpCode::NOT => {
                     let value = self.pop(ep);
-                    self.push(ep, Value::Bool(!Self::is_truthy(&value)));
+                    self.push(ep, Value::Bool(!Self::is_truthy(&value)))?;
                 }
                 OpCode::EQUAL => {
                     let r = self.pop(ep);
                     let l = self.pop(ep);
-                    self.push(ep, Value::Bool(Self::is_equal(&l, &r)));
+                    self.push(ep, Value::Bool(Self::is_equal(&l, &r)))?;
                 }
                 OpCode::NOT_EQUAL => {
                     let r = self.pop(ep);
                     let l = self.pop(ep);
-                    self.push(ep, Value::Bool(!Self::is_equal(&l, &r)));
+                    self.push(ep, Value::Bool(!Self::is_equal(&l, &r)))?;
                 }
                 OpCode::LESS => {
                     let r = self.pop(ep);
                     let l = self.pop(ep);
-                    self.push(ep, Value::Bool(Self::is_less(&l, &r)?));
+                    self.push(ep, Value::Bool(Self::is_less(&l, &r)?))?;
                 }
                 OpCode::LESS_EQUAL => {
                     let r = self.pop(ep);
                     let l = self.pop(ep);
-                    self.push(ep, Value::Bool(!Self::is_greater(&l, &r)?));
+                    self.push(ep, Value::Bool(!Self::is_greater(&l, &r)?))?;
                 }
                 OpCode::GREATER => {
                     let r = self.pop(ep);
                     let l = self.pop(ep);
-                    self.push(ep, Value::Bool(Self::is_greater(&l, &r)?));
+                    self.push(ep, Value::Bool(Self::is_greater(&l, &r)?))?;
                 }
                 OpCode::GREATER_EQUAL => {
                     let r = self.pop(ep);
                     let l = self.pop(ep);
-                    self.push(ep, Value::Bool(!Self::is_less(&l, &r)?));
+                    self.push(ep, Value::Bool(!Self::is_less(&l, &r)?))?;
                 }
                 OpCode::CONCAT => {
                     let r = self.pop(ep);
                     let l = self.pop(ep);
                     match (l, r) {
                         (Value::String(left), Value::String(right)) => {
-                            self.push(ep, Value::String(left + &right))
+                            self.push(ep, Value::String(left + &right))?
                         }
                         (Value::String(left), v2) => {
-                            self.push(ep, Value::String(left + &v2.to_string()))
+                            self.push(ep, Value::String(left + &v2.to_string()))?
                         }
                         (v1, Value::String(right)) => {
-                            self.push(ep, Value::String(v1.to_string() + &right))
+                            self.push(ep, Value::String(v1.to_string() + &right))?
+                        }
+                        (v1, v2) => {
+                            self.push(ep, Value::String(v1.to_string() + &v2.to_string()))?
                         }
-                        (v1, v2) => self.push(ep, Value::String(v1.to_string() + &v2.to_string())),
                     }
                 }
 
@@ -1112,11 +2160,37 @@ impl<'gc> VM<'gc> {
                     self.pop(ep); //  last =
                 }
 
-                OpCode::POPS(n) => self.popn_drop(ep, *n), //TODO here's that 255 local limit again
+                OpCode::POPS(n) => self.popn_drop(ep, *n as usize), //TODO here's that 255 local limit again
+                OpCode::DUPLICATE(n) => {
+                    let n = *n as usize;
+                    // each push moves the next value we want to the same depth
+                    for _ in 0..n {
+                        let value = self.grab(ep, n).clone();
+                        self.push(ep, value)?;
+                    }
+                }
 
                 OpCode::CLOSE_UPVALUES(n) => {
                     self.close_n_upvalues(ep, *n);
                 }
+                OpCode::TO_BE_CLOSED { index } => {
+                    let slot = frame.stack_snapshot + *index as usize;
+                    match &self.stack[slot] {
+                        // nothing to close, same as lua
+                        Value::Nil | Value::Bool(false) => {}
+                        Value::Table(t) => {
+                            t.borrow().by_meta_method(MetaMethod::Close)?;
+                            self.to_be_closed.push(slot);
+                        }
+                        // userdata meta methods are only found when called
+                        Value::UserData(_) => self.to_be_closed.push(slot),
+                        _ => return Err(SiltError::MetaMethodMissing(MetaMethod::Close)),
+                    }
+                }
+                OpCode::CLOSE { index } => {
+                    let level = frame.stack_snapshot + *index as usize;
+                    self.close_to_be_closed(ep, level, Value::Nil)?;
+                }
 
                 OpCode::GOTO_IF_FALSE(offset) => {
                     let value = self.peek(ep);
@@ -1155,15 +2229,28 @@ impl<'gc> VM<'gc> {
                     if Self::is_greater(iterator, compare)? {
                         frame.forward(*skip);
                     } else {
-                        self.push(ep, iterator.clone())
+                        self.push(ep, iterator.clone())?
                     }
                     // self.push(iterator.clone());
                     // if iterator > compare {
                     //     frame.forward(*skip);
                     // }
                 }
+                OpCode::FOR_GENERIC(vars, skip) => {
+                    // the iterator call left `vars` results on top of the hidden function, state and control locals
+                    let first = unsafe { &*ep.ip.sub(*vars as usize) };
+                    if let Value::Nil = first {
+                        for _ in 0..*vars {
+                            self.pop(ep);
+                        }
+                        frame.forward(*skip);
+                    } else {
+                        let control = first.clone();
+                        unsafe { *ep.ip.sub(*vars as usize + 1) = control };
+                    }
+                }
                 OpCode::INCREMENT { index } => {
-                    let value = frame.get_val_mut(*index);
+                    let value = unsafe { &mut *self.slot(frame.stack_snapshot + *index as usize) };
                     let step = self.peek(ep);
                     value.increment(step)?;
                 }
@@ -1222,12 +2309,12 @@ impl<'gc> VM<'gc> {
 
                     #[cfg(feature = "dev-out")]
                     {
-                        frame.print_local_stack();
+                        self.print_local_stack(frame);
                         frame.function.print_upvalues();
                     }
 
                     devout!("GET_UPVALUE: {}", value);
-                    self.push(ep, value);
+                    self.push(ep, value)?;
                 }
                 OpCode::SET_UPVALUE { index } => {
                     let value = self.peek(ep); // TODO pop and set would be faster, less cloning
@@ -1238,8 +2325,13 @@ impl<'gc> VM<'gc> {
                     // unsafe { *upvalue.value = value };
                 }
 
-                OpCode::CALL(arity, multi) => {
-                    let value = self.peekn(ep, *arity);
+                OpCode::CALL(_, _) | OpCode::CALL_OPEN(_, _) => {
+                    let (arity, multi) = match instruction {
+                        OpCode::CALL_OPEN(a, m) => (*a as usize + self.open_count, *m),
+                        OpCode::CALL(a, m) => (*a as usize, *m),
+                        _ => unreachable!(),
+                    };
+                    let value = self.peekn(ep, arity);
                     devout!(" | -> {}", value);
                     match value {
                         Value::Closure(c) => {
@@ -1260,17 +2352,19 @@ impl<'gc> VM<'gc> {
                             // frames.push(new_frame);
                             // frame = frames.last_mut().unwrap();
                             // frame.local_stack = frame_top;
-                            let arity = *arity as usize;
                             // println!("arity {}",arity);
-
-                            let frame_top = unsafe { ep.ip.sub(arity + 1) };
-                            let new_frame =
-                                CallFrame::new(c.clone(), self.stack_count - arity - 1, *multi);
+                            let c = *c;
+                            self.enter_frame()?;
+                            let varargs = self.adjust_arguments(ep, &c, arity)?;
+                            let arity = c.function.arity as usize;
+
+                            let mut new_frame =
+                                CallFrame::new(c, self.stack_count - arity - 1, multi);
+                            new_frame.varargs = varargs;
                             frames.push(new_frame);
                             frame = frames.last_mut().unwrap();
-                            frame.local_stack = frame_top;
                             frame_count += 1;
-                            devout!("top of frame stack {}", unsafe { &*frame.local_stack });
+                            devout!("top of frame stack {}", self.stack[frame.stack_snapshot]);
                         }
                         Value::Function(_func) => {
                             // let frame_top =
@@ -1294,13 +2388,28 @@ impl<'gc> VM<'gc> {
                         Value::NativeFunction(_) => {
                             // get args including the function value at index 0. We do it here so don't have mutability issues with native fn
                             // TODO get a reference instead of the a-pop-olypse
-                            let mut args = self.popn(ep, *arity + 1);
+                            let mut args = self.popn(ep, arity + 1);
                             // todo!("Hi there! we need to set arity of userdata functions to include self! At least this is hirting our abstraction, we could force it but that's dangerous! Let's perhas make userdata methods Option<Self>");
 
                             if let Value::NativeFunction(f) = args.remove(0) {
-                                let res = f.f.call(self, ep.mc, &args);
+                                let res = f.f.call(self, ep.mc, &args)?;
                                 // self.popn_drop(*param_count);
-                                self.push(ep, res?);
+                                match self.request.take() {
+                                    Some(request) => {
+                                        if let Some(new_frame) =
+                                            self.fulfill(ep, frames, request, multi)?
+                                        {
+                                            self.enter_frame()?;
+                                            frames.push(new_frame);
+                                            frame_count += 1;
+                                        }
+                                        if self.yielded.is_some() {
+                                            return Ok(Value::Nil);
+                                        }
+                                        frame = frames.last_mut().unwrap();
+                                    }
+                                    None => self.push_returns(ep, res, multi)?,
+                                }
                             } else {
                                 unreachable!();
                             }
@@ -1311,6 +2420,70 @@ impl<'gc> VM<'gc> {
                     }
                 }
 
+                OpCode::TAIL_CALL(_) | OpCode::TAIL_CALL_OPEN(_) => {
+                    let arity = match instruction {
+                        OpCode::TAIL_CALL_OPEN(a) => *a as usize + self.open_count,
+                        OpCode::TAIL_CALL(a) => *a as usize,
+                        _ => unreachable!(),
+                    };
+                    match self.peekn(ep, arity) {
+                        Value::Closure(c) => {
+                            let c = *c;
+                            let varargs = self.adjust_arguments(ep, &c, arity)?;
+                            let arity = c.function.arity as usize;
+                            // slide the callee and arguments down over our own frame and take its place
+                            let values = self.popn(ep, arity + 1);
+                            let base = self.slot(frame.stack_snapshot);
+                            self.close_upvalues_by_return(ep.mc, base);
+                            ep.ip = base;
+                            self.stack_count = frame.stack_snapshot;
+                            self.pushn(ep, values, arity + 1)?;
+                            let mut new_frame =
+                                CallFrame::new(c, frame.stack_snapshot, frame.multi_return);
+                            new_frame.varargs = varargs;
+                            new_frame.protected = frame.protected.take();
+                            new_frame.coroutine = frame.coroutine;
+                            *frame = new_frame;
+                        }
+                        Value::NativeFunction(_) => {
+                            // nothing to reuse, call it and let the RETURN_OPEN that follows hand back every result
+                            let mut args = self.popn(ep, arity + 1);
+                            if let Value::NativeFunction(f) = args.remove(0) {
+                                let res = f.f.call(self, ep.mc, &args)?;
+                                if let Some(request) = self.request.take() {
+                                    if let Some(new_frame) = self.fulfill(ep, frames, request, 0)? {
+                                        self.enter_frame()?;
+                                        frames.push(new_frame);
+                                        frame_count += 1;
+                                    }
+                                    if self.yielded.is_some() {
+                                        return Ok(Value::Nil);
+                                    }
+                                    frame = frames.last_mut().unwrap();
+                                } else {
+                                    let count = res.len().max(1);
+                                    self.pushn(ep, res, count)?;
+                                    self.open_count = count;
+                                }
+                            } else {
+                                unreachable!();
+                            }
+                        }
+                        value => {
+                            return Err(SiltError::NotCallable(format!("Value: {}", value)));
+                        }
+                    }
+                }
+                OpCode::VARARGS(n) => {
+                    let n = *n as usize;
+                    let values = frame.varargs.clone();
+                    if n == 0 {
+                        self.open_count = values.len();
+                        self.pushn(ep, values, self.open_count)?;
+                    } else {
+                        self.pushn(ep, values, n)?;
+                    }
+                }
                 OpCode::PRINT => {
                     println!("<<<<<< {} >>>>>>>", self.pop(ep));
                 }
@@ -1322,20 +2495,25 @@ impl<'gc> VM<'gc> {
                 OpCode::LENGTH => {
                     let value = self.pop(ep);
                     match value {
-                        Value::String(s) => self.push(ep, Value::Integer(s.len() as i64)),
-                        Value::Table(t) => self.push(ep, Value::Integer(t.borrow().len() as i64)),
+                        Value::String(s) => self.push(ep, Value::Integer(s.len() as i64))?,
+                        Value::Table(t) => {
+                            self.push(ep, Value::Integer(t.borrow().len() as i64))?
+                        }
                         _ => Err(SiltError::ExpInvalidLength(value.to_error()))?,
                     }
                 }
                 OpCode::NEW_TABLE => {
-                    self.push(ep, self.new_table(ep.mc));
+                    self.push(ep, self.new_table(ep.mc))?;
                     *self.table_counter.borrow_mut() += 1;
                 }
                 OpCode::TABLE_INSERT { offset } => {
                     self.insert_immediate_table(ep, *offset)?;
                 }
                 OpCode::TABLE_BUILD(n) => {
-                    self.build_table(ep, *n)?;
+                    self.build_table(ep, *n as usize)?;
+                }
+                OpCode::TABLE_BUILD_OPEN(n) => {
+                    self.build_table(ep, *n as usize + self.open_count)?;
                 }
                 OpCode::TABLE_SET { depth } => {
                     let value = self.pop(ep);
@@ -1362,6 +2540,10 @@ impl<'gc> VM<'gc> {
                         _ => Err(SiltError::MetaMethodMissing(MetaMethod::Index)),
                     }?;
                 }
+                OpCode::TABLE_ASSIGN { offset, depth } => {
+                    let value = self.pop(ep);
+                    self.assign_path(ep, *offset as usize, *depth, value)?;
+                }
                 // OpCode::TABLE_SET_BY_CONSTANT { constant } => {
                 //     let value = self.pop();
                 //     let key = Self::get_chunk(&frame).get_constant(*constant);
@@ -1408,6 +2590,27 @@ impl<'gc> VM<'gc> {
                         _ => return Err(SiltError::VmNonTableOperations(value.to_error())),
                     }
                 }
+                OpCode::GET_METHOD => {
+                    let key = self.pop(ep);
+                    let object = self.pop(ep);
+                    let method = match object {
+                        Value::Table(t) => (*t).borrow().get_value(&key),
+                        Value::UserData(ud) => {
+                            let field_name = key.pure_string();
+                            let mut mu = ud.borrow_mut(ep.mc);
+                            crate::userdata::vm_integration::get_field(
+                                self,
+                                &self.userdata_registry,
+                                ep.mc,
+                                mu.deref_mut(),
+                                &field_name,
+                            )?
+                        }
+                        _ => return Err(SiltError::VmNonTableOperations(object.to_error())),
+                    };
+                    self.push(ep, method)?;
+                    self.push(ep, object)?;
+                }
                 OpCode::TABLE_GET_FROM { index: _ } => {
                     // let key = self.pop();
 
@@ -1429,7 +2632,7 @@ impl<'gc> VM<'gc> {
 
                         let v: Value = (*t).borrow().get_value(&key);
                         // let v:Value = t.borrow().get_value(&key);
-                        self.push(ep, v);
+                        self.push(ep, v)?;
                     } else {
                         return Err(SiltError::VmNonTableOperations(table.to_error()));
                     }
@@ -1553,14 +2756,18 @@ impl<'gc> VM<'gc> {
         };
 
         let mut ep = Ephemeral::new(mc, self.stack.as_mut_ptr());
-        self.stack_count += res.len();
-        match self.external_functions.get(u) {
+        match self.external_functions.get(u).copied() {
             Some(f) => {
                 for param in res {
-                    VM::push_raw(&mut ep, param);
+                    if let Err(code) = self.push(&mut ep, param) {
+                        return Err(vec![ErrorTuple {
+                            code,
+                            location: (0, 0),
+                        }]);
+                    }
                 }
 
-                self.run(mc, *f)
+                self.run(mc, f)
             }
             None => Err(vec![ErrorTuple {
                 code: SiltError::Unknown,
@@ -1615,13 +2822,14 @@ impl<'gc> VM<'gc> {
         frame: &CallFrame<'gc>,
     ) -> Gc<'gc, RefLock<UpValue<'gc>>> {
         #[cfg(feature = "dev-out")]
-        frame.print_local_stack();
-        let value = unsafe { frame.local_stack.add(index as usize) };
+        self.print_local_stack(frame);
+        let value = self.slot(frame.stack_snapshot + index as usize);
         devout!("2capture_upvalue at index {} : {}", index, unsafe {
             &*value
         });
+        // keep open upvalues ordered by stack location, lowest first
         let mut ind = None;
-        for (i, up) in self.open_upvalues.iter().enumerate() {
+        for (i, up) in self.open_upvalues.iter().enumerate().rev() {
             let u = *up;
             let upvalue = u.borrow();
             if upvalue.location == value {
@@ -1728,15 +2936,15 @@ impl<'gc> VM<'gc> {
     //     Err(SiltError::Unknown)
     // }
 
-    fn build_table(&mut self, ep: &mut Ephemeral<'_, 'gc>, n: u8) -> Result<(), SiltError> {
-        let offset = n as usize + 1;
+    fn build_table(&mut self, ep: &mut Ephemeral<'_, 'gc>, n: usize) -> Result<(), SiltError> {
+        let offset = n + 1;
         let table_point = unsafe { ep.ip.sub(offset) };
         let table = unsafe { &*table_point };
         if let Value::Table(t) = table {
             let mut b = (*t).borrow_mut(ep.mc);
             // push in reverse
             for i in (0..n).rev() {
-                let value = unsafe { ep.ip.sub(i as usize + 1).replace(Value::Nil) };
+                let value = unsafe { ep.ip.sub(i + 1).replace(Value::Nil) };
                 b.push(value);
             }
 
@@ -1789,7 +2997,7 @@ impl<'gc> VM<'gc> {
         if let Value::Table(t) = table {
             let mut current = *t;
             for i in 1..=depth {
-                let key = unsafe { ep.ip.sub(i as usize).replace(Value::Nil) };
+                let key = unsafe { table_point.add(i as usize).replace(Value::Nil) };
                 devout!("get from table with key: {}", key);
                 if i == depth {
                     // let offset = depth as usize;
@@ -1815,12 +3023,8 @@ impl<'gc> VM<'gc> {
                         Some(Value::Table(t)) => {
                             current = *t;
                         }
-                        Some(v) => {
-                            return Err(SiltError::VmNonTableOperations(v.to_error()));
-                        }
-                        None => {
-                            return Err(SiltError::VmNonTableOperations(ValueTypes::Nil));
-                        }
+                        Some(v) => return Err(field_error(&key, v.to_error())),
+                        None => return Err(field_error(&key, ValueTypes::Nil)),
                     }
                 }
             }
@@ -1846,6 +3050,69 @@ impl<'gc> VM<'gc> {
         // }
     }
 
+    /** fit the arguments on the stack to the closure's parameters, extras are handed back if it's variadic */
+    fn adjust_arguments(
+        &mut self,
+        ep: &mut Ephemeral<'_, 'gc>,
+        closure: &Closure<'gc>,
+        arity: usize,
+    ) -> Result<Vec<Value<'gc>>, SiltError> {
+        let params = closure.function.arity as usize;
+        if arity > params {
+            if closure.function.is_variadic {
+                Ok(self.popn(ep, arity - params))
+            } else {
+                self.popn_drop(ep, arity - params);
+                Ok(vec![])
+            }
+        } else {
+            self.push_nils(ep, params - arity)?;
+            Ok(vec![])
+        }
+    }
+
+    /** like operate_table's store but the table and keys are read in place and left for a later POPS */
+    fn assign_path(
+        &mut self,
+        ep: &mut Ephemeral<'_, 'gc>,
+        offset: usize,
+        depth: u8,
+        value: Value<'gc>,
+    ) -> Result<(), SiltError> {
+        let table_point = unsafe { ep.ip.sub(offset) };
+        let key_at = |i: u8| unsafe { &*table_point.add(i as usize) };
+        let mut current = match unsafe { &*table_point } {
+            Value::Table(t) => *t,
+            Value::UserData(u) if depth == 1 => {
+                let field_name = key_at(1).pure_string();
+                let u = &mut *(*u).borrow_mut(ep.mc);
+                let reg = &self.userdata_registry;
+                return crate::userdata::vm_integration::set_field(
+                    self,
+                    reg,
+                    ep.mc,
+                    u,
+                    &field_name,
+                    value,
+                )
+                .map(|_| ());
+            }
+            v => return Err(SiltError::VmNonTableOperations(v.to_error())),
+        };
+        for i in 1..depth {
+            let next = match current.borrow().getr(key_at(i)) {
+                Some(Value::Table(t)) => *t,
+                Some(v) => return Err(field_error(key_at(i), v.to_error())),
+                None => return Err(field_error(key_at(i), ValueTypes::Nil)),
+            };
+            current = next;
+        }
+        current
+            .borrow_mut(ep.mc)
+            .insert(key_at(depth).clone(), value);
+        Ok(())
+    }
+
     /// Handle binary operations with UserData
     pub(crate) fn handle_userdata_binary_op(
         &mut self,
@@ -1920,6 +3187,72 @@ impl<'gc> VM<'gc> {
         self.register_native_function(mc, "setmetatable", crate::standard::setmetatable);
         self.register_native_function(mc, "getmetatable", crate::standard::getmetatable);
         self.register_native_function(mc, "test_ent", crate::standard::test_ent);
+        let next = self.wrap_native_function_multi(mc, crate::standard::next);
+        self.globals
+            .borrow_mut(mc)
+            .insert("next".into(), next.clone());
+        let pairs = crate::standard::new_pairs(mc, next);
+        self.globals.borrow_mut(mc).insert("pairs".into(), pairs);
+        self.register_native_function_multi(mc, "ipairs", crate::standard::ipairs);
+        self.register_native_function_multi(mc, "select", crate::standard::select);
+        self.register_native_function_multi(mc, "error", crate::standard::error);
+        self.register_native_function_multi(mc, "pcall", crate::standard::pcall);
+        self.register_native_function_multi(mc, "xpcall", crate::standard::xpcall);
+
+        let table = self.new_table(mc);
+        if let Value::Table(t) = &table {
+            let mut t = (*t).borrow_mut(mc);
+            t.insert(
+                "pack".into(),
+                self.wrap_native_function_multi(mc, crate::standard::table_pack),
+            );
+            t.insert(
+                "unpack".into(),
+                self.wrap_native_function_multi(mc, crate::standard::table_unpack),
+            );
+        }
+        self.globals.borrow_mut(mc).insert("table".into(), table);
+
+        let debug = self.new_table(mc);
+        if let Value::Table(t) = &debug {
+            (*t).borrow_mut(mc).insert(
+                "traceback".into(),
+                self.wrap_native_function_multi(mc, crate::standard::debug_traceback),
+            );
+        }
+        self.globals.borrow_mut(mc).insert("debug".into(), debug);
+
+        let coroutine = self.new_table(mc);
+        if let Value::Table(t) = &coroutine {
+            let mut t = (*t).borrow_mut(mc);
+            t.insert(
+                "create".into(),
+                self.wrap_native_function_multi(mc, crate::standard::coroutine_create),
+            );
+            t.insert(
+                "resume".into(),
+                self.wrap_native_function_multi(mc, crate::standard::coroutine_resume),
+            );
+            t.insert(
+                "yield".into(),
+                self.wrap_native_function_multi(mc, crate::standard::coroutine_yield),
+            );
+            t.insert(
+                "status".into(),
+                self.wrap_native_function_multi(mc, crate::standard::coroutine_status),
+            );
+            t.insert(
+                "wrap".into(),
+                self.wrap_native_function_multi(mc, crate::standard::coroutine_wrap),
+            );
+            t.insert(
+                "isyieldable".into(),
+                self.wrap_native_function_multi(mc, crate::standard::coroutine_isyieldable),
+            );
+        }
+        self.globals
+            .borrow_mut(mc)
+            .insert("coroutine".into(), coroutine);
 
         // Example of closure without turbofish
         // let test = Box::new(5);
@@ -1951,11 +3284,37 @@ impl<'gc> VM<'gc> {
     {
         let raw = NativeFunctionRaw::new::<A, _, _>(function);
 
-        let f = WrappedFn { f: Rc::new(raw) };
+        let f = WrappedFn::new(Rc::new(raw));
         // Value::NativeFunction(Gc::new(mc, f))
         let v = Value::NativeFunction(Gc::new(mc, f));
         self.globals.borrow_mut(mc).insert(name.into(), v);
     }
+
+    /// same as register_native_function but the function may return multiple values
+    pub fn register_native_function_multi<A, F, R>(
+        &mut self,
+        mc: &Mutation<'gc>,
+        name: &str,
+        function: F,
+    ) where
+        A: FromLuaMulti<'gc>,
+        F: Fn(&mut VM<'gc>, &Mutation<'gc>, A) -> Result<R, SiltError> + 'gc,
+        R: ToLuaMulti<'gc> + 'gc,
+    {
+        let v = self.wrap_native_function_multi(mc, function);
+        self.globals.borrow_mut(mc).insert(name.into(), v);
+    }
+
+    /// wrap a multiple return native function as a value without registering it as a global
+    pub fn wrap_native_function_multi<A, F, R>(&self, mc: &Mutation<'gc>, function: F) -> Value<'gc>
+    where
+        A: FromLuaMulti<'gc>,
+        F: Fn(&mut VM<'gc>, &Mutation<'gc>, A) -> Result<R, SiltError> + 'gc,
+        R: ToLuaMulti<'gc> + 'gc,
+    {
+        let raw = NativeFunctionRaw::new_multi::<A, _, _>(function);
+        Value::NativeFunction(Gc::new(mc, WrappedFn::new(Rc::new(raw))))
+    }
     // pub fn register_native_function<T, R>(
     //     &mut self,
     //     mc: &Mutation<'gc>,
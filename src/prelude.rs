@@ -1,9 +1,9 @@
 #[doc(no_inline)]
 pub use crate::{
-    compiler::Compiler,
+    compiler::{Compiler, LanguageFlags},
     error::{SiltError as LuaError, ValueTypes},
     function::{Closure, FunctionObject},
-    lua::{Lua, VM},
+    lua::{Limits, Lua, VM},
     table::Table,
     userdata::{UserData, UserDataFields, UserDataTypedMap},
     value::{Reference, Value},
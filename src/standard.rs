@@ -1,8 +1,13 @@
-use gc_arena::Mutation;
+use std::rc::Rc;
+
+use gc_arena::{lock::RefLock, Gc, Mutation};
 
 use crate::{
     prelude::VM,
-    userdata::{InnerResult, TestEnt},
+    error::SiltError,
+    function::{NativeFunctionRaw, WrappedFn},
+    thread::Thread,
+    userdata::{InnerResult, MetaMethod, MultiResult, TestEnt},
     value::{Value, FromLuaMulti},
 };
 
@@ -59,9 +64,338 @@ pub fn getmetatable<'lua>(
     })
 }
 
-pub fn select<'lua>(_: &mut VM, _: &Mutation<'lua>, _args: Vec<Value<'lua>>) -> InnerResult<'lua> {
-    // Value::Nil
-    todo!()
+pub fn next<'lua>(_: &mut VM, _: &Mutation<'lua>, args: Vec<Value<'lua>>) -> MultiResult<'lua> {
+    let key = args.get(1).cloned().unwrap_or(Value::Nil);
+    match args.first() {
+        Some(Value::Table(t)) => Ok(match t.borrow().next(&key)? {
+            Some((k, v)) => vec![k, v],
+            None => vec![Value::Nil],
+        }),
+        Some(v) => Err(SiltError::VmNonTableOperations(v.to_error())),
+        None => Err(SiltError::VmNonTableOperations(Value::Nil.to_error())),
+    }
+}
+
+/** `pairs` holding on to the `next` it hands out, which is the same one registered as the global */
+pub fn new_pairs<'lua>(mc: &Mutation<'lua>, next: Value<'lua>) -> Value<'lua> {
+    let iterator = next.clone();
+    let raw = NativeFunctionRaw::new_multi(
+        move |vm: &mut VM<'lua>, _: &Mutation<'lua>, args: Vec<Value<'lua>>| {
+            pairs(vm, args, iterator.clone())
+        },
+    );
+    let f = WrappedFn {
+        f: Rc::new(raw),
+        bound: next,
+    };
+    Value::NativeFunction(Gc::new(mc, f))
+}
+
+/** `pairs(t)` is `next, t, nil` unless t's metatable has `__pairs`, then that's called with t and
+ * what it returns is used instead */
+fn pairs<'lua>(vm: &mut VM<'lua>, args: Vec<Value<'lua>>, next: Value<'lua>) -> MultiResult<'lua> {
+    match args.first() {
+        Some(Value::Table(t)) => match t.borrow().by_meta_method(MetaMethod::Pairs) {
+            Ok(f) => {
+                vm.request_call(f, vec![Value::Table(*t)]);
+                Ok(vec![])
+            }
+            Err(SiltError::MetaMethodMissing(_)) => Ok(vec![next, Value::Table(*t), Value::Nil]),
+            Err(e) => Err(e),
+        },
+        Some(v) => Err(SiltError::VmNonTableOperations(v.to_error())),
+        None => Err(SiltError::VmNonTableOperations(Value::Nil.to_error())),
+    }
+}
+
+/** iterator function for ipairs, walks integer keys from 1 until the first nil */
+fn ipairs_next<'lua>(
+    _: &mut VM,
+    _: &Mutation<'lua>,
+    args: Vec<Value<'lua>>,
+) -> MultiResult<'lua> {
+    let i = match args.get(1) {
+        Some(Value::Integer(i)) => *i + 1,
+        _ => 1,
+    };
+    match args.first() {
+        Some(Value::Table(t)) => Ok(match t.borrow().get(Value::Integer(i)) {
+            Some(v) => vec![Value::Integer(i), v.clone()],
+            None => vec![Value::Nil],
+        }),
+        Some(v) => Err(SiltError::VmNonTableOperations(v.to_error())),
+        None => Err(SiltError::VmNonTableOperations(Value::Nil.to_error())),
+    }
+}
+
+pub fn ipairs<'lua>(
+    vm: &mut VM<'lua>,
+    mc: &Mutation<'lua>,
+    args: Vec<Value<'lua>>,
+) -> MultiResult<'lua> {
+    match args.first() {
+        Some(t @ Value::Table(_)) => Ok(vec![
+            vm.wrap_native_function_multi(mc, ipairs_next),
+            t.clone(),
+            Value::Integer(0),
+        ]),
+        Some(v) => Err(SiltError::VmNonTableOperations(v.to_error())),
+        None => Err(SiltError::VmNonTableOperations(Value::Nil.to_error())),
+    }
+}
+
+/** integer argument for the standard library, floats and strings must hold an exact integer */
+fn integer_arg(args: &[Value], i: usize, name: &str) -> Result<Option<i64>, SiltError> {
+    match args.get(i) {
+        None | Some(Value::Nil) => Ok(None),
+        Some(Value::Integer(n)) => Ok(Some(*n)),
+        Some(Value::Number(n)) if n.fract() == 0. => Ok(Some(*n as i64)),
+        Some(Value::String(s)) if s.parse::<i64>().is_ok() => Ok(s.parse::<i64>().ok()),
+        Some(v) => Err(SiltError::VmBadArgument(
+            i + 1,
+            name.to_string(),
+            format!("number expected, got {}", v.to_error()),
+        )),
+    }
+}
+
+/** `select('#', ...)` counts the values, `select(n, ...)` returns everything from n on, negative n counts from the end */
+pub fn select<'lua>(_: &mut VM, _: &Mutation<'lua>, args: Vec<Value<'lua>>) -> MultiResult<'lua> {
+    let count = args.len().saturating_sub(1) as i64;
+    if let Some(Value::String(s)) = args.first() {
+        if s == "#" {
+            return Ok(vec![Value::Integer(count)]);
+        }
+    }
+    let n = match integer_arg(&args, 0, "select")? {
+        Some(n) if n < 0 && -n <= count => count + n + 1,
+        Some(n) if n > 0 => n,
+        _ => {
+            return Err(SiltError::VmBadArgument(
+                1,
+                "select".to_string(),
+                "index out of range".to_string(),
+            ))
+        }
+    };
+    Ok(args.into_iter().skip(n as usize).collect())
+}
+
+/** pack all arguments into a new table with the count stored in field `n` */
+pub fn table_pack<'lua>(
+    vm: &mut VM<'lua>,
+    mc: &Mutation<'lua>,
+    args: Vec<Value<'lua>>,
+) -> MultiResult<'lua> {
+    let table = vm.new_table(mc);
+    if let Value::Table(t) = &table {
+        let mut t = t.borrow_mut(mc);
+        let n = args.len() as i64;
+        for (i, v) in args.into_iter().enumerate() {
+            t.insert(Value::Integer(i as i64 + 1), v);
+        }
+        t.insert("n".into(), Value::Integer(n));
+    }
+    Ok(vec![table])
+}
+
+/** return the values of `t[i]` through `t[j]`, defaulting to the whole array part */
+pub fn table_unpack<'lua>(
+    vm: &mut VM<'lua>,
+    _: &Mutation<'lua>,
+    args: Vec<Value<'lua>>,
+) -> MultiResult<'lua> {
+    match args.first() {
+        Some(Value::Table(t)) => {
+            let t = t.borrow();
+            let i = integer_arg(&args, 1, "unpack")?.unwrap_or(1);
+            let j = integer_arg(&args, 2, "unpack")?.unwrap_or(t.border() as i64);
+            if j as i128 - i as i128 >= vm.limits.stack as i128 {
+                return Err(SiltError::VmUnpackTooMany);
+            }
+            Ok((i..=j)
+                .map(|k| t.get(Value::Integer(k)).cloned().unwrap_or(Value::Nil))
+                .collect())
+        }
+        Some(v) => Err(SiltError::VmNonTableOperations(v.to_error())),
+        None => Err(SiltError::VmNonTableOperations(Value::Nil.to_error())),
+    }
+}
+
+/** `error(value, level)` raises any value, a pcall hands it back untouched. A string message is prefixed with the position of the call `level` up, 1 being the function that called error and 0 leaving it alone */
+pub fn error<'lua>(
+    vm: &mut VM<'lua>,
+    _: &Mutation<'lua>,
+    args: Vec<Value<'lua>>,
+) -> MultiResult<'lua> {
+    let level = integer_arg(&args, 1, "error")?.unwrap_or(1).max(0) as usize;
+    let value = args.into_iter().next().unwrap_or(Value::Nil);
+    Err(vm.throw(value, level))
+}
+
+/** `pcall(f, ...)` returns `true` and everything f returns, or `false` and the error if it raised one */
+pub fn pcall<'lua>(
+    vm: &mut VM<'lua>,
+    _: &Mutation<'lua>,
+    mut args: Vec<Value<'lua>>,
+) -> MultiResult<'lua> {
+    if args.is_empty() {
+        return Err(SiltError::VmBadArgument(
+            1,
+            "pcall".to_string(),
+            "value expected".to_string(),
+        ));
+    }
+    let callee = args.remove(0);
+    // the VM makes the call once we return so it can unwind back to it
+    vm.protect(callee, args, Value::Nil);
+    Ok(vec![])
+}
+
+/** `xpcall(f, msgh, ...)` is pcall but an error is passed through msgh before the stack unwinds, it's result is returned in place of the error */
+pub fn xpcall<'lua>(
+    vm: &mut VM<'lua>,
+    _: &Mutation<'lua>,
+    mut args: Vec<Value<'lua>>,
+) -> MultiResult<'lua> {
+    if args.len() < 2 {
+        return Err(SiltError::VmBadArgument(
+            2,
+            "xpcall".to_string(),
+            "value expected".to_string(),
+        ));
+    }
+    let callee = args.remove(0);
+    let handler = args.remove(0);
+    vm.protect(callee, args, handler);
+    Ok(vec![])
+}
+
+/** `debug.traceback(message, level)` the message followed by the calls running from `level` on, any other value than a string is returned untouched */
+pub fn debug_traceback<'lua>(
+    vm: &mut VM<'lua>,
+    _: &Mutation<'lua>,
+    args: Vec<Value<'lua>>,
+) -> MultiResult<'lua> {
+    let message = match args.first() {
+        None | Some(Value::Nil) => None,
+        Some(Value::String(s)) => Some(s.to_string()),
+        Some(v) => return Ok(vec![v.clone()]),
+    };
+    let level = integer_arg(&args, 1, "traceback")?.unwrap_or(1).max(0) as usize;
+    // only the VM can see the frames, it returns the string for us
+    vm.request_traceback(message, level);
+    Ok(vec![])
+}
+
+/** coroutine argument for the standard library */
+fn thread_arg<'lua>(
+    args: &[Value<'lua>],
+    i: usize,
+    name: &str,
+) -> Result<Gc<'lua, RefLock<Thread<'lua>>>, SiltError> {
+    match args.get(i) {
+        Some(Value::Thread(t)) => Ok(*t),
+        v => Err(SiltError::VmBadArgument(
+            i + 1,
+            name.to_string(),
+            format!(
+                "coroutine expected, got {}",
+                v.unwrap_or(&Value::Nil).to_error()
+            ),
+        )),
+    }
+}
+
+/** `coroutine.create(f)` a coroutine that runs f once it's first resumed */
+pub fn coroutine_create<'lua>(
+    _: &mut VM<'lua>,
+    mc: &Mutation<'lua>,
+    args: Vec<Value<'lua>>,
+) -> MultiResult<'lua> {
+    Ok(vec![new_coroutine(mc, &args, "create")?])
+}
+
+/** Unlike lua the body has to be a lua function, silt can't suspend a native halfway so it's
+ * refused up front */
+fn new_coroutine<'lua>(mc: &Mutation<'lua>, args: &[Value<'lua>], name: &str) -> InnerResult<'lua> {
+    let reason = match args.first() {
+        Some(Value::Closure(c)) => {
+            return Ok(Value::Thread(Gc::new(mc, RefLock::new(Thread::new(*c)))))
+        }
+        Some(Value::NativeFunction(_)) => {
+            "silt can't run a native function as a coroutine, wrap it in a lua function".to_string()
+        }
+        v => format!(
+            "function expected, got {}",
+            v.unwrap_or(&Value::Nil).to_error()
+        ),
+    };
+    Err(SiltError::VmBadArgument(1, name.to_string(), reason))
+}
+
+/** `coroutine.resume(co, ...)` runs co until it yields or returns, `true` and the values passed either way or `false` and the error if it failed */
+pub fn coroutine_resume<'lua>(
+    vm: &mut VM<'lua>,
+    _: &Mutation<'lua>,
+    mut args: Vec<Value<'lua>>,
+) -> MultiResult<'lua> {
+    let thread = thread_arg(&args, 0, "resume")?;
+    args.remove(0);
+    // the VM swaps in the coroutine's stack and runs it once we return
+    vm.request_resume(thread, args, false);
+    Ok(vec![])
+}
+
+/** `coroutine.yield(...)` suspends the running coroutine, it's values are returned by resume and the next resume's values are returned here */
+pub fn coroutine_yield<'lua>(
+    vm: &mut VM<'lua>,
+    _: &Mutation<'lua>,
+    args: Vec<Value<'lua>>,
+) -> MultiResult<'lua> {
+    vm.request_yield(args)?;
+    Ok(vec![])
+}
+
+/** `coroutine.status(co)` is one of "suspended", "running", "normal" or "dead" */
+pub fn coroutine_status<'lua>(
+    _: &mut VM<'lua>,
+    _: &Mutation<'lua>,
+    args: Vec<Value<'lua>>,
+) -> MultiResult<'lua> {
+    let thread = thread_arg(&args, 0, "status")?;
+    let status = thread.borrow().status.to_string();
+    Ok(vec![Value::String(status)])
+}
+
+/** `coroutine.wrap(f)` a function resuming a new coroutine each time it's called, it returns the values without the leading `true` and raises the coroutine's errors */
+pub fn coroutine_wrap<'lua>(
+    _: &mut VM<'lua>,
+    mc: &Mutation<'lua>,
+    args: Vec<Value<'lua>>,
+) -> MultiResult<'lua> {
+    let coroutine = new_coroutine(mc, &args, "wrap")?;
+    let thread = thread_arg(&[coroutine.clone()], 0, "wrap")?;
+    let raw = NativeFunctionRaw::new_multi(
+        move |vm: &mut VM<'lua>, _: &Mutation<'lua>, args: Vec<Value<'lua>>| {
+            vm.request_resume(thread, args, true);
+            MultiResult::Ok(vec![])
+        },
+    );
+    let f = WrappedFn {
+        f: Rc::new(raw),
+        bound: coroutine,
+    };
+    Ok(vec![Value::NativeFunction(Gc::new(mc, f))])
+}
+
+/** `coroutine.isyieldable()` true when running in a coroutine that isn't inside a metamethod or native call */
+pub fn coroutine_isyieldable<'lua>(
+    vm: &mut VM<'lua>,
+    _: &Mutation<'lua>,
+    _: Vec<Value<'lua>>,
+) -> MultiResult<'lua> {
+    Ok(vec![Value::Bool(vm.is_yieldable())])
 }
 
 pub fn test_ent<'lua>(
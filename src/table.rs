@@ -1,5 +1,5 @@
 use std::{
-    collections::{hash_map::Iter, HashMap},
+    collections::HashMap,
     vec::IntoIter,
 };
 
@@ -15,7 +15,13 @@ use crate::{
 #[derive(Collect)]
 #[collect(no_drop)]
 pub struct Table<'v> {
-    data: HashMap<Value<'v>, Value<'v>>,
+    /** key to position within entries */
+    index: HashMap<Value<'v>, usize>,
+    /** key value pairs in insertion order, gives next() a stable order to walk. A nil value marks
+     * a removed key which we keep so a traversal can continue past it */
+    entries: Vec<(Value<'v>, Value<'v>)>,
+    /** count of entries with a non-nil value */
+    live: usize,
     meta: Option<Value<'v>>,
     // data: RefLock<HashMap<String, String>>,
     /** replicate standard lua behavior */
@@ -26,7 +32,9 @@ pub struct Table<'v> {
 impl<'v> Table<'v> {
     pub fn new(id: usize) -> Self {
         Table {
-            data: HashMap::new(),
+            index: HashMap::new(),
+            entries: vec![],
+            live: 0,
             meta: None,
             counter: 0,
             id,
@@ -42,22 +50,27 @@ impl<'v> Table<'v> {
 // where
         // T: ToLua<'v>,
     {
-        let mut data = HashMap::new();
+        let mut t = Table::new(id);
         for (k, v) in input.into_iter() {
             let kk: Value = k.into_value(vm, mc)?;
             let vv = v.into_value(vm, mc)?;
-            data.insert(kk, vv);
+            t.insert(kk, vv);
         }
-        Ok(Table {
-            data,
-            meta: None,
-            counter: 0,
-            id,
-        })
+        Ok(t)
     }
 
     pub fn insert<'f>(&mut self, key: Value<'v>, value: Value<'v>) {
-        self.data.insert(key, value);
+        self.set(key, value);
+    }
+
+    fn find(&self, key: &Value<'v>) -> Option<&Value<'v>> {
+        match self.index.get(key) {
+            Some(&i) => match &self.entries[i].1 {
+                Value::Nil => None,
+                v => Some(v),
+            },
+            None => None,
+        }
     }
 
     // same as get but accepts reference Into<&Value> which is better
@@ -66,7 +79,7 @@ impl<'v> Table<'v> {
         'v: 'f,
         T: Into<&'f Value<'v>>,
     {
-        self.data.get(key.into())
+        self.find(key.into())
     }
 
     pub fn get<'f, T>(&self, key: T) -> Option<&Value<'v>>
@@ -74,15 +87,15 @@ impl<'v> Table<'v> {
         'v: 'f,
         T: Into<Value<'v>>,
     {
-        self.data.get(&key.into())
+        self.find(&key.into())
     }
 
     pub fn getn(&self, i: usize) -> Option<&Value<'v>> {
-        self.data.get(&Value::Integer(i as i64))
+        self.find(&Value::Integer(i as i64))
     }
 
     pub fn get_value(&self, key: &Value<'v>) -> Value<'v> {
-        let r = self.data.get(key);
+        let r = self.find(key);
         match r {
             Some(v) => v.clone(),
             None => Value::Nil,
@@ -94,7 +107,7 @@ impl<'v> Table<'v> {
         'v: 'f,
         T: Into<Value<'v>>,
     {
-        match self.data.get(&key.into()) {
+        match self.find(&key.into()) {
             Some(v) => v.into(),
             _ => 0.,
         }
@@ -106,12 +119,72 @@ impl<'v> Table<'v> {
         K: Into<Value<'v>>,
         V: Into<Value<'v>>,
     {
-        self.data.insert(key.into(), val.into())
+        let key = key.into();
+        let val = val.into();
+        let is_nil = matches!(val, Value::Nil);
+        match self.index.get(&key) {
+            Some(&i) => {
+                let old = std::mem::replace(&mut self.entries[i].1, val);
+                match (&old, is_nil) {
+                    (Value::Nil, false) => self.live += 1,
+                    (Value::Nil, true) => {}
+                    (_, true) => self.live -= 1,
+                    _ => {}
+                }
+                match old {
+                    Value::Nil => None,
+                    v => Some(v),
+                }
+            }
+            None => {
+                if !is_nil {
+                    if self.entries.len() - self.live >= self.live.max(8) {
+                        self.compact();
+                    }
+                    self.index.insert(key.clone(), self.entries.len());
+                    self.entries.push((key, val));
+                    self.live += 1;
+                }
+                None
+            }
+        }
+    }
+
+    /** drop removed keys once they're as many as the live ones. Only done when adding a key, which
+     * lua doesn't allow during a traversal, so a next() on a removed key mid traversal still works */
+    fn compact(&mut self) {
+        self.entries.retain(|(_, v)| !matches!(v, Value::Nil));
+        self.index.clear();
+        for (i, (k, _)) in self.entries.iter().enumerate() {
+            self.index.insert(k.clone(), i);
+        }
+    }
+
+    /** slots in use including removed keys not yet compacted */
+    #[cfg(test)]
+    pub(crate) fn slots(&self) -> usize {
+        self.entries.len()
+    }
+
+    /** Lua's next, return the key value pair following the provided key or the first pair if nil.
+     * None once we've walked off the end */
+    pub fn next(&self, key: &Value<'v>) -> Result<Option<(Value<'v>, Value<'v>)>, SiltError> {
+        let start = match key {
+            Value::Nil => 0,
+            k => match self.index.get(k) {
+                Some(&i) => i + 1,
+                None => return Err(SiltError::TableInvalidNextKey),
+            },
+        };
+        Ok(self.entries[start..]
+            .iter()
+            .find(|(_, v)| !matches!(v, Value::Nil))
+            .map(|(k, v)| (k.clone(), v.clone())))
     }
 
     pub fn to_exval(&self) -> ExTable {
         let mut map = HashMap::new();
-        for (k, v) in self.data.iter() {
+        for (k, v) in self.iter() {
             map.insert(k.clone().into(), v.clone().into());
         }
         ExTable {
@@ -121,11 +194,21 @@ impl<'v> Table<'v> {
     }
 
     pub fn len(&self) -> usize {
-        self.data.len()
+        self.live
+    }
+
+    /** the array part's length, the last n of 1..=n with a value in each. Keys past a gap and non
+     * integer keys don't count */
+    pub fn border(&self) -> usize {
+        let mut n = 0;
+        while self.getn(n + 1).is_some() {
+            n += 1;
+        }
+        n
     }
 
     pub fn is_empty(&self) -> bool {
-        self.data.is_empty()
+        self.live == 0
     }
 
     // pub fn display(&self){
@@ -135,12 +218,10 @@ impl<'v> Table<'v> {
     pub fn push(&mut self, value: Value<'v>) {
         // DEV this just feels clunky to replicate lua's behavior
         self.counter += 1;
-        let mut key = Value::Integer(self.counter);
-        while self.data.contains_key(&key) {
+        while self.find(&Value::Integer(self.counter)).is_some() {
             self.counter += 1;
         }
-        key.force_to_int(self.counter);
-        self.data.insert(key, value);
+        self.insert(Value::Integer(self.counter), value);
     }
 
     pub fn concat_array<A, I>(&mut self, array: I)
@@ -151,7 +232,7 @@ impl<'v> Table<'v> {
         self.counter += 1;
         for v in array.into_iter() {
             let key = Value::Integer(self.counter);
-            self.data.insert(key, A::into(v));
+            self.insert(key, A::into(v));
             self.counter += 1;
         }
     }
@@ -184,8 +265,11 @@ impl<'v> Table<'v> {
         }
         Err(SiltError::MetaMethodMissing(method))
     }
-    pub fn iter(&self) -> Iter<'_, Value<'v>, Value<'v>> {
-        self.data.iter()
+    pub fn iter(&self) -> impl Iterator<Item = (&Value<'v>, &Value<'v>)> {
+        self.entries
+            .iter()
+            .filter(|(_, v)| !matches!(v, Value::Nil))
+            .map(|(k, v)| (k, v))
     }
 }
 
@@ -194,9 +278,8 @@ impl ToString for Table<'_> {
         format!(
             "table{}[{}]{{{}}}",
             self.id,
-            self.data.len(),
-            self.data
-                .iter()
+            self.live,
+            self.iter()
                 .map(|(k, v)| format!("{}: {}", k, v))
                 .collect::<Vec<String>>()
                 .join(", ")
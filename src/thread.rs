@@ -0,0 +1,71 @@
+use std::fmt::Display;
+
+use gc_arena::{lock::RefLock, Collect, Gc};
+
+use crate::{
+    function::{CallFrame, Closure, UpValue},
+    value::Value,
+};
+
+/** where a coroutine is at, as `coroutine.status` reports it */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ThreadStatus {
+    /** created or yielded, waiting on a resume */
+    Suspended,
+    Running,
+    /** resumed another coroutine and is waiting on it */
+    Normal,
+    /** returned or failed, can't be resumed again */
+    Dead,
+}
+
+impl Display for ThreadStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ThreadStatus::Suspended => write!(f, "suspended"),
+            ThreadStatus::Running => write!(f, "running"),
+            ThreadStatus::Normal => write!(f, "normal"),
+            ThreadStatus::Dead => write!(f, "dead"),
+        }
+    }
+}
+
+/** a coroutine, it's own value stack and call frames kept aside while it isn't running. The VM
+ * trades stacks with it on resume so pointers into either stay put */
+pub struct Thread<'gc> {
+    pub status: ThreadStatus,
+    /** the body, called on the first resume */
+    pub(crate) function: Gc<'gc, Closure<'gc>>,
+    pub(crate) stack: Vec<Value<'gc>>,
+    pub(crate) stack_count: usize,
+    pub(crate) open_upvalues: Vec<Gc<'gc, RefLock<UpValue<'gc>>>>,
+    pub(crate) to_be_closed: Vec<usize>,
+    /** calls running when we last yielded, the top one is waiting on the call to yield */
+    pub(crate) frames: Vec<CallFrame<'gc>>,
+    /** how many results the call to yield asked for, 0 leaves them open */
+    pub(crate) multi: u8,
+}
+
+impl<'gc> Thread<'gc> {
+    pub fn new(function: Gc<'gc, Closure<'gc>>) -> Self {
+        Self {
+            status: ThreadStatus::Suspended,
+            function,
+            stack: vec![Value::Nil; 256],
+            stack_count: 0,
+            open_upvalues: vec![],
+            to_be_closed: vec![],
+            frames: vec![],
+            multi: 0,
+        }
+    }
+}
+
+unsafe impl<'gc> Collect for Thread<'gc> {
+    fn trace(&self, cc: &gc_arena::Collection) {
+        self.function.trace(cc);
+        self.stack.trace(cc);
+        self.open_upvalues.trace(cc);
+        self.frames.trace(cc);
+    }
+}
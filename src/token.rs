@@ -29,6 +29,7 @@ pub enum Token {
 
     // symbols
     Dot,
+    VarArgs,
     Assign, //Equal
 
     // operator
@@ -72,6 +73,14 @@ pub enum Token {
     Number(f64),
     Integer(i64),
     StringLiteral(Box<str>),
+    /** backtick template without any `{expr}`, text only */
+    TemplateString(Box<str>),
+    /** template text up to the first `{` */
+    TemplateHead(Box<str>),
+    /** template text between a `}` and the next `{` */
+    TemplateMiddle(Box<str>),
+    /** template text from the last `}` to the closing backtick */
+    TemplateTail(Box<str>),
     True,
     False,
 
@@ -90,6 +99,10 @@ pub enum Token {
 pub enum Operator {
     Not,
     Tilde,
+    BitAnd,
+    BitOr,
+    ShiftLeft,
+    ShiftRight,
     And,
     Or,
     Add,
@@ -176,10 +189,15 @@ impl Display for Token {
             Token::SemiColon => write!(f, ";"),
             Token::Comma => write!(f, ","),
             Token::StringLiteral(ref s) => write!(f, "string({})", s),
+            Token::TemplateString(ref s) => write!(f, "template({})", s),
+            Token::TemplateHead(ref s) => write!(f, "template_head({})", s),
+            Token::TemplateMiddle(ref s) => write!(f, "template_middle({})", s),
+            Token::TemplateTail(ref s) => write!(f, "template_tail({})", s),
             Token::OpenBracket => write!(f, "["),
             Token::CloseBracket => write!(f, "]"),
             // Token::EOF => write!(f, "EOF"),
             Token::Dot => write!(f, "call"),
+            Token::VarArgs => write!(f, "..."),
             // Token::Bang => write!(f, "!"),
             Token::Type => write!(f, "type"),
             Token::ColonIdentifier(ref ident) => write!(f, ":{}", ident),
@@ -212,6 +230,10 @@ impl Display for Operator {
             Operator::Greater => write!(f, ">"),
             Operator::GreaterEqual => write!(f, ">="),
             Operator::Tilde => write!(f, "~"),
+            Operator::BitAnd => write!(f, "&"),
+            Operator::BitOr => write!(f, "|"),
+            Operator::ShiftLeft => write!(f, "<<"),
+            Operator::ShiftRight => write!(f, ">>"),
             Operator::Length => write!(f, "#"),
             Operator::ColonEquals => write!(f, ":="),
         }
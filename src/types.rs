@@ -1,3 +1,6 @@
+use std::fmt::{Display, Formatter};
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
 pub enum Type {
     Number,
     Integer,
@@ -11,5 +14,74 @@ pub enum Type {
     Thread,
     LightUserdata,
     Any,
+    /** nothing is known about the value, never reported and never checked */
+    #[default]
     None,
 }
+
+impl Type {
+    /** resolve the name used in an annotation like `local x: number` */
+    pub fn from_name(name: &str) -> Option<Type> {
+        match name {
+            "number" => Some(Type::Number),
+            "integer" => Some(Type::Integer),
+            "string" => Some(Type::String),
+            "boolean" => Some(Type::Boolean),
+            "nil" => Some(Type::Nil),
+            "function" => Some(Type::Function),
+            "table" => Some(Type::Table),
+            "userdata" => Some(Type::Userdata),
+            "thread" => Some(Type::Thread),
+            "any" => Some(Type::Any),
+            _ => None,
+        }
+    }
+
+    /** can a value of type `other` be stored where this type is required, an integer is still a number */
+    pub fn accepts(&self, other: Type) -> bool {
+        match (self, other) {
+            (Type::Any | Type::None, _) | (_, Type::Any | Type::None) => true,
+            (Type::Number, Type::Integer) => true,
+            (Type::Table, Type::List) => true,
+            (a, b) => *a == b,
+        }
+    }
+
+    /** both possibilities collapse into one type only if they agree, an integer or a number is still a number */
+    pub fn merge(self, other: Type) -> Type {
+        match (self, other) {
+            (a, b) if a == b => a,
+            (Type::Integer | Type::Number, Type::Integer | Type::Number) => Type::Number,
+            _ => Type::None,
+        }
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Number => write!(f, "number"),
+            Type::Integer => write!(f, "integer"),
+            Type::String => write!(f, "string"),
+            Type::Boolean => write!(f, "boolean"),
+            Type::Nil => write!(f, "nil"),
+            Type::Function => write!(f, "function"),
+            Type::Table => write!(f, "table"),
+            Type::List => write!(f, "list"),
+            Type::Userdata => write!(f, "userdata"),
+            Type::Thread => write!(f, "thread"),
+            Type::LightUserdata => write!(f, "lightuserdata"),
+            Type::Any => write!(f, "any"),
+            Type::None => write!(f, "unknown"),
+        }
+    }
+}
+
+/** parameter and return annotations of a function, unannotated slots are Type::None */
+#[derive(Clone, PartialEq, Debug)]
+pub struct FunctionType {
+    pub params: Vec<Type>,
+    pub returns: Type,
+    /** best guess from the function's return statements, only a hint when returns isn't annotated */
+    pub inferred_returns: Type,
+}
@@ -22,6 +22,7 @@ use crate::{
 
 /// Result type for Lua operations
 pub type InnerResult<'gc> = Result<Value<'gc>, SiltError>;
+pub type MultiResult<'gc> = Result<Vec<Value<'gc>>, SiltError>;
 pub type ToInnerResult<'gc, V: ToLua<'gc>> = V;
 
 /// Trait for Rust types that can be used as Lua UserData
@@ -182,7 +183,7 @@ pub struct UserDataTypedMap<'gc, T: UserData + 'gc> {
     methods: HashMap<String, UserDataMethodClosure<'gc>>,
     // methods2: HashMap<String, dyn MethodHandler<'gc,T,_,_>>,
     // method_cache: Vec<NativeFunctionRc<'gc>>,
-    meta_methods: Vec<UserDataMethodClosure<'gc>>,
+    meta_methods: HashMap<usize, UserDataMethodClosure<'gc>>,
     getters: HashMap<String, Box<UserDataGetterFn<'gc, T>>>,
     setters: HashMap<String, Box<UserDataSetterFn<'gc, T>>>,
     // type_id: std::any::TypeId,
@@ -194,7 +195,7 @@ impl<'gc, T: UserData + 'static> UserDataTypedMap<'gc, T> {
         Self {
             methods: HashMap::new(),
             // method_cache: Vec::new(),
-            meta_methods: Vec::new(),
+            meta_methods: HashMap::new(),
             getters: HashMap::new(),
             setters: HashMap::new(),
             // type_id: std::any::TypeId::of::<T>(),
@@ -275,7 +276,9 @@ impl<'gc, T: UserData + 'static> UserDataTypedMap<'gc, T> {
             //     // Value::Nil
             // };
             let native_fn = method_fn;
-            let raw = NativeFunctionRaw { func: native_fn };
+            let raw = NativeFunctionRaw {
+                func: Box::new(move |vm, mc, args| native_fn(vm, mc, args).map(|v| vec![v])),
+            };
             let r = Rc::new(raw);
             // self.method_cache.push(r.clone());
             self.getters.insert(
@@ -1098,6 +1101,7 @@ pub enum MetaMethod {
     ToString, // tostring etc
     Pairs,    // pairs builtin fn
     IPairs,   // ipairs builtin fn
+    Close,    // leaving the scope of a <close> local
 }
 
 impl std::fmt::Display for MetaMethod {
@@ -1131,6 +1135,7 @@ impl std::fmt::Display for MetaMethod {
             MetaMethod::ToString => write!(f, "tostring"),
             MetaMethod::Pairs => write!(f, "pairs"),
             MetaMethod::IPairs => write!(f, "ipairs"),
+            MetaMethod::Close => write!(f, "close"),
         }
     }
 }
@@ -1165,6 +1170,7 @@ impl<'a> Into<MetaMethod> for &'a str {
             "__tostring" => MetaMethod::ToString,
             "__pairs" => MetaMethod::Pairs,
             "__ipairs" => MetaMethod::IPairs,
+            "__close" => MetaMethod::Close,
             _ => panic!("Unknown metamethod: {}", self),
         }
     }
@@ -1214,6 +1220,7 @@ impl MetaMethod {
             MetaMethod::ToString => "__tostring",
             MetaMethod::Pairs => "__pairs",
             MetaMethod::IPairs => "__ipairs",
+            MetaMethod::Close => "__close",
         }
     }
     fn as_ind(&self) -> usize {
@@ -1246,6 +1253,7 @@ impl MetaMethod {
             MetaMethod::ToString => 25,
             MetaMethod::Pairs => 26,
             MetaMethod::IPairs => 27,
+            MetaMethod::Close => 28,
         }
     }
 }
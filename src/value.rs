@@ -17,6 +17,7 @@ use crate::{
     lua::VM,
     prelude::UserData,
     table::Table,
+    thread::Thread,
     userdata::{MetaMethod, UserDataWrapper},
 };
 
@@ -78,6 +79,8 @@ pub enum Value<'gc> {
     // NativeFunction(Gc<'gc, WrappedFn<'gc>>),
     NativeFunction(Gc<'gc, WrappedFn<'gc>>),
     UserData(Gc<'gc, RefLock<UserDataWrapper>>),
+    /** a coroutine */
+    Thread(Gc<'gc, RefLock<Thread<'gc>>>),
     #[cfg(feature = "vectors")]
     Vec3(Vec3),
     #[cfg(feature = "vectors")]
@@ -184,6 +187,7 @@ impl Into<ExVal> for Value<'_> {
             Value::Closure(c) => ExVal::Meta(format!("=>({})", c.function).into()),
             Value::NativeFunction(_) => ExVal::Meta("native_function".to_string()),
             Value::UserData(u) => ExVal::UserData(format!("{} userdata", u.borrow().type_name())),
+            Value::Thread(_) => ExVal::Meta("thread".to_string()),
             #[cfg(feature = "vectors")]
             Value::Vec3(v) => ExVal::Vec3(v),
             #[cfg(feature = "vectors")]
@@ -239,6 +243,7 @@ impl std::fmt::Display for Value<'_> {
             Value::Function(ff) => write!(f, "{}", ff),
             Value::Table(t) => write!(f, "table[;{}]", t.borrow().len()),
             Value::UserData(_) => write!(f, "userdata"), // TODO
+            Value::Thread(_) => write!(f, "thread"),
             #[cfg(feature = "vectors")]
             Value::Vec3(v) => write!(f, "{}", v),
             #[cfg(feature = "vectors")]
@@ -269,6 +274,7 @@ impl<'v> Value<'v> {
             Value::Closure(_) => ValueTypes::Closure,
             Value::Table(_) => ValueTypes::Table,
             Value::UserData(_) => ValueTypes::UserData,
+            Value::Thread(_) => ValueTypes::Thread,
             #[cfg(feature = "vectors")]
             Value::Vec3(_) => ValueTypes::Vec3,
             #[cfg(feature = "vectors")]
@@ -304,6 +310,7 @@ impl<'v> Value<'v> {
             },
             Value::Closure(_) => "closure".to_string(),
             Value::UserData(_) => "userdata".to_string(),
+            Value::Thread(_) => "thread".to_string(),
             Value::Infinity(b) => format!("{}inf", if *b { "" } else { "-" }),
             Value::Bool(b) => b.to_string(),
             Value::Nil => "nil".to_string(),
@@ -371,6 +378,7 @@ impl<'v> Value<'v> {
             // }),
             Value::Table(t) => Value::Table(Gc::clone(t)),
             Value::UserData(u) => Value::UserData(Gc::clone(u)),
+            Value::Thread(t) => Value::Thread(Gc::clone(t)),
             #[cfg(feature = "vectors")]
             Value::Vec3(v) => Value::Vec3(*v),
             #[cfg(feature = "vectors")]
@@ -398,6 +406,7 @@ impl PartialEq for Value<'_> {
             (Value::Function(i), Value::Function(j)) => Gc::ptr_eq(*i, *j), // Rc::ptr_eq(i, j),
 
             (Value::Table(i), Value::Table(j)) => Gc::ptr_eq(*i, *j),
+            (Value::Thread(i), Value::Thread(j)) => Gc::ptr_eq(*i, *j),
             _ => false,
         }
     }
@@ -924,6 +933,12 @@ where
     }
 }
 
+impl<'a> ToLuaMulti<'a> for Vec<Value<'a>> {
+    fn to_lua_multi(self, _: &VM<'a>, _: &Mutation<'a>) -> ValuesResult<'a> {
+        Ok(self)
+    }
+}
+
 trait NotSingle {}
 // impl<T> NotSingle for Vec<T>{}
 // impl<T, const N: usize> NotSingle for [ T; N ]{}
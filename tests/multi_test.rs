@@ -18,7 +18,16 @@ fn simple(source: &str) -> ExVal {
 
 #[test]
 fn mult() {
-    assert_eq!(1, 2)
+    valeq!(
+        r#"
+        function pair()
+            return 1, 2
+        end
+        local t = {pair(), pair()}
+        return #t
+        "#,
+        ExVal::Integer(3)
+    );
 }
 
 #[test]
@@ -33,7 +42,7 @@ fn multiple_returns() {
         "#;
 
     if let ExVal::Table(t) = simple(source_in) {
-        assert_eq!(t.get("a"), Some(&ExVal::Integer(1)));
+        assert_eq!(t.get("a"), Some(&ExVal::Integer(2)));
         assert_eq!(t.get("b"), Some(&ExVal::String("hello".to_string())));
         assert_eq!(t.get("c"), Some(&ExVal::Bool(true)));
     } else {
@@ -73,8 +82,9 @@ fn multiple_returns_extra() {
 
     if let ExVal::Table(t) = simple(source_in) {
         assert_eq!(t.get("a"), Some(&ExVal::Integer(42)));
-        assert_eq!(t.get("b"), Some(&ExVal::Nil));
-        assert_eq!(t.get("c"), Some(&ExVal::Nil));
+        // a nil field is never stored in the table
+        assert_eq!(t.get("b"), None);
+        assert_eq!(t.get("c"), None);
     } else {
         panic!("Expected table result");
     }